@@ -0,0 +1,422 @@
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::value::Value;
+
+// State of the session's random number generator, 0 until seeded or first used
+static RANDOM_STATE: Mutex<u64> = Mutex::new(0);
+
+/// A function provided by the calculator itself rather than defined by the user
+pub struct Builtin {
+	pub name: &'static str,
+	pub arity: usize,
+	pub function: fn(&[f64]) -> f64,
+}
+
+/// Registry of all built-in functions, consulted for calls, completion and `:builtins`
+pub const BUILTINS: &[Builtin] = &[
+	Builtin {
+		name: "abs",
+		arity: 1,
+		function: |args| args[0].abs(),
+	},
+	Builtin {
+		name: "ceil",
+		arity: 1,
+		function: |args| args[0].ceil(),
+	},
+	Builtin {
+		name: "clamp01",
+		arity: 1,
+		function: |args| args[0].clamp(0.0, 1.0),
+	},
+	Builtin {
+		name: "cos",
+		arity: 1,
+		function: |args| args[0].cos(),
+	},
+	Builtin {
+		name: "exp",
+		arity: 1,
+		function: |args| args[0].exp(),
+	},
+	Builtin {
+		name: "floor",
+		arity: 1,
+		function: |args| args[0].floor(),
+	},
+	Builtin {
+		name: "ln",
+		arity: 1,
+		function: |args| args[0].ln(),
+	},
+	Builtin {
+		name: "log10",
+		arity: 1,
+		function: |args| args[0].log10(),
+	},
+	Builtin {
+		name: "max",
+		arity: 2,
+		function: |args| args[0].max(args[1]),
+	},
+	Builtin {
+		name: "min",
+		arity: 2,
+		function: |args| args[0].min(args[1]),
+	},
+	Builtin {
+		name: "pow",
+		arity: 2,
+		function: |args| args[0].powf(args[1]),
+	},
+	Builtin {
+		name: "rand_range",
+		arity: 2,
+		function: |args| args[0] + (args[1] - args[0]) * next_random(),
+	},
+	Builtin {
+		name: "random",
+		arity: 0,
+		function: |_| next_random(),
+	},
+	Builtin {
+		name: "round",
+		arity: 1,
+		function: |args| args[0].round(),
+	},
+	Builtin {
+		name: "sin",
+		arity: 1,
+		function: |args| args[0].sin(),
+	},
+	Builtin {
+		name: "sqrt",
+		arity: 1,
+		function: |args| args[0].sqrt(),
+	},
+	Builtin {
+		name: "tan",
+		arity: 1,
+		function: |args| args[0].tan(),
+	},
+];
+
+/// A built-in function taking values of any kind, such as strings, rather than only numbers
+pub struct ValueBuiltin {
+	pub name: &'static str,
+	pub arity: usize,
+	/// Returns None for functions called only for their effect, like `print`
+	pub function: fn(&[Value]) -> Result<Option<Value>, String>,
+}
+
+/// Registry of built-in functions that accept strings
+pub const VALUE_BUILTINS: &[ValueBuiltin] = &[
+	ValueBuiltin {
+		name: "bench",
+		arity: 2,
+		function: |args| {
+			// The expression is given as a string so it can be evaluated again and again
+			let Value::String(expression) = &args[0] else {
+				return Err(format!(
+					"bench expects an expression string, got {}",
+					args[0].kind_name()
+				));
+			};
+			let times = args[1].as_number()?;
+			if times < 1.0 || times.fract() != 0.0 {
+				return Err("bench expects a positive whole number of evaluations".to_string());
+			}
+			let nanoseconds = crate::time_evaluations(expression, times as usize)?;
+			Ok(Some(Value::Number(nanoseconds)))
+		},
+	},
+	ValueBuiltin {
+		name: "len",
+		arity: 1,
+		function: |args| match &args[0] {
+			Value::String(s) => Ok(Some(Value::Integer(s.chars().count() as i64))),
+			Value::List(items) => Ok(Some(Value::Integer(items.len() as i64))),
+			other => Err(format!("len expects a string, got {}", other.kind_name())),
+		},
+	},
+	ValueBuiltin {
+		name: "print",
+		arity: 1,
+		function: |args| {
+			// Strings print as plain text, without their quotes
+			match &args[0] {
+				Value::String(s) => println!("{}", s),
+				other => println!("{}", other),
+			}
+			Ok(None)
+		},
+	},
+	ValueBuiltin {
+		name: "to_number",
+		arity: 1,
+		function: |args| match &args[0] {
+			Value::String(s) => parse_number(s).map(Some),
+			Value::List(_) => Err("to_number expects a string, got a list".to_string()),
+			number => Ok(Some(number.clone())),
+		},
+	},
+	ValueBuiltin {
+		name: "to_string",
+		arity: 1,
+		function: |args| match &args[0] {
+			Value::String(s) => Ok(Some(Value::String(s.clone()))),
+			// Real numbers are written in full, not rounded to the display precision, so
+			// to_number gives back the same value
+			Value::Number(n) => Ok(Some(Value::String(format!("{}", n)))),
+			other => Ok(Some(Value::String(other.to_string()))),
+		},
+	},
+];
+
+/// Parse the text of a number, ignoring surrounding whitespace. Whole numbers become integers.
+fn parse_number(text: &str) -> Result<Value, String> {
+	let text = text.trim();
+	if let Ok(integer) = text.parse::<i64>() {
+		return Ok(Value::Integer(integer));
+	}
+	match text.parse::<f64>() {
+		Ok(number) if number.is_finite() => Ok(Value::Number(number)),
+		_ => Err(format!("Cannot convert \"{}\" to a number", text)),
+	}
+}
+
+/// A function passed to a built-in by name, called with numeric arguments
+pub type FunctionArgument<'a> = &'a mut dyn FnMut(&[f64]) -> Result<f64, String>;
+
+/// A built-in function whose first argument is the name of a function it calls, like `deriv`
+pub struct HigherOrderBuiltin {
+	pub name: &'static str,
+	/// Number of arguments, counting the function
+	pub arity: usize,
+	/// Called with the named function and the remaining arguments
+	pub function: fn(FunctionArgument, &[Value]) -> Result<Value, String>,
+}
+
+/// Registry of built-in functions that take a function as their first argument
+pub const HIGHER_ORDER_BUILTINS: &[HigherOrderBuiltin] = &[
+	HigherOrderBuiltin {
+		name: "deriv",
+		arity: 2,
+		function: |f, args| Ok(Value::Number(derivative(f, args[0].as_number()?)?)),
+	},
+	HigherOrderBuiltin {
+		name: "filter",
+		arity: 2,
+		function: |f, args| {
+			let mut kept = Vec::new();
+			for item in list_argument("filter", &args[0])? {
+				if crate::is_truthy(f(&[item.as_number()?])?) {
+					kept.push(item.clone());
+				}
+			}
+			Ok(Value::List(kept))
+		},
+	},
+	HigherOrderBuiltin {
+		name: "integrate",
+		arity: 3,
+		function: |f, args| {
+			let area = integral(f, args[0].as_number()?, args[1].as_number()?)?;
+			Ok(Value::Number(area))
+		},
+	},
+	HigherOrderBuiltin {
+		name: "map",
+		arity: 2,
+		function: |f, args| {
+			let items = list_argument("map", &args[0])?
+				.iter()
+				.map(|item| Ok(Value::Number(f(&[item.as_number()?])?)))
+				.collect::<Result<_, String>>()?;
+			Ok(Value::List(items))
+		},
+	},
+	HigherOrderBuiltin {
+		name: "reduce",
+		arity: 3,
+		function: |f, args| {
+			// The function combines the running total with each element in turn
+			let mut total = args[0].as_number()?;
+			for item in list_argument("reduce", &args[1])? {
+				total = f(&[total, item.as_number()?])?;
+			}
+			Ok(Value::Number(total))
+		},
+	},
+	HigherOrderBuiltin {
+		name: "solve",
+		arity: 3,
+		function: |f, args| {
+			let root = bisect(f, args[0].as_number()?, args[1].as_number()?)?;
+			Ok(Value::Number(root))
+		},
+	},
+];
+
+/// The elements of an argument that must be a list
+fn list_argument<'a>(name: &str, value: &'a Value) -> Result<&'a [Value], String> {
+	match value {
+		Value::List(items) => Ok(items),
+		other => Err(format!(
+			"{} expects a list, got {}",
+			name,
+			other.kind_name()
+		)),
+	}
+}
+
+/// Approximate the derivative of `f` at `x` with a central difference
+fn derivative(f: FunctionArgument, x: f64) -> Result<f64, String> {
+	// The step scales with x so it stays significant for large x
+	let h = 1e-5 * x.abs().max(1.0);
+	Ok((f(&[x + h])? - f(&[x - h])?) / (2.0 * h))
+}
+
+/// Number of subdivisions used by `integrate`, which must be even for Simpson's rule
+const INTEGRATION_STEPS: usize = 200;
+
+/// Approximate the definite integral of `f` over `[a, b]` with Simpson's rule
+fn integral(f: FunctionArgument, a: f64, b: f64) -> Result<f64, String> {
+	if a.is_nan() || b.is_nan() || a > b {
+		return Err(format!("integrate expects a <= b, got {} and {}", a, b));
+	}
+	let h = (b - a) / INTEGRATION_STEPS as f64;
+	let mut sum = f(&[a])? + f(&[b])?;
+	for i in 1..INTEGRATION_STEPS {
+		let weight = if i % 2 == 1 { 4.0 } else { 2.0 };
+		sum += weight * f(&[a + i as f64 * h])?;
+	}
+	Ok(sum * h / 3.0)
+}
+
+/// Find a root of `f` in `[lo, hi]` by bisection; `f` must change sign over the interval
+fn bisect(f: FunctionArgument, lo: f64, hi: f64) -> Result<f64, String> {
+	let (mut lo, mut hi) = (lo.min(hi), lo.max(hi));
+	let mut f_lo = f(&[lo])?;
+	let f_hi = f(&[hi])?;
+	if f_lo == 0.0 {
+		return Ok(lo);
+	}
+	if f_hi == 0.0 {
+		return Ok(hi);
+	}
+	if f_lo.is_nan() || f_hi.is_nan() || (f_lo > 0.0) == (f_hi > 0.0) {
+		return Err(format!(
+			"solve needs f(lo) and f(hi) to have opposite signs, got {} and {}",
+			f_lo, f_hi
+		));
+	}
+
+	// Halving stops once the midpoint can no longer move between the bounds
+	loop {
+		let mid = lo + (hi - lo) / 2.0;
+		if mid <= lo || mid >= hi {
+			return Ok(mid);
+		}
+		let f_mid = f(&[mid])?;
+		if f_mid == 0.0 {
+			return Ok(mid);
+		}
+		if (f_mid > 0.0) == (f_lo > 0.0) {
+			lo = mid;
+			f_lo = f_mid;
+		} else {
+			hi = mid;
+		}
+	}
+}
+
+/// Seed the random number generator so the following random values are reproducible
+pub fn seed_random(seed: u64) {
+	let mut state = RANDOM_STATE
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	// Xorshift gets stuck at zero, so scramble the seed into a nonzero state
+	*state = (seed ^ 0x9E37_79B9_7F4A_7C15).max(1);
+}
+
+/// Next uniformly distributed value in [0, 1) from the session's xorshift64* generator
+fn next_random() -> f64 {
+	let mut state = RANDOM_STATE
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	if *state == 0 {
+		// Unseeded sessions start from the clock
+		let nanos = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map_or(0, |elapsed| elapsed.as_nanos() as u64);
+		*state = (nanos ^ 0x9E37_79B9_7F4A_7C15).max(1);
+	}
+	let mut x = *state;
+	x ^= x >> 12;
+	x ^= x << 25;
+	x ^= x >> 27;
+	*state = x;
+	// The top 53 bits fill an f64 mantissa exactly
+	(x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Look up a built-in function by name
+pub fn find_builtin(name: &str) -> Option<&'static Builtin> {
+	BUILTINS.iter().find(|builtin| builtin.name == name)
+}
+
+/// Look up a built-in function that accepts strings by name
+pub fn find_value_builtin(name: &str) -> Option<&'static ValueBuiltin> {
+	VALUE_BUILTINS.iter().find(|builtin| builtin.name == name)
+}
+
+/// Look up a built-in function that takes a function argument by name
+pub fn find_higher_order_builtin(name: &str) -> Option<&'static HigherOrderBuiltin> {
+	HIGHER_ORDER_BUILTINS
+		.iter()
+		.find(|builtin| builtin.name == name)
+}
+
+/// Whether a name refers to any built-in function
+pub fn is_builtin(name: &str) -> bool {
+	find_builtin(name).is_some()
+		|| find_value_builtin(name).is_some()
+		|| find_higher_order_builtin(name).is_some()
+}
+
+/// Names of all built-in functions
+pub fn builtin_names() -> impl Iterator<Item = &'static str> {
+	let names = BUILTINS.iter().map(|builtin| builtin.name);
+	names
+		.chain(VALUE_BUILTINS.iter().map(|builtin| builtin.name))
+		.chain(HIGHER_ORDER_BUILTINS.iter().map(|builtin| builtin.name))
+}
+
+/// Format the built-in functions as a listing sorted by name, one per line
+pub fn format_builtins() -> String {
+	let mut builtins: Vec<(&str, usize)> = BUILTINS
+		.iter()
+		.map(|builtin| (builtin.name, builtin.arity))
+		.chain(
+			VALUE_BUILTINS
+				.iter()
+				.map(|builtin| (builtin.name, builtin.arity)),
+		)
+		.chain(
+			HIGHER_ORDER_BUILTINS
+				.iter()
+				.map(|builtin| (builtin.name, builtin.arity)),
+		)
+		.collect();
+	builtins.sort();
+	builtins
+		.iter()
+		.map(|(name, arity)| {
+			let noun = if *arity == 1 { "argument" } else { "arguments" };
+			format!("  {:<8} {} {}", name, arity, noun)
+		})
+		.collect::<Vec<_>>()
+		.join("\n")
+}
@@ -1,35 +1,35 @@
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct LangInteger {
 	pub value: i64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct LangRealNumber {
 	pub value: f64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum LangNumber {
 	Integer(LangInteger),
 	RealNumber(LangRealNumber),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct LangSymbol {
 	pub value: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct LangString {
 	pub value: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct LangOperator {
 	pub value: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token {
 	Number(LangNumber),
 	Symbol(LangSymbol),
@@ -37,6 +37,50 @@ pub enum Token {
 	Operator(LangOperator),
 }
 
+/// Reserved words that stand for binary operators when they follow an operand
+const WORD_OPERATORS: &[(&str, &str)] =
+	&[("mod", "%"), ("div", "div"), ("and", "&&"), ("or", "||")];
+
+/// Turn the word operators into operator tokens: `mod`, `div`, `and` and `or` after an
+/// operand, and `not` in front of one. Anywhere else they stay symbols, so `mod = 3`
+/// still names a variable.
+fn convert_word_operators(tokens: &mut [Token]) {
+	let ends_operand = |token: Option<&Token>| match token {
+		Some(Token::Number(_) | Token::String(_)) => true,
+		// Keywords start a statement or clause rather than ending an operand
+		Some(Token::Symbol(symbol)) => {
+			!matches!(symbol.value.as_str(), "if" | "else" | "fn" | "when")
+		}
+		Some(Token::Operator(op)) => op.value == ")" || op.value == "]",
+		None => false,
+	};
+	let starts_operand = |token: Option<&Token>| match token {
+		Some(Token::Number(_) | Token::String(_) | Token::Symbol(_)) => true,
+		Some(Token::Operator(op)) => matches!(op.value.as_str(), "(" | "[" | "-"),
+		None => false,
+	};
+
+	for i in 0..tokens.len() {
+		let Token::Symbol(symbol) = &tokens[i] else {
+			continue;
+		};
+		let after_operand = i > 0 && ends_operand(tokens.get(i - 1));
+		let operator = match symbol.value.as_str() {
+			"not" if !after_operand && starts_operand(tokens.get(i + 1)) => Some("not"),
+			word if after_operand => WORD_OPERATORS
+				.iter()
+				.find(|(name, _)| *name == word)
+				.map(|(_, operator)| *operator),
+			_ => None,
+		};
+		if let Some(operator) = operator {
+			tokens[i] = Token::Operator(LangOperator {
+				value: operator.to_string(),
+			});
+		}
+	}
+}
+
 pub fn lex(line: &str) -> Vec<Token> {
 	let mut tokens: Vec<Token> = Vec::new();
 
@@ -71,16 +115,23 @@ pub fn lex(line: &str) -> Vec<Token> {
 		if ch == '\r' {
 			continue;
 		} else if ch == '\n' {
-			// Finish the current token
-			token_chars_collection.push(std::mem::replace(&mut current_token_chars, None).unwrap());
+			// Finish the current token, if any (blank lines and brackets leave none pending)
+			if let Some(token) = current_token_chars.take() {
+				token_chars_collection.push(token);
+			}
 			token_chars_collection.push(TokenChars::Operator(vec!['\n']));
 			continue;
 		}
 
 		let bracket_chars = ['(', ')', '{', '}', '[', ']'];
 
-		// Handle bracket characters as separate single-character operators
-		if bracket_chars.contains(&ch) {
+		// Handle bracket characters as separate single-character operators,
+		// except inside strings and comments
+		let in_text = matches!(
+			current_token_chars,
+			Some(TokenChars::String(_)) | Some(TokenChars::Comment(_))
+		);
+		if bracket_chars.contains(&ch) && !in_text {
 			// End current token if any
 			if let Some(token) = current_token_chars.take() {
 				token_chars_collection.push(token);
@@ -199,6 +250,64 @@ pub fn lex(line: &str) -> Vec<Token> {
 		token_chars_collection.push(token_chars);
 	}
 
+	/// Fold a `-` directly in front of a number into the number when it can't be subtraction:
+	/// at the start of the input or after another operator, an opening bracket or a comma.
+	/// A `^` after the number keeps the minus separate, since `-2 ^ 2` means `-(2 ^ 2)`.
+	fn fold_negative_literals(token_chars_collection: &mut Vec<TokenChars>) {
+		let is_significant = |token: &TokenChars| {
+			!matches!(token, TokenChars::Whitespace(_) | TokenChars::Comment(_))
+		};
+
+		let mut i = 0;
+		while i + 1 < token_chars_collection.len() {
+			let ends_with_minus = matches!(
+				&token_chars_collection[i],
+				TokenChars::Operator(chars) if chars.last() == Some(&'-')
+			);
+			if !ends_with_minus || !matches!(token_chars_collection[i + 1], TokenChars::Number(_)) {
+				i += 1;
+				continue;
+			}
+
+			// A minus grouped with other operator characters (`*-`, `=-`) always follows an
+			// operator; a lone minus needs a look at what came before it
+			let lone_minus = matches!(
+				&token_chars_collection[i],
+				TokenChars::Operator(chars) if chars.len() == 1
+			);
+			let previous = token_chars_collection[..i]
+				.iter()
+				.rev()
+				.find(|token| is_significant(token));
+			let after_operator = match previous {
+				None => true,
+				Some(TokenChars::Operator(chars)) => !matches!(chars.as_slice(), [')' | ']' | '}']),
+				Some(_) => false,
+			};
+			let next = token_chars_collection[i + 2..]
+				.iter()
+				.find(|token| is_significant(token));
+			let before_exponent =
+				matches!(next, Some(TokenChars::Operator(chars)) if chars.first() == Some(&'^'));
+
+			if (!lone_minus || after_operator) && !before_exponent {
+				if let TokenChars::Number(chars) = &mut token_chars_collection[i + 1] {
+					chars.insert(0, '-');
+				}
+				if lone_minus {
+					token_chars_collection.remove(i);
+					continue;
+				}
+				if let TokenChars::Operator(chars) = &mut token_chars_collection[i] {
+					chars.pop();
+				}
+			}
+			i += 1;
+		}
+	}
+
+	fold_negative_literals(&mut token_chars_collection);
+
 	// Remove Whitespace tokens from token_chars_collection
 	token_chars_collection.retain(|token| match token {
 		TokenChars::Whitespace(_) => false,
@@ -245,5 +354,6 @@ pub fn lex(line: &str) -> Vec<Token> {
 		}
 	}
 
+	convert_word_operators(&mut tokens);
 	tokens
 }
@@ -1,9 +1,15 @@
 use rustyline;
 
+mod builtins;
 mod lex;
 mod parse;
+mod value;
+use builtins::{
+	find_builtin, find_higher_order_builtin, find_value_builtin, format_builtins, is_builtin,
+};
 use lex::{Token, lex};
-use parse::{LangBlock, LangLine, parse_block};
+use parse::{LangBlock, LangLine, parse_program};
+use value::Value;
 
 use inkwell::OptimizationLevel;
 use inkwell::builder::Builder;
@@ -14,23 +20,363 @@ use inkwell::targets::{
 	CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetMachine,
 };
 
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fs;
+use std::io::{IsTerminal, Read};
 use std::path::Path;
-use std::sync::{LazyLock, Mutex};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::{LazyLock, Mutex, MutexGuard};
 
 use inkwell::types::FloatType;
-use inkwell::values::{FloatValue, FunctionValue};
+use inkwell::values::{BasicValue, FloatValue, FunctionValue};
 
 // Global variable storage for the REPL session
 static VARIABLES: LazyLock<Mutex<HashMap<String, f64>>> =
 	LazyLock::new(|| Mutex::new(HashMap::new()));
 
+// Variables holding lists. Compiled code only reads numbers, so these are kept apart from
+// VARIABLES, and each name is in one or the other.
+static LIST_VARIABLES: LazyLock<Mutex<HashMap<String, Vec<Value>>>> =
+	LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// Names of global variables read by JIT-compiled code, indexed by slot number
+static GLOBAL_SLOTS: LazyLock<Mutex<Vec<String>>> = LazyLock::new(|| Mutex::new(Vec::new()));
+
 // Global function storage for the REPL session
 static FUNCTIONS: LazyLock<Mutex<HashMap<String, parse::LangFunction>>> =
 	LazyLock::new(|| Mutex::new(HashMap::new()));
 
+// Guarded clauses of each function, tried in definition order before its unguarded definition
+static FUNCTION_CLAUSES: LazyLock<Mutex<HashMap<String, Vec<parse::LangNamedFunction>>>> =
+	LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// Names each function's definition and guarded clauses use besides their own parameters and
+// locals, sorted. Worked out when the function is defined, for the call graph and `:functions`.
+static FUNCTION_DEPENDENCIES: LazyLock<Mutex<HashMap<String, Vec<String>>>> =
+	LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// Set whenever an evaluation error is reported, so the REPL prompt can show it
+static ERROR_REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// User-configurable settings for the REPL session
+#[derive(Clone)]
+struct Settings {
+	/// Maximum number of decimal places shown when printing results
+	precision: usize,
+	/// Compiler used to link executables created by `:compile`
+	linker: String,
+	/// Extra flags passed to the linker
+	linker_flags: Vec<String>,
+	/// Whether printed results are recorded for `:stats`
+	record_stats: bool,
+	/// Whether integer arithmetic produces exact fractions instead of reals
+	exact: bool,
+	/// Value read from variables that have not been assigned
+	undefined_default: f64,
+	/// Whether reading an unassigned variable is an error instead of giving the default
+	strict_variables: bool,
+	/// Whether integer arithmetic that overflows is an error instead of becoming real
+	overflow_errors: bool,
+	/// How displayed results are rounded to the precision
+	rounding: RoundingMode,
+	/// Whether compiled arithmetic carries LLVM fast-math flags
+	fast_math: bool,
+	/// Whether calls check their arguments against annotated parameter types
+	strict_types: bool,
+	/// Whether operations are counted and reported after each top-level statement
+	profile: bool,
+	/// Most tokens allowed on one line of input, if limited
+	max_tokens: Option<usize>,
+	/// Whether named functions are defined before the other statements of an input run
+	hoist_functions: bool,
+	/// Whether inputs relying on operator precedence get a warning suggesting parentheses
+	precedence_lint: bool,
+	/// Whether assignments print the values they stored
+	echo_assignments: bool,
+	/// Whether defining a function warns about names in its body that aren't defined
+	check_names: bool,
+	/// What `/` gives for two integers that don't divide exactly
+	integer_division: IntegerDivision,
+}
+
+/// Direction in which displayed results are rounded at the configured precision
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RoundingMode {
+	Nearest,
+	Up,
+	Down,
+	TowardZero,
+}
+
+/// Result of dividing two integers with `/`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum IntegerDivision {
+	/// A real number, so `5 / 2` is 2.5
+	Float,
+	/// The quotient rounded down to an integer, like `div`, so `5 / 2` is 2. Variables and
+	/// function parameters hold reals, so `x / 2` still divides exactly.
+	Floor,
+}
+
+impl Default for Settings {
+	fn default() -> Self {
+		// The linker can be preconfigured through the environment
+		let linker = std::env::var("FCALC_LINKER").unwrap_or_else(|_| default_linker().to_string());
+		let linker_flags = std::env::var("FCALC_LINK_FLAGS")
+			.map(|flags| flags.split_whitespace().map(String::from).collect())
+			.unwrap_or_default();
+		// So can echoing assignments, which piped programs can't turn on with `:echo`
+		let echo_assignments = std::env::var("FCALC_ECHO").is_ok_and(|echo| echo == "on");
+
+		Settings {
+			precision: 15,
+			linker,
+			linker_flags,
+			record_stats: false,
+			exact: false,
+			undefined_default: 0.0,
+			strict_variables: false,
+			overflow_errors: false,
+			rounding: RoundingMode::Nearest,
+			fast_math: false,
+			strict_types: false,
+			profile: false,
+			max_tokens: None,
+			hoist_functions: false,
+			precedence_lint: false,
+			echo_assignments,
+			check_names: false,
+			integer_division: IntegerDivision::Float,
+		}
+	}
+}
+
+/// Compiler used to link executables on this platform
+fn default_linker() -> &'static str {
+	if cfg!(target_os = "linux") {
+		"gcc"
+	} else {
+		"clang"
+	}
+}
+
+/// Build the shell command linking an object file into an executable
+fn build_link_command(
+	linker: &str,
+	flags: &[String],
+	output_path: &str,
+	object_path: &str,
+) -> String {
+	let output_path = if cfg!(target_os = "windows") {
+		format!("{}.exe", output_path)
+	} else {
+		output_path.to_string()
+	};
+
+	let mut command = format!("{} -o {} {} -lm", linker, output_path, object_path);
+	for flag in flags {
+		command.push(' ');
+		command.push_str(flag);
+	}
+	command
+}
+
+// Global settings for the REPL session
+static SETTINGS: LazyLock<Mutex<Settings>> = LazyLock::new(|| Mutex::new(Settings::default()));
+
+/// Lock the session settings, recovering them if the lock was poisoned
+fn settings() -> MutexGuard<'static, Settings> {
+	SETTINGS
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Value of a variable that has not been assigned: the `:default` value, or an error
+/// in strict mode
+fn undefined_variable(name: &str) -> Result<f64, String> {
+	let settings = settings();
+	if settings.strict_variables {
+		Err(format!("Undefined variable '{}'", name))
+	} else {
+		Ok(settings.undefined_default)
+	}
+}
+
+/// Running aggregates over the results printed in a session, reported by `:stats`
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ResultStats {
+	count: usize,
+	min: f64,
+	max: f64,
+	sum: f64,
+}
+
+impl ResultStats {
+	const EMPTY: ResultStats = ResultStats {
+		count: 0,
+		min: f64::INFINITY,
+		max: f64::NEG_INFINITY,
+		sum: 0.0,
+	};
+
+	/// Add one result to the aggregates
+	fn record(&mut self, value: f64) {
+		self.count += 1;
+		self.min = self.min.min(value);
+		self.max = self.max.max(value);
+		self.sum += value;
+	}
+
+	/// Mean of the recorded results, if there are any
+	fn mean(&self) -> Option<f64> {
+		(self.count > 0).then(|| self.sum / self.count as f64)
+	}
+}
+
+// Results recorded while `:stats` recording is on
+static RESULT_STATS: Mutex<ResultStats> = Mutex::new(ResultStats::EMPTY);
+
+/// Lock the recorded result statistics, recovering them if the lock was poisoned
+fn result_stats() -> MutexGuard<'static, ResultStats> {
+	RESULT_STATS
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Format the recorded result statistics for `:stats`
+fn format_stats(stats: &ResultStats) -> String {
+	match stats.mean() {
+		Some(mean) => format!(
+			"count: {}  min: {}  max: {}  mean: {}",
+			stats.count,
+			format_value(stats.min),
+			format_value(stats.max),
+			format_value(mean)
+		),
+		None => "count: 0".to_string(),
+	}
+}
+
+/// Operations counted for one top-level statement while `:profile` is on
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ProfileCounts {
+	/// Arithmetic operators applied by the interpreter
+	arithmetic: usize,
+	/// Calls to user-defined and built-in functions
+	calls: usize,
+	/// Functions compiled with LLVM
+	compilations: usize,
+}
+
+impl ProfileCounts {
+	const ZERO: ProfileCounts = ProfileCounts {
+		arithmetic: 0,
+		calls: 0,
+		compilations: 0,
+	};
+}
+
+// Operations counted for the statement being profiled
+static PROFILE_COUNTS: Mutex<ProfileCounts> = Mutex::new(ProfileCounts::ZERO);
+
+/// Lock the profile counts, recovering them if the lock was poisoned
+fn profile_counts() -> MutexGuard<'static, ProfileCounts> {
+	PROFILE_COUNTS
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Update the profile counts if profiling is on
+fn count_profiled(update: impl FnOnce(&mut ProfileCounts)) {
+	if settings().profile {
+		update(&mut profile_counts());
+	}
+}
+
+/// Format the profile counts of a statement for `:profile`
+fn format_profile(counts: &ProfileCounts) -> String {
+	format!(
+		"profile: {} arithmetic, {} calls, {} compilations",
+		counts.arithmetic, counts.calls, counts.compilations
+	)
+}
+
+thread_local! {
+	// LLVM context shared by all compilation on this thread. Context is neither Send nor
+	// Sync, so each thread gets its own, created on first use and kept for the thread's life.
+	static LLVM_CONTEXT: &'static Context = {
+		CONTEXTS_CREATED.with(|count| count.set(count.get() + 1));
+		Box::leak(Box::new(Context::create()))
+	};
+
+	// Number of LLVM contexts created on this thread
+	static CONTEXTS_CREATED: Cell<usize> = const { Cell::new(0) };
+
+	// Functions compiled for calls on this thread, by name
+	static COMPILED_FUNCTIONS: RefCell<HashMap<String, Rc<CompiledFunction>>> =
+		RefCell::new(HashMap::new());
+
+	// Number of functions compiled for calls on this thread
+	static FUNCTION_COMPILATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Get this thread's persistent LLVM context
+fn llvm_context() -> &'static Context {
+	LLVM_CONTEXT.with(|context| *context)
+}
+
+/// A function compiled for calls. Each one has its own module and execution engine, so
+/// redefining a function replaces just its code and leaves the others compiled.
+struct CompiledFunction {
+	/// The definition, fast-math, strict variables and integer division settings it was
+	/// compiled with
+	source: (parse::LangFunction, bool, bool, IntegerDivision),
+	codegen: LLVMCodeGen<'static>,
+}
+
+/// Get the compiled code for a call to a function, compiling it only if it hasn't been
+/// yet or has changed since
+fn compiled_function(
+	name: &str,
+	function: &parse::LangFunction,
+) -> Result<Rc<CompiledFunction>, Box<dyn Error>> {
+	let source = {
+		let settings = settings();
+		(
+			function.clone(),
+			settings.fast_math,
+			settings.strict_variables,
+			settings.integer_division,
+		)
+	};
+	let cached = COMPILED_FUNCTIONS.with(|compiled| {
+		compiled
+			.borrow()
+			.get(name)
+			.filter(|compiled| compiled.source == source)
+			.cloned()
+	});
+	if let Some(compiled) = cached {
+		return Ok(compiled);
+	}
+
+	let mut codegen = LLVMCodeGen::new(llvm_context())?;
+	codegen.compile_function(name, function)?;
+	FUNCTION_COMPILATIONS.with(|count| count.set(count.get() + 1));
+
+	let compiled = Rc::new(CompiledFunction { source, codegen });
+	COMPILED_FUNCTIONS.with(|functions| {
+		functions
+			.borrow_mut()
+			.insert(name.to_string(), Rc::clone(&compiled));
+	});
+	Ok(compiled)
+}
+
 /// LLVM Code Generator for functions and expressions
 struct LLVMCodeGen<'ctx> {
 	context: &'ctx Context,
@@ -38,14 +384,38 @@ struct LLVMCodeGen<'ctx> {
 	builder: Builder<'ctx>,
 	execution_engine: ExecutionEngine<'ctx>,
 	float_type: FloatType<'ctx>,
+	/// Whether global variables are loaded when the code runs (JIT) rather than
+	/// baked in as constants at compile time (standalone executables)
+	dynamic_globals: bool,
+	/// Whether float arithmetic is emitted with `FAST_MATH_FLAGS`
+	fast_math: bool,
+	/// Whether `:intdiv floor` is on. Compiled code only has reals, so it can't tell which
+	/// divisions are of two integers and leaves `/` to the interpreter.
+	floor_division: bool,
 }
 
+/// Fast-math flags for `:fastmath on`: reassociation, no signed zeros, reciprocals,
+/// contraction and approximate functions. NaN and infinity stay well-defined, since
+/// division by zero and failed conditions rely on them.
+const FAST_MATH_FLAGS: u32 = llvm_sys::LLVMFastMathAllowReassoc
+	| llvm_sys::LLVMFastMathNoSignedZeros
+	| llvm_sys::LLVMFastMathAllowReciprocal
+	| llvm_sys::LLVMFastMathAllowContract
+	| llvm_sys::LLVMFastMathApproxFunc;
+
 impl<'ctx> LLVMCodeGen<'ctx> {
 	fn new(context: &'ctx Context) -> Result<Self, Box<dyn Error>> {
 		let module = context.create_module("fcalc");
 		let execution_engine = module.create_jit_execution_engine(OptimizationLevel::Aggressive)?;
 		let builder = context.create_builder();
 		let float_type = context.f64_type();
+		let (fast_math, floor_division) = {
+			let settings = settings();
+			(
+				settings.fast_math,
+				settings.integer_division == IntegerDivision::Floor,
+			)
+		};
 
 		Ok(LLVMCodeGen {
 			context,
@@ -53,6 +423,9 @@ impl<'ctx> LLVMCodeGen<'ctx> {
 			builder,
 			execution_engine,
 			float_type,
+			dynamic_globals: true,
+			fast_math,
+			floor_division,
 		})
 	}
 
@@ -69,6 +442,13 @@ impl<'ctx> LLVMCodeGen<'ctx> {
 
 		// Create a dummy execution engine for compatibility, but we won't use it
 		let execution_engine = module.create_jit_execution_engine(OptimizationLevel::Aggressive)?;
+		let (fast_math, floor_division) = {
+			let settings = settings();
+			(
+				settings.fast_math,
+				settings.integer_division == IntegerDivision::Floor,
+			)
+		};
 
 		Ok(LLVMCodeGen {
 			context,
@@ -76,9 +456,22 @@ impl<'ctx> LLVMCodeGen<'ctx> {
 			builder,
 			execution_engine,
 			float_type,
+			dynamic_globals: false,
+			fast_math,
+			floor_division,
 		})
 	}
 
+	/// Mark a float arithmetic result with fast-math flags when they are enabled
+	fn fast_math(&self, value: FloatValue<'ctx>) -> FloatValue<'ctx> {
+		if self.fast_math
+			&& let Some(instruction) = value.as_instruction_value()
+		{
+			instruction.set_fast_math_flags(FAST_MATH_FLAGS);
+		}
+		value
+	}
+
 	/// Declare printf function for printing results
 	fn declare_printf(&mut self) -> FunctionValue<'ctx> {
 		// Check if printf is already declared
@@ -86,8 +479,7 @@ impl<'ctx> LLVMCodeGen<'ctx> {
 			return printf_fn;
 		}
 
-		let i8_type = self.context.i8_type();
-		let i8_ptr_type = i8_type.ptr_type(inkwell::AddressSpace::default());
+		let i8_ptr_type = self.char_ptr_type();
 		let i32_type = self.context.i32_type();
 
 		// printf signature: int printf(const char* format, ...)
@@ -96,6 +488,266 @@ impl<'ctx> LLVMCodeGen<'ctx> {
 		self.module.add_function("printf", printf_type, None)
 	}
 
+	/// Declare snprintf for formatting a result into a buffer
+	fn declare_snprintf(&mut self) -> FunctionValue<'ctx> {
+		if let Some(snprintf_fn) = self.module.get_function("snprintf") {
+			return snprintf_fn;
+		}
+
+		// snprintf signature: int snprintf(char* buffer, size_t size, const char* format, ...)
+		let i8_ptr_type = self.char_ptr_type();
+		let snprintf_type = self.context.i32_type().fn_type(
+			&[
+				i8_ptr_type.into(),
+				self.context.i64_type().into(),
+				i8_ptr_type.into(),
+			],
+			true,
+		);
+		self.module.add_function("snprintf", snprintf_type, None)
+	}
+
+	/// Declare strcmp for comparing formatted text
+	fn declare_strcmp(&mut self) -> FunctionValue<'ctx> {
+		if let Some(strcmp_fn) = self.module.get_function("strcmp") {
+			return strcmp_fn;
+		}
+
+		// strcmp signature: int strcmp(const char* a, const char* b)
+		let i8_ptr_type = self.char_ptr_type();
+		let strcmp_type = self
+			.context
+			.i32_type()
+			.fn_type(&[i8_ptr_type.into(), i8_ptr_type.into()], false);
+		self.module.add_function("strcmp", strcmp_type, None)
+	}
+
+	/// Pointer type used for C strings
+	fn char_ptr_type(&self) -> inkwell::types::PointerType<'ctx> {
+		self.context
+			.i8_type()
+			.ptr_type(inkwell::AddressSpace::default())
+	}
+
+	/// Load the byte at `index` of a C string
+	fn build_char_at(
+		&self,
+		text: inkwell::values::PointerValue<'ctx>,
+		index: inkwell::values::IntValue<'ctx>,
+	) -> inkwell::values::IntValue<'ctx> {
+		let i8_type = self.context.i8_type();
+		let address = unsafe {
+			self.builder
+				.build_in_bounds_gep(i8_type, text, &[index], "char_ptr")
+				.unwrap()
+		};
+		self.builder
+			.build_load(i8_type, address, "char")
+			.unwrap()
+			.into_int_value()
+	}
+
+	/// Print a result the same way `format_value` shows it at the REPL with nearest rounding:
+	/// `%.{precision}f` with trailing zeros trimmed, or `%.{precision}g` for a nonzero value
+	/// too small to show at that precision
+	fn build_print_result(&mut self, value: FloatValue<'ctx>) {
+		let precision = settings().precision;
+		let i8_type = self.context.i8_type();
+		let i64_type = self.context.i64_type();
+		let function = self
+			.builder
+			.get_insert_block()
+			.and_then(|block| block.get_parent())
+			.unwrap();
+
+		// Any double fits in 310 digits before the point, plus sign, point and decimals
+		let size = 320 + precision as u64;
+		let buffer_type = i8_type.array_type(size as u32);
+		let buffer = self
+			.builder
+			.build_alloca(buffer_type, "result_text")
+			.unwrap();
+		let zero = i64_type.const_zero();
+		let text = unsafe {
+			self.builder
+				.build_in_bounds_gep(buffer_type, buffer, &[zero, zero], "text")
+				.unwrap()
+		};
+		let snprintf_fn = self.declare_snprintf();
+		let fixed_format = self.create_global_string_ptr(&format!("%.{}f", precision), "fmt_fixed");
+		let length = self
+			.builder
+			.build_call(
+				snprintf_fn,
+				&[
+					text.into(),
+					i64_type.const_int(size, false).into(),
+					fixed_format.into(),
+					value.into(),
+				],
+				"length",
+			)
+			.unwrap()
+			.try_as_basic_value()
+			.left()
+			.unwrap()
+			.into_int_value();
+
+		if precision > 0 {
+			// Step back over trailing zeros, then drop the point if nothing is left after it
+			let entry_block = self.builder.get_insert_block().unwrap();
+			let scan_block = self.context.append_basic_block(function, "trim_zeros");
+			let trimmed_block = self.context.append_basic_block(function, "trimmed");
+			let length = self
+				.builder
+				.build_int_s_extend(length, i64_type, "length64")
+				.unwrap();
+			let last = self
+				.builder
+				.build_int_sub(length, i64_type.const_int(1, false), "last")
+				.unwrap();
+			self.builder.build_unconditional_branch(scan_block).unwrap();
+
+			self.builder.position_at_end(scan_block);
+			let index = self.builder.build_phi(i64_type, "index").unwrap();
+			let index_value = index.as_basic_value().into_int_value();
+			let character = self.build_char_at(text, index_value);
+			let is_zero = self
+				.builder
+				.build_int_compare(
+					inkwell::IntPredicate::EQ,
+					character,
+					i8_type.const_int(b'0' as u64, false),
+					"is_zero",
+				)
+				.unwrap();
+			let previous = self
+				.builder
+				.build_int_sub(index_value, i64_type.const_int(1, false), "previous")
+				.unwrap();
+			index.add_incoming(&[(&last, entry_block), (&previous, scan_block)]);
+			self.builder
+				.build_conditional_branch(is_zero, scan_block, trimmed_block)
+				.unwrap();
+
+			self.builder.position_at_end(trimmed_block);
+			let is_point = self
+				.builder
+				.build_int_compare(
+					inkwell::IntPredicate::EQ,
+					character,
+					i8_type.const_int(b'.' as u64, false),
+					"is_point",
+				)
+				.unwrap();
+			let after = self
+				.builder
+				.build_int_add(index_value, i64_type.const_int(1, false), "after")
+				.unwrap();
+			let end = self
+				.builder
+				.build_select(is_point, index_value, after, "end")
+				.unwrap()
+				.into_int_value();
+			let end_address = unsafe {
+				self.builder
+					.build_in_bounds_gep(i8_type, text, &[end], "end_ptr")
+					.unwrap()
+			};
+			self.builder
+				.build_store(end_address, i8_type.const_zero())
+				.unwrap();
+		}
+
+		// Text of "0" or "-0" means the value is zero at this precision
+		let strcmp_fn = self.declare_strcmp();
+		let zero_text = self.create_global_string_ptr("0", "text_zero");
+		let negative_zero_text = self.create_global_string_ptr("-0", "text_negative_zero");
+		let mut shows_zero = Vec::new();
+		for (candidate, name) in [
+			(zero_text, "is_zero_text"),
+			(negative_zero_text, "is_negative_zero_text"),
+		] {
+			let compared = self
+				.builder
+				.build_call(strcmp_fn, &[text.into(), candidate.into()], "compared")
+				.unwrap()
+				.try_as_basic_value()
+				.left()
+				.unwrap()
+				.into_int_value();
+			shows_zero.push(
+				self.builder
+					.build_int_compare(
+						inkwell::IntPredicate::EQ,
+						compared,
+						self.context.i32_type().const_zero(),
+						name,
+					)
+					.unwrap(),
+			);
+		}
+		let shows_zero = self
+			.builder
+			.build_or(shows_zero[0], shows_zero[1], "shows_zero")
+			.unwrap();
+		let is_nonzero = self
+			.builder
+			.build_float_compare(
+				inkwell::FloatPredicate::ONE,
+				value,
+				self.float_type.const_zero(),
+				"is_nonzero",
+			)
+			.unwrap();
+		let too_small = self
+			.builder
+			.build_and(shows_zero, is_nonzero, "too_small")
+			.unwrap();
+
+		let printf_fn = self.declare_printf();
+		let small_block = self.context.append_basic_block(function, "print_small");
+		let text_block = self.context.append_basic_block(function, "print_text");
+		let printed_block = self.context.append_basic_block(function, "printed");
+		self.builder
+			.build_conditional_branch(too_small, small_block, text_block)
+			.unwrap();
+
+		self.builder.position_at_end(small_block);
+		let small_format =
+			self.create_global_string_ptr(&format!("%.{}g\n", precision.max(1)), "fmt_small");
+		self.builder
+			.build_call(
+				printf_fn,
+				&[small_format.into(), value.into()],
+				"printf_call",
+			)
+			.unwrap();
+		self.builder
+			.build_unconditional_branch(printed_block)
+			.unwrap();
+
+		self.builder.position_at_end(text_block);
+		// Negative values that round to zero shouldn't print a sign
+		let shown = self
+			.builder
+			.build_select(shows_zero, zero_text, text, "shown")
+			.unwrap();
+		let text_format = self.create_global_string_ptr("%s\n", "fmt_text");
+		self.builder
+			.build_call(
+				printf_fn,
+				&[text_format.into(), shown.into()],
+				"printf_call",
+			)
+			.unwrap();
+		self.builder
+			.build_unconditional_branch(printed_block)
+			.unwrap();
+
+		self.builder.position_at_end(printed_block);
+	}
+
 	/// Create a global string constant and return a pointer to the string data
 	fn create_global_string_ptr(
 		&mut self,
@@ -178,14 +830,15 @@ impl<'ctx> LLVMCodeGen<'ctx> {
 			.map_err(|e| format!("Failed to write object file: {}", e))?;
 
 		// Link the object file to create executable
-		#[cfg(target_os = "macos")]
-		let link_command = format!("clang -o {} {} -lm", output_path, object_path);
-
-		#[cfg(target_os = "linux")]
-		let link_command = format!("gcc -o {} {} -lm", output_path, object_path);
-
-		#[cfg(target_os = "windows")]
-		let link_command = format!("clang -o {}.exe {} -lm", output_path, object_path);
+		let link_command = {
+			let settings = settings();
+			build_link_command(
+				&settings.linker,
+				&settings.linker_flags,
+				output_path,
+				&object_path,
+			)
+		};
 
 		// Execute the link command
 		let output = std::process::Command::new("sh")
@@ -223,12 +876,6 @@ impl<'ctx> LLVMCodeGen<'ctx> {
 		let basic_block = self.context.append_basic_block(main_function, "entry");
 		self.builder.position_at_end(basic_block);
 
-		// Declare printf function
-		let printf_fn = self.declare_printf();
-
-		// Create format string for printing floating point numbers
-		let format_ptr = self.create_global_string_ptr("%.15g\n", "fmt_float");
-
 		// Get the user function
 		if let Some(user_function) = self.module.get_function(function_name) {
 			// Prepare arguments
@@ -250,14 +897,8 @@ impl<'ctx> LLVMCodeGen<'ctx> {
 				.unwrap()
 				.into_float_value();
 
-			// Call printf to print the result
-			self.builder
-				.build_call(
-					printf_fn,
-					&[format_ptr.into(), result_value.into()],
-					"printf_call",
-				)
-				.unwrap();
+			// Print the result the way the REPL shows it
+			self.build_print_result(result_value);
 
 			// Return 0 for success
 			let return_val = i32_type.const_int(0, false);
@@ -303,43 +944,7 @@ impl<'ctx> LLVMCodeGen<'ctx> {
 
 		// Return the result
 		self.builder.build_return(Some(&result)).unwrap();
-
-		Ok(llvm_function)
-	}
-
-	/// Compile a named function definition to LLVM IR
-	fn compile_named_function(
-		&mut self,
-		named_function: &parse::LangNamedFunction,
-	) -> Result<FunctionValue<'ctx>, Box<dyn Error>> {
-		// Create function type: all parameters and return value are f64
-		let param_types: Vec<_> = (0..named_function.parameters.len())
-			.map(|_| self.float_type.into())
-			.collect();
-		let fn_type = self.float_type.fn_type(&param_types, false);
-
-		// Create the function
-		let llvm_function = self
-			.module
-			.add_function(&named_function.name, fn_type, None);
-		let basic_block = self.context.append_basic_block(llvm_function, "entry");
-		self.builder.position_at_end(basic_block);
-
-		// Create parameter bindings
-		let mut param_values = HashMap::new();
-		for (i, param_name) in named_function.parameters.iter().enumerate() {
-			let param_value = llvm_function
-				.get_nth_param(i as u32)
-				.ok_or(format!("Missing parameter {}", i))?
-				.into_float_value();
-			param_values.insert(param_name.clone(), param_value);
-		}
-
-		// Compile the function body
-		let result = self.compile_block(&named_function.body, &param_values)?;
-
-		// Return the result
-		self.builder.build_return(Some(&result)).unwrap();
+		count_profiled(|counts| counts.compilations += 1);
 
 		Ok(llvm_function)
 	}
@@ -386,33 +991,247 @@ impl<'ctx> LLVMCodeGen<'ctx> {
 					}
 					last_result = self.compile_function_call(call, variables)?;
 				}
+				parse::LangBlockItem::List(_) => {
+					// Compiled code only works with numbers
+					return Err("Lists in expressions - use runtime evaluation".into());
+				}
+				parse::LangBlockItem::If(if_item) => {
+					last_result = self.compile_if(if_item, variables)?;
+				}
+				parse::LangBlockItem::Match(match_item) => {
+					let subject_line = parse::LangLine {
+						tokens: match_item.subject.clone(),
+					};
+					if self.contains_user_function_calls(&subject_line) {
+						return Err(
+							"Function contains calls to other functions - use runtime evaluation"
+								.into(),
+						);
+					}
+					// Compiled code always produces a number, so a match that can fall through
+					// every arm is left to the interpreter, where it gives no value
+					if match_item.arms.iter().all(|arm| arm.pattern.is_some()) {
+						return Err("Match without a default arm - use runtime evaluation".into());
+					}
+					let subject = self.compile_line(&subject_line, variables)?;
+					last_result = self.compile_match_arms(subject, &match_item.arms, variables)?;
+				}
 			}
 		}
 
 		Ok(last_result)
 	}
 
-	/// Check if a line contains calls to user-defined functions
-	fn contains_user_function_calls(&self, line: &parse::LangLine) -> bool {
-		// Look for function call patterns in the tokens
-		let mut i = 0;
-		while i + 1 < line.tokens.len() {
-			if let (Token::Symbol(name), Token::Operator(op)) =
-				(&line.tokens[i], &line.tokens[i + 1])
-			{
-				if op.value == "(" && self.is_user_defined_function(&name.value) {
-					return true;
-				}
-			}
-			i += 1;
+	/// Compile an if/else item into conditional branches joined by a phi node.
+	/// A missing else branch yields 0.
+	fn compile_if(
+		&mut self,
+		if_item: &parse::LangIf,
+		variables: &HashMap<String, FloatValue<'ctx>>,
+	) -> Result<FloatValue<'ctx>, Box<dyn Error>> {
+		if if_item.assign_to.is_some() {
+			return Err("Assignment of an if expression - use runtime evaluation".into());
 		}
-		false
+		let condition_line = parse::LangLine {
+			tokens: if_item.condition.clone(),
+		};
+		if condition_line
+			.tokens
+			.iter()
+			.any(|token| matches!(token, Token::Operator(op) if op.value == "{"))
+		{
+			return Err("Block as an if condition - use runtime evaluation".into());
+		}
+		if self.contains_user_function_calls(&condition_line) {
+			return Err(
+				"Function contains calls to other functions - use runtime evaluation".into(),
+			);
+		}
+		let condition = self.compile_line(&condition_line, variables)?;
+
+		self.compile_branches(
+			condition,
+			|codegen| codegen.compile_block(&if_item.then_block, variables),
+			|codegen| match &if_item.else_block {
+				Some(else_block) => codegen.compile_block(else_block, variables),
+				None => Ok(codegen.float_type.const_float(0.0)),
+			},
+		)
 	}
 
-	/// Check if a function name refers to a user-defined function
-	fn is_user_defined_function(&self, name: &str) -> bool {
-		match FUNCTIONS.lock() {
-			Ok(functions) => functions.contains_key(name),
+	/// Compile match arms into a chain of branches, testing the subject against each case
+	/// literal in turn. The `_` arm matches anything; no matching arm yields 0.
+	fn compile_match_arms(
+		&mut self,
+		subject: FloatValue<'ctx>,
+		arms: &[parse::LangMatchArm],
+		variables: &HashMap<String, FloatValue<'ctx>>,
+	) -> Result<FloatValue<'ctx>, Box<dyn Error>> {
+		let Some((arm, rest)) = arms.split_first() else {
+			return Ok(self.float_type.const_float(0.0));
+		};
+		let Some(pattern) = &arm.pattern else {
+			return self.compile_block(&arm.body, variables);
+		};
+
+		let literal = self.float_type.const_float(match_pattern_value(pattern));
+		let is_equal = self
+			.builder
+			.build_float_compare(inkwell::FloatPredicate::OEQ, subject, literal, "is_case")
+			.unwrap();
+		let condition = self
+			.builder
+			.build_unsigned_int_to_float(is_equal, self.float_type, "case")
+			.unwrap();
+		self.compile_branches(
+			condition,
+			|codegen| codegen.compile_block(&arm.body, variables),
+			|codegen| codegen.compile_match_arms(subject, rest, variables),
+		)
+	}
+
+	/// Compile operands joined by `&&` or `||` into short-circuit branches, so each operand
+	/// only runs when the result still depends on it. The result is 1 or 0.
+	fn compile_logical(
+		&mut self,
+		operands: &[&[Token]],
+		operator: &str,
+		variables: &HashMap<String, FloatValue<'ctx>>,
+	) -> Result<FloatValue<'ctx>, Box<dyn Error>> {
+		if operands[0].is_empty() {
+			return Err("Syntax error: '&&' and '||' need an operand on each side".into());
+		}
+		let first = self.compile_expression(operands[0], variables)?;
+		if operands.len() == 1 {
+			let is_true = self.build_is_truthy(first);
+			return Ok(self
+				.builder
+				.build_unsigned_int_to_float(is_true, self.float_type, "truth")
+				.unwrap());
+		}
+
+		let rest = &operands[1..];
+		let decided = self
+			.float_type
+			.const_float(if operator == "||" { 1.0 } else { 0.0 });
+		if operator == "||" {
+			self.compile_branches(
+				first,
+				|_| Ok(decided),
+				|codegen| codegen.compile_logical(rest, operator, variables),
+			)
+		} else {
+			self.compile_branches(
+				first,
+				|codegen| codegen.compile_logical(rest, operator, variables),
+				|_| Ok(decided),
+			)
+		}
+	}
+
+	/// Emit the truthiness test of `is_truthy`: ordered and not equal to zero, so NaN is false
+	fn build_is_truthy(&self, value: FloatValue<'ctx>) -> inkwell::values::IntValue<'ctx> {
+		let zero = self.float_type.const_float(0.0);
+		self.builder
+			.build_float_compare(inkwell::FloatPredicate::ONE, value, zero, "is_true")
+			.unwrap()
+	}
+
+	/// Emit a branch on whether `condition` is truthy to two compiled values and merge them
+	fn compile_branches(
+		&mut self,
+		condition: FloatValue<'ctx>,
+		compile_then: impl FnOnce(&mut Self) -> Result<FloatValue<'ctx>, Box<dyn Error>>,
+		compile_else: impl FnOnce(&mut Self) -> Result<FloatValue<'ctx>, Box<dyn Error>>,
+	) -> Result<FloatValue<'ctx>, Box<dyn Error>> {
+		let is_true = self.build_is_truthy(condition);
+
+		let function = self
+			.builder
+			.get_insert_block()
+			.unwrap()
+			.get_parent()
+			.unwrap();
+		let then_bb = self.context.append_basic_block(function, "then");
+		let else_bb = self.context.append_basic_block(function, "else");
+		let merge_bb = self.context.append_basic_block(function, "merge");
+
+		self.builder
+			.build_conditional_branch(is_true, then_bb, else_bb)
+			.unwrap();
+
+		// Branches may add blocks of their own (e.g. division checks), so the phi
+		// takes each value from wherever its branch ended
+		self.builder.position_at_end(then_bb);
+		let then_value = compile_then(self)?;
+		let then_end = self.builder.get_insert_block().unwrap();
+		self.builder.build_unconditional_branch(merge_bb).unwrap();
+
+		self.builder.position_at_end(else_bb);
+		let else_value = compile_else(self)?;
+		let else_end = self.builder.get_insert_block().unwrap();
+		self.builder.build_unconditional_branch(merge_bb).unwrap();
+
+		self.builder.position_at_end(merge_bb);
+		let phi = self
+			.builder
+			.build_phi(self.float_type, "if_result")
+			.unwrap();
+		phi.add_incoming(&[(&then_value, then_end), (&else_value, else_end)]);
+		Ok(phi.as_basic_value().into_float_value())
+	}
+
+	/// Emit a call that reads a global variable's value at run time
+	fn build_global_load(&self, name: &str) -> FloatValue<'ctx> {
+		let load_fn = match self.module.get_function("fcalc_load_global") {
+			Some(load_fn) => load_fn,
+			None => {
+				let i64_type = self.context.i64_type();
+				let fn_type = self.float_type.fn_type(&[i64_type.into()], false);
+				let load_fn = self.module.add_function("fcalc_load_global", fn_type, None);
+				self.execution_engine
+					.add_global_mapping(&load_fn, fcalc_load_global as *const () as usize);
+				load_fn
+			}
+		};
+		let slot = self
+			.context
+			.i64_type()
+			.const_int(global_slot(name) as u64, false);
+		self.builder
+			.build_call(load_fn, &[slot.into()], name)
+			.unwrap()
+			.try_as_basic_value()
+			.left()
+			.unwrap()
+			.into_float_value()
+	}
+
+	/// Check if a line contains calls to user-defined functions
+	fn contains_user_function_calls(&self, line: &parse::LangLine) -> bool {
+		// Look for function call patterns in the tokens
+		let mut i = 0;
+		while i + 1 < line.tokens.len() {
+			if let (Token::Symbol(name), Token::Operator(op)) =
+				(&line.tokens[i], &line.tokens[i + 1])
+			{
+				if op.value == "(" && self.is_user_defined_function(&name.value) {
+					return true;
+				}
+			}
+			i += 1;
+		}
+		false
+	}
+
+	/// Check if a function name refers to a user-defined function.
+	/// Built-ins only exist in the interpreter, so they count as well.
+	fn is_user_defined_function(&self, name: &str) -> bool {
+		if is_builtin(name) || !guarded_clauses(name).is_empty() {
+			return true;
+		}
+		match FUNCTIONS.lock() {
+			Ok(functions) => functions.contains_key(name),
 			Err(poisoned) => {
 				let functions = poisoned.into_inner();
 				functions.contains_key(name)
@@ -426,8 +1245,40 @@ impl<'ctx> LLVMCodeGen<'ctx> {
 		line: &parse::LangLine,
 		variables: &HashMap<String, FloatValue<'ctx>>,
 	) -> Result<FloatValue<'ctx>, Box<dyn Error>> {
-		// Convert infix to postfix
-		let postfix_tokens = infix_to_postfix(&line.tokens);
+		self.compile_expression(&line.tokens, variables)
+	}
+
+	/// Compile an infix expression, turning a top-level conditional into branches
+	fn compile_expression(
+		&mut self,
+		tokens: &[Token],
+		variables: &HashMap<String, FloatValue<'ctx>>,
+	) -> Result<FloatValue<'ctx>, Box<dyn Error>> {
+		if let Some((question, colon)) = find_ternary(tokens) {
+			let condition = self.compile_expression(&tokens[..question], variables)?;
+			return self.compile_branches(
+				condition,
+				|codegen| codegen.compile_expression(&tokens[question + 1..colon], variables),
+				|codegen| codegen.compile_expression(&tokens[colon + 1..], variables),
+			);
+		}
+
+		if let Some(disjuncts) = split_top_level(tokens, "||") {
+			return self.compile_logical(&disjuncts, "||", variables);
+		}
+		if let Some(conjuncts) = split_top_level(tokens, "&&") {
+			return self.compile_logical(&conjuncts, "&&", variables);
+		}
+
+		if tokens.iter().any(
+			|t| matches!(t, Token::Operator(op) if matches!(op.value.as_str(), "?" | ":" | "&&" | "||")),
+		) {
+			return Err("Nested conditional expression - use runtime evaluation".into());
+		}
+
+		// Convert infix to postfix, with unary minus handled the same way as the interpreter
+		let unary_processed_tokens = preprocess_unary_minus(tokens);
+		let postfix_tokens = infix_to_postfix(&unary_processed_tokens);
 		self.compile_postfix_expression(&postfix_tokens, variables)
 	}
 
@@ -453,26 +1304,39 @@ impl<'ctx> LLVMCodeGen<'ctx> {
 					// Look up variable value
 					if let Some(&value) = variables.get(&symbol.value) {
 						value_stack.push(value);
-					} else {
-						// Try to get global variable value
-						let global_value = match VARIABLES.lock() {
-							Ok(vars) => vars.get(&symbol.value).copied(),
-							Err(poisoned) => {
-								let vars = poisoned.into_inner();
-								vars.get(&symbol.value).copied()
-							}
-						};
+						continue;
+					}
 
-						let value = self.float_type.const_float(global_value.unwrap_or(0.0));
-						value_stack.push(value);
+					// Try to get global variable value
+					let global_value = match VARIABLES.lock() {
+						Ok(vars) => vars.get(&symbol.value).copied(),
+						Err(poisoned) => {
+							let vars = poisoned.into_inner();
+							vars.get(&symbol.value).copied()
+						}
+					};
+					// Strict mode errors can only be raised while compiling
+					let global_value = match global_value {
+						Some(value) => value,
+						None => undefined_variable(&symbol.value)?,
+					};
+					if self.dynamic_globals {
+						// Read the global's current value each time the code runs
+						value_stack.push(self.build_global_load(&symbol.value));
+					} else {
+						value_stack.push(self.float_type.const_float(global_value));
 					}
 				}
+				Token::Operator(op) if is_binary_operator(&op.value) && value_stack.len() < 2 => {
+					return Err(missing_operand_error(&op.value));
+				}
 				Token::Operator(op) => match op.value.as_str() {
 					"+" => {
 						if value_stack.len() >= 2 {
 							let b = value_stack.pop().unwrap();
 							let a = value_stack.pop().unwrap();
-							let result = self.builder.build_float_add(a, b, "add").unwrap();
+							let result =
+								self.fast_math(self.builder.build_float_add(a, b, "add").unwrap());
 							value_stack.push(result);
 						}
 					}
@@ -480,7 +1344,8 @@ impl<'ctx> LLVMCodeGen<'ctx> {
 						if value_stack.len() >= 2 {
 							let b = value_stack.pop().unwrap();
 							let a = value_stack.pop().unwrap();
-							let result = self.builder.build_float_sub(a, b, "sub").unwrap();
+							let result =
+								self.fast_math(self.builder.build_float_sub(a, b, "sub").unwrap());
 							value_stack.push(result);
 						}
 					}
@@ -488,11 +1353,15 @@ impl<'ctx> LLVMCodeGen<'ctx> {
 						if value_stack.len() >= 2 {
 							let b = value_stack.pop().unwrap();
 							let a = value_stack.pop().unwrap();
-							let result = self.builder.build_float_mul(a, b, "mul").unwrap();
+							let result =
+								self.fast_math(self.builder.build_float_mul(a, b, "mul").unwrap());
 							value_stack.push(result);
 						}
 					}
-					"/" => {
+					"/" if self.floor_division => {
+						return Err("Division under :intdiv floor - use runtime evaluation".into());
+					}
+					"/" | "div" => {
 						if value_stack.len() >= 2 {
 							let b = value_stack.pop().unwrap();
 							let a = value_stack.pop().unwrap();
@@ -527,7 +1396,12 @@ impl<'ctx> LLVMCodeGen<'ctx> {
 
 							// Division block
 							self.builder.position_at_end(div_bb);
-							let result = self.builder.build_float_div(a, b, "div").unwrap();
+							let mut result =
+								self.fast_math(self.builder.build_float_div(a, b, "div").unwrap());
+							if op.value == "div" {
+								// Integer division rounds the quotient down
+								result = self.build_unary_intrinsic("llvm.floor", result)?;
+							}
 							self.builder
 								.build_unconditional_branch(continue_bb)
 								.unwrap();
@@ -550,12 +1424,87 @@ impl<'ctx> LLVMCodeGen<'ctx> {
 							value_stack.push(phi.as_basic_value().into_float_value());
 						}
 					}
+					"%" => {
+						let b = value_stack.pop().unwrap();
+						let a = value_stack.pop().unwrap();
+						let result =
+							self.fast_math(self.builder.build_float_rem(a, b, "rem").unwrap());
+						value_stack.push(result);
+					}
+					"^" => {
+						let b = value_stack.pop().unwrap();
+						let a = value_stack.pop().unwrap();
+						let pow = inkwell::intrinsics::Intrinsic::find("llvm.pow")
+							.and_then(|intrinsic| {
+								intrinsic.get_declaration(&self.module, &[self.float_type.into()])
+							})
+							.ok_or("LLVM pow intrinsic not available")?;
+						let result = self
+							.builder
+							.build_call(pow, &[a.into(), b.into()], "pow")
+							.unwrap()
+							.try_as_basic_value()
+							.left()
+							.unwrap()
+							.into_float_value();
+						value_stack.push(self.fast_math(result));
+					}
+					UNARY_MINUS => {
+						let value = value_stack
+							.pop()
+							.ok_or_else(|| missing_operand_error("-"))?;
+						let result =
+							self.fast_math(self.builder.build_float_neg(value, "neg").unwrap());
+						value_stack.push(result);
+					}
+					NOT => {
+						let value = value_stack
+							.pop()
+							.ok_or_else(|| missing_operand_error(NOT))?;
+						// The negation of truthiness: zero or NaN
+						let zero = self.float_type.const_float(0.0);
+						let is_false = self
+							.builder
+							.build_float_compare(inkwell::FloatPredicate::UEQ, value, zero, "not")
+							.unwrap();
+						let result = self
+							.builder
+							.build_unsigned_int_to_float(is_false, self.float_type, "not_result")
+							.unwrap();
+						value_stack.push(result);
+					}
+					"==" | "!=" | "<" | ">" | "<=" | ">=" => {
+						if value_stack.len() >= 2 {
+							let b = value_stack.pop().unwrap();
+							let a = value_stack.pop().unwrap();
+							let predicate = match op.value.as_str() {
+								"==" => inkwell::FloatPredicate::OEQ,
+								"!=" => inkwell::FloatPredicate::UNE,
+								"<" => inkwell::FloatPredicate::OLT,
+								">" => inkwell::FloatPredicate::OGT,
+								"<=" => inkwell::FloatPredicate::OLE,
+								_ => inkwell::FloatPredicate::OGE,
+							};
+							let is_true = self
+								.builder
+								.build_float_compare(predicate, a, b, "cmp")
+								.unwrap();
+
+							// Comparisons evaluate to 1.0 (true) or 0.0 (false)
+							let result = self
+								.builder
+								.build_unsigned_int_to_float(is_true, self.float_type, "cmp_result")
+								.unwrap();
+							value_stack.push(result);
+						}
+					}
 					_ => {
 						return Err(format!("Unsupported operator: {}", op.value).into());
 					}
 				},
-				_ => {
-					return Err("Unsupported token type in expression".into());
+				Token::String(_) => {
+					// Strings only exist in the interpreter
+					return Err("Strings in expressions - use runtime evaluation".into());
 				}
 			}
 		}
@@ -563,6 +1512,27 @@ impl<'ctx> LLVMCodeGen<'ctx> {
 		value_stack.last().copied().ok_or("Empty expression".into())
 	}
 
+	/// Call a one-argument LLVM intrinsic such as `llvm.floor` on a value
+	fn build_unary_intrinsic(
+		&self,
+		name: &str,
+		value: FloatValue<'ctx>,
+	) -> Result<FloatValue<'ctx>, Box<dyn Error>> {
+		let intrinsic = inkwell::intrinsics::Intrinsic::find(name)
+			.and_then(|intrinsic| {
+				intrinsic.get_declaration(&self.module, &[self.float_type.into()])
+			})
+			.ok_or(format!("LLVM {} intrinsic not available", name))?;
+		Ok(self
+			.builder
+			.build_call(intrinsic, &[value.into()], name)
+			.unwrap()
+			.try_as_basic_value()
+			.left()
+			.unwrap()
+			.into_float_value())
+	}
+
 	/// Compile a function call
 	fn compile_function_call(
 		&mut self,
@@ -596,6 +1566,42 @@ impl<'ctx> LLVMCodeGen<'ctx> {
 	}
 }
 
+/// Get the slot number identifying a global variable name in JIT-compiled code
+fn global_slot(name: &str) -> usize {
+	let mut names = GLOBAL_SLOTS
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	match names.iter().position(|n| n == name) {
+		Some(slot) => slot,
+		None => {
+			names.push(name.to_string());
+			names.len() - 1
+		}
+	}
+}
+
+/// Called from JIT-compiled code to read the current value of a global variable.
+/// Undefined variables read as the `:default` value; compiled code can't report an error,
+/// so strict mode is checked when compiling instead.
+extern "C" fn fcalc_load_global(slot: u64) -> f64 {
+	let name = match GLOBAL_SLOTS
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner())
+		.get(slot as usize)
+	{
+		Some(name) => name.clone(),
+		None => return settings().undefined_default,
+	};
+	let value = match VARIABLES.lock() {
+		Ok(vars) => vars.get(&name).copied(),
+		Err(poisoned) => {
+			let vars = poisoned.into_inner();
+			vars.get(&name).copied()
+		}
+	};
+	value.unwrap_or_else(|| settings().undefined_default)
+}
+
 /// Check if a function contains calls to other user-defined functions
 fn function_contains_user_function_calls(function: &parse::LangFunction) -> bool {
 	contains_user_function_calls_in_block(&function.body)
@@ -620,6 +1626,42 @@ fn contains_user_function_calls_in_block(block: &parse::LangBlock) -> bool {
 					return true;
 				}
 			}
+			parse::LangBlockItem::List(list)
+				if list.items.iter().any(|item| {
+					contains_user_function_calls_in_line(&parse::LangLine {
+						tokens: item.clone(),
+					})
+				}) =>
+			{
+				return true;
+			}
+			parse::LangBlockItem::If(if_item) => {
+				let condition = parse::LangLine {
+					tokens: if_item.condition.clone(),
+				};
+				if contains_user_function_calls_in_line(&condition)
+					|| contains_user_function_calls_in_block(&if_item.then_block)
+					|| if_item
+						.else_block
+						.as_ref()
+						.is_some_and(contains_user_function_calls_in_block)
+				{
+					return true;
+				}
+			}
+			parse::LangBlockItem::Match(match_item) => {
+				let subject = parse::LangLine {
+					tokens: match_item.subject.clone(),
+				};
+				if contains_user_function_calls_in_line(&subject)
+					|| match_item
+						.arms
+						.iter()
+						.any(|arm| contains_user_function_calls_in_block(&arm.body))
+				{
+					return true;
+				}
+			}
 			_ => {}
 		}
 	}
@@ -628,6 +1670,14 @@ fn contains_user_function_calls_in_block(block: &parse::LangBlock) -> bool {
 
 /// Check if a line contains calls to user-defined functions
 fn contains_user_function_calls_in_line(line: &parse::LangLine) -> bool {
+	// Function application and pipelines always call a function by name
+	if line
+		.tokens
+		.iter()
+		.any(|token| matches!(token, Token::Operator(op) if op.value == "@" || op.value == "|>"))
+	{
+		return true;
+	}
 	let mut i = 0;
 	while i + 1 < line.tokens.len() {
 		if let (Token::Symbol(name), Token::Operator(op)) = (&line.tokens[i], &line.tokens[i + 1]) {
@@ -640,8 +1690,12 @@ fn contains_user_function_calls_in_line(line: &parse::LangLine) -> bool {
 	false
 }
 
-/// Check if a function name refers to a user-defined function (global version)
+/// Check if a function name refers to a user-defined function (global version).
+/// Built-ins only exist in the interpreter, so they count as well.
 fn is_user_defined_function_global(name: &str) -> bool {
+	if is_builtin(name) || !guarded_clauses(name).is_empty() {
+		return true;
+	}
 	match FUNCTIONS.lock() {
 		Ok(functions) => functions.contains_key(name),
 		Err(poisoned) => {
@@ -651,6 +1705,133 @@ fn is_user_defined_function_global(name: &str) -> bool {
 	}
 }
 
+/// Names used in a function's body that aren't its parameters, assigned in the body, global
+/// variables or functions, in order of first use. These are likely typos, but a global that
+/// is only defined later shows up too, so they are only warned about.
+fn unknown_symbols(function: &parse::LangNamedFunction) -> Vec<String> {
+	let mut used = Vec::new();
+	let mut local = function.parameters.clone();
+	local.push(function.name.clone());
+	if let Some(guard) = &function.guard {
+		collect_line_symbols(guard, &mut used, &mut local);
+	}
+	collect_block_symbols(&function.body, &mut used, &mut local);
+
+	let variables = match VARIABLES.lock() {
+		Ok(variables) => variables.clone(),
+		Err(poisoned) => poisoned.into_inner().clone(),
+	};
+	used.into_iter()
+		.filter(|name| {
+			!local.contains(name)
+				&& !variables.contains_key(name)
+				&& list_variable(name).is_none()
+				&& !is_user_defined_function_global(name)
+		})
+		.collect()
+}
+
+/// Gather the names a block uses into `used`, and the names it defines (assignment targets
+/// and the parameters of functions inside it) into `local`
+fn collect_block_symbols(block: &LangBlock, used: &mut Vec<String>, local: &mut Vec<String>) {
+	for item in &block.items {
+		match item {
+			parse::LangBlockItem::Line(line) => collect_line_symbols(&line.tokens, used, local),
+			parse::LangBlockItem::Block(nested_block) => {
+				collect_block_symbols(nested_block, used, local);
+			}
+			parse::LangBlockItem::Function(function) => {
+				local.extend(function.parameters.iter().cloned());
+				collect_block_symbols(&function.body, used, local);
+			}
+			parse::LangBlockItem::NamedFunction(function) => {
+				local.push(function.name.clone());
+				local.extend(function.parameters.iter().cloned());
+				collect_block_symbols(&function.body, used, local);
+			}
+			parse::LangBlockItem::FunctionCall(call) => {
+				if !used.contains(&call.name) {
+					used.push(call.name.clone());
+				}
+				for argument in &call.arguments {
+					collect_line_symbols(argument, used, local);
+				}
+			}
+			parse::LangBlockItem::List(list) => {
+				for item in &list.items {
+					collect_line_symbols(item, used, local);
+				}
+			}
+			parse::LangBlockItem::If(if_item) => {
+				local.extend(if_item.assign_to.iter().cloned());
+				collect_line_symbols(&if_item.condition, used, local);
+				collect_block_symbols(&if_item.then_block, used, local);
+				if let Some(else_block) = &if_item.else_block {
+					collect_block_symbols(else_block, used, local);
+				}
+			}
+			parse::LangBlockItem::Match(match_item) => {
+				collect_line_symbols(&match_item.subject, used, local);
+				for arm in &match_item.arms {
+					collect_block_symbols(&arm.body, used, local);
+				}
+			}
+		}
+	}
+}
+
+/// Gather the names one expression uses and assigns, like `collect_block_symbols`
+fn collect_line_symbols(tokens: &[Token], used: &mut Vec<String>, local: &mut Vec<String>) {
+	const KEYWORDS: &[&str] = &["if", "else", "fn", "when", "match", "not"];
+
+	if let Some((names, _)) = split_tuple_assignment(tokens) {
+		local.extend(names.iter().map(|name| name.to_string()));
+	}
+	let mut rest = tokens;
+	while let Some((name, value_tokens)) = split_assignment(rest) {
+		local.push(name.to_string());
+		rest = value_tokens;
+	}
+	// Parameters of a lambda written inside the expression, as in `map((v) => { v }, xs)`
+	for (i, token) in tokens.iter().enumerate() {
+		if matches!(token, Token::Operator(op) if op.value == "=>") {
+			let parameters = tokens[..i]
+				.iter()
+				.rev()
+				.skip(1)
+				.take_while(|token| !matches!(token, Token::Operator(op) if op.value == "("));
+			for parameter in parameters {
+				if let Token::Symbol(symbol) = parameter {
+					local.push(symbol.value.clone());
+				}
+			}
+		}
+	}
+
+	for token in tokens {
+		if let Token::Symbol(symbol) = token
+			&& !KEYWORDS.contains(&symbol.value.as_str())
+			&& !used.contains(&symbol.value)
+		{
+			used.push(symbol.value.clone());
+		}
+	}
+}
+
+/// Warn about the names in a function being defined that look like typos, when `:checknames`
+/// is on
+fn warn_unknown_symbols(function: &parse::LangNamedFunction) {
+	if !settings().check_names {
+		return;
+	}
+	for name in unknown_symbols(function) {
+		println!(
+			"Warning: '{}' in {} is not a parameter, variable or function",
+			name, function.name
+		);
+	}
+}
+
 /// Evaluate a function at runtime using the interpreter
 fn evaluate_function_at_runtime(
 	function: &parse::LangFunction,
@@ -663,6 +1844,10 @@ fn evaluate_function_at_runtime(
 			Err(poisoned) => poisoned.into_inner().clone(),
 		}
 	};
+	let original_lists = match LIST_VARIABLES.lock() {
+		Ok(lists) => lists.clone(),
+		Err(poisoned) => poisoned.into_inner().clone(),
+	};
 
 	// Set up parameter bindings
 	{
@@ -699,6 +1884,10 @@ fn evaluate_function_at_runtime(
 			}
 		}
 	}
+	match LIST_VARIABLES.lock() {
+		Ok(mut lists) => *lists = original_lists,
+		Err(poisoned) => *poisoned.into_inner() = original_lists,
+	}
 
 	match result {
 		Some(value) => Ok(value),
@@ -714,7 +1903,8 @@ fn eval_block_with_function_preprocessing(block: &parse::LangBlock) -> Option<f6
 	for item in &block.items {
 		match item {
 			parse::LangBlockItem::Line(line) => {
-				let result = eval_line(line); // eval_line already does function call preprocessing
+				// eval_line_value already does function call preprocessing
+				let result = eval_line_value(line);
 				if result.is_some() {
 					last_result = result;
 				}
@@ -722,7 +1912,7 @@ fn eval_block_with_function_preprocessing(block: &parse::LangBlock) -> Option<f6
 			parse::LangBlockItem::Block(nested_block) => {
 				let result = eval_block_with_function_preprocessing(nested_block);
 				if result.is_some() {
-					last_result = result;
+					last_result = result.map(Value::Number);
 				}
 			}
 			parse::LangBlockItem::Function(_) => {
@@ -731,23 +1921,45 @@ fn eval_block_with_function_preprocessing(block: &parse::LangBlock) -> Option<f6
 			parse::LangBlockItem::NamedFunction(_) => {
 				has_function_definition = true;
 			}
-			parse::LangBlockItem::FunctionCall(call) => match execute_function_call(call) {
+			parse::LangBlockItem::FunctionCall(call) => match execute_statement_call(call) {
 				Ok(value) => {
-					last_result = Some(value);
+					last_result = value;
 				}
 				Err(e) => {
-					println!("Error executing function call: {}", e);
+					report_error(&format!("Error executing function call: {}", e));
 					return None;
 				}
 			},
+			parse::LangBlockItem::List(list) => match evaluate_list(list) {
+				Ok(value) => last_result = Some(value),
+				Err(e) => report_error(&format!("Error: {}", e)),
+			},
+			parse::LangBlockItem::If(if_item) => {
+				let result = eval_if(if_item, eval_block_with_function_preprocessing);
+				if result.is_some() {
+					last_result = result.map(Value::Number);
+				}
+			}
+			parse::LangBlockItem::Match(match_item) => {
+				let result = eval_match(match_item, eval_block_with_function_preprocessing);
+				if result.is_some() {
+					last_result = result.map(Value::Number);
+				}
+			}
 		}
 	}
 
 	// If there's a function definition in the block, return None
 	if has_function_definition {
-		None
-	} else {
-		last_result
+		return None;
+	}
+	// Lists and strings can be worked with along the way, but the result has to be a number
+	match last_result?.as_number() {
+		Ok(value) => Some(value),
+		Err(e) => {
+			report_error(&format!("Error: {}", e));
+			None
+		}
 	}
 }
 
@@ -758,9 +1970,13 @@ fn compile_and_store_named_function(
 	// Convert to LangFunction for storage
 	let function = parse::LangFunction {
 		parameters: named_function.parameters.clone(),
+		parameter_types: named_function.parameter_types.clone(),
 		body: named_function.body.clone(),
 	};
 
+	// Drop any code compiled for an earlier definition, in case this one can't be compiled
+	COMPILED_FUNCTIONS.with(|compiled| compiled.borrow_mut().remove(&named_function.name));
+
 	// Check if this function contains calls to other functions
 	if function_contains_user_function_calls(&function) {
 		// Store the function for runtime evaluation, skip LLVM compilation
@@ -776,12 +1992,8 @@ fn compile_and_store_named_function(
 		return Ok(());
 	}
 
-	// Try LLVM compilation for simple functions
-	let context = Context::create();
-	let mut codegen = LLVMCodeGen::new(&context)?;
-
-	// Try to compile the named function
-	match codegen.compile_named_function(named_function) {
+	// Try LLVM compilation for simple functions, keeping the code for its calls
+	match compiled_function(&named_function.name, &function) {
 		Ok(_) => {
 			// Successfully compiled with LLVM, store the function
 			match FUNCTIONS.lock() {
@@ -816,40 +2028,303 @@ fn compile_and_store_named_function(
 	Ok(())
 }
 
-/// Execute a function call using LLVM
-fn execute_function_call(call: &parse::LangFunctionCall) -> Result<f64, Box<dyn Error>> {
-	// Check if function exists in our store
-	let function_opt = match FUNCTIONS.lock() {
-		Ok(functions) => functions.get(&call.name).cloned(),
-		Err(poisoned) => {
-			let functions = poisoned.into_inner();
-			functions.get(&call.name).cloned()
-		}
+/// How calls to a user-defined function are executed
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionStatus {
+	/// JIT-compiled to native code with LLVM
+	Compiled,
+	/// Evaluated by the interpreter, e.g. because it calls other functions
+	Runtime,
+}
+
+/// List every user-defined function, sorted by name, with how calls to it are executed
+fn function_status() -> Vec<(String, FunctionStatus)> {
+	let mut functions: Vec<(String, parse::LangFunction)> = match FUNCTIONS.lock() {
+		Ok(functions) => functions.clone().into_iter().collect(),
+		Err(poisoned) => poisoned.into_inner().clone().into_iter().collect(),
+	};
+	functions.sort_by(|a, b| a.0.cmp(&b.0));
+	let dependencies = match FUNCTION_DEPENDENCIES.lock() {
+		Ok(dependencies) => dependencies.clone(),
+		Err(poisoned) => poisoned.into_inner().clone(),
 	};
 
-	if let Some(function) = function_opt {
-		// Evaluate argument expressions to get actual values
-		let mut arg_values = Vec::new();
-		for arg_tokens in &call.arguments {
-			// First preprocess function calls in arguments
-			let function_processed = match preprocess_tokens_for_function_calls(arg_tokens) {
-				Ok(tokens) => tokens,
-				Err(e) => {
-					return Err(
-						format!("Error preprocessing function calls in argument: {}", e).into(),
-					);
-				}
+	functions
+		.into_iter()
+		.map(|(name, function)| {
+			// Same decision execute_function_call makes: calls to other functions, built-ins
+			// included, mean runtime evaluation, as does anything codegen couldn't handle
+			// when the function was defined and compiled
+			let calls_functions = dependencies.get(&name).is_some_and(|used| {
+				used.iter()
+					.any(|used| is_user_defined_function_global(used))
+			});
+			let compiled = COMPILED_FUNCTIONS.with(|compiled| {
+				compiled
+					.borrow()
+					.get(&name)
+					.is_some_and(|compiled| compiled.source.0 == function)
+			});
+			let status = if compiled && !calls_functions {
+				FunctionStatus::Compiled
+			} else {
+				FunctionStatus::Runtime
 			};
-			let unary_processed = preprocess_unary_minus(&function_processed);
-			let postfix = infix_to_postfix(&unary_processed);
-			match execute_postfix_tokens(&postfix)? {
-				Some(value) => arg_values.push(value),
-				None => return Err("Argument expression evaluation failed".into()),
+			(name, status)
+		})
+		.collect()
+}
+
+/// Every user-defined function, sorted by name, with the user-defined functions it calls or
+/// passes by name, also sorted. Anonymous functions are left out.
+fn call_graph() -> Vec<(String, Vec<String>)> {
+	let dependencies = match FUNCTION_DEPENDENCIES.lock() {
+		Ok(dependencies) => dependencies.clone(),
+		Err(poisoned) => poisoned.into_inner().clone(),
+	};
+	let is_anonymous = |name: &str| {
+		function_ast(name).is_some_and(|function| anonymous_function_name(&function) == name)
+	};
+
+	let mut graph: Vec<(String, Vec<String>)> = dependencies
+		.iter()
+		.filter(|(name, _)| !is_anonymous(name))
+		.map(|(name, used)| {
+			let callees = used
+				.iter()
+				.filter(|callee| dependencies.contains_key(*callee) && !is_anonymous(callee))
+				.cloned()
+				.collect();
+			(name.clone(), callees)
+		})
+		.collect();
+	graph.sort();
+	graph
+}
+
+/// Groups of two or more functions in a call graph that call each other in a cycle, each
+/// group and the groups themselves sorted by name
+fn recursion_cycles(graph: &[(String, Vec<String>)]) -> Vec<Vec<String>> {
+	let callees: HashMap<&str, &Vec<String>> = graph
+		.iter()
+		.map(|(name, callees)| (name.as_str(), callees))
+		.collect();
+	let reachable_from = |start: &str| {
+		let mut reached: Vec<&str> = Vec::new();
+		let mut pending = vec![start];
+		while let Some(name) = pending.pop() {
+			for callee in callees
+				.get(name)
+				.into_iter()
+				.flat_map(|callees| callees.iter())
+			{
+				if !reached.contains(&callee.as_str()) {
+					reached.push(callee);
+					pending.push(callee);
+				}
 			}
 		}
-
-		// Check argument count matches function parameters
-		if arg_values.len() != function.parameters.len() {
+		reached
+	};
+	let reachable: HashMap<&str, Vec<&str>> = graph
+		.iter()
+		.map(|(name, _)| (name.as_str(), reachable_from(name)))
+		.collect();
+
+	// Functions are in a cycle together when each can reach the other
+	let mut cycles: Vec<Vec<String>> = Vec::new();
+	for (name, _) in graph {
+		if cycles.iter().any(|cycle| cycle.contains(name)) {
+			continue;
+		}
+		let cycle: Vec<String> = graph
+			.iter()
+			.map(|(other, _)| other)
+			.filter(|other| {
+				reachable[name.as_str()].contains(&other.as_str())
+					&& reachable[other.as_str()].contains(&name.as_str())
+			})
+			.cloned()
+			.collect();
+		if cycle.len() > 1 {
+			cycles.push(cycle);
+		}
+	}
+	cycles
+}
+
+/// Format a call graph for `:graph`, one `caller -> callees` line per function followed by
+/// any cycles of mutual recursion
+fn format_call_graph(graph: &[(String, Vec<String>)]) -> String {
+	let mut lines: Vec<String> = graph
+		.iter()
+		.map(|(name, callees)| {
+			if callees.is_empty() {
+				name.clone()
+			} else {
+				format!("{} -> {}", name, callees.join(", "))
+			}
+		})
+		.collect();
+	for cycle in recursion_cycles(graph) {
+		lines.push(format!("Mutual recursion: {}", cycle.join(", ")));
+	}
+	lines.join("\n")
+}
+
+/// Get a copy of a user-defined function's syntax tree
+fn function_ast(name: &str) -> Option<parse::LangFunction> {
+	match FUNCTIONS.lock() {
+		Ok(functions) => functions.get(name).cloned(),
+		Err(poisoned) => {
+			let functions = poisoned.into_inner();
+			functions.get(name).cloned()
+		}
+	}
+}
+
+/// Reconstruct the source of a user-defined function as a `fn` definition
+fn function_source(name: &str) -> Option<String> {
+	let function = function_ast(name)?;
+	Some(format!(
+		"fn {}({}) {{\n{}}}",
+		name,
+		parse::parameters_to_string(&function.parameters, &function.parameter_types),
+		parse::block_to_source(&function.body, 1)
+	))
+}
+
+/// Store a guarded clause of a function. A clause with the same parameters and guard as an
+/// earlier one replaces it.
+fn add_guarded_clause(clause: &parse::LangNamedFunction) {
+	let mut all_clauses = match FUNCTION_CLAUSES.lock() {
+		Ok(all_clauses) => all_clauses,
+		Err(poisoned) => poisoned.into_inner(),
+	};
+	let clauses = all_clauses.entry(clause.name.clone()).or_default();
+	match clauses
+		.iter_mut()
+		.find(|existing| existing.parameters == clause.parameters && existing.guard == clause.guard)
+	{
+		Some(existing) => *existing = clause.clone(),
+		None => clauses.push(clause.clone()),
+	}
+}
+
+/// Work out and store the names a function uses, after its definition or one of its guarded
+/// clauses changes
+fn record_dependencies(name: &str) {
+	let mut clauses = guarded_clauses(name);
+	if let Some(function) = function_ast(name) {
+		clauses.push(parse::LangNamedFunction {
+			name: name.to_string(),
+			parameters: function.parameters,
+			parameter_types: function.parameter_types,
+			body: function.body,
+			guard: None,
+		});
+	}
+
+	let mut dependencies = Vec::new();
+	for clause in &clauses {
+		let mut used = Vec::new();
+		let mut local = clause.parameters.clone();
+		if let Some(guard) = &clause.guard {
+			collect_line_symbols(guard, &mut used, &mut local);
+		}
+		collect_block_symbols(&clause.body, &mut used, &mut local);
+		dependencies.extend(used.into_iter().filter(|name| !local.contains(name)));
+	}
+	dependencies.sort();
+	dependencies.dedup();
+
+	match FUNCTION_DEPENDENCIES.lock() {
+		Ok(mut all_dependencies) => all_dependencies.insert(name.to_string(), dependencies),
+		Err(poisoned) => poisoned.into_inner().insert(name.to_string(), dependencies),
+	};
+}
+
+/// Get the guarded clauses of a function in definition order
+fn guarded_clauses(name: &str) -> Vec<parse::LangNamedFunction> {
+	match FUNCTION_CLAUSES.lock() {
+		Ok(all_clauses) => all_clauses.get(name).cloned().unwrap_or_default(),
+		Err(poisoned) => {
+			let all_clauses = poisoned.into_inner();
+			all_clauses.get(name).cloned().unwrap_or_default()
+		}
+	}
+}
+
+/// Find the first guarded clause taking these arguments whose guard holds for them
+fn select_clause(
+	clauses: &[parse::LangNamedFunction],
+	arg_values: &[f64],
+) -> Result<Option<parse::LangFunction>, Box<dyn Error>> {
+	for clause in clauses {
+		let Some(guard) = &clause.guard else {
+			continue;
+		};
+		if clause.parameters.len() != arg_values.len() {
+			continue;
+		}
+
+		// Evaluate the guard like a function body with the arguments bound
+		let guard_function = parse::LangFunction {
+			parameters: clause.parameters.clone(),
+			parameter_types: clause.parameter_types.clone(),
+			body: LangBlock {
+				items: vec![parse::LangBlockItem::Line(LangLine {
+					tokens: guard.clone(),
+				})],
+			},
+		};
+		if is_truthy(evaluate_function_at_runtime(&guard_function, arg_values)?) {
+			return Ok(Some(parse::LangFunction {
+				parameters: clause.parameters.clone(),
+				parameter_types: clause.parameter_types.clone(),
+				body: clause.body.clone(),
+			}));
+		}
+	}
+	Ok(None)
+}
+
+/// Execute a function call using LLVM
+fn execute_function_call(call: &parse::LangFunctionCall) -> Result<f64, Box<dyn Error>> {
+	count_profiled(|counts| counts.calls += 1);
+
+	// Check if function exists in our store
+	let function_opt = match FUNCTIONS.lock() {
+		Ok(functions) => functions.get(&call.name).cloned(),
+		Err(poisoned) => {
+			let functions = poisoned.into_inner();
+			functions.get(&call.name).cloned()
+		}
+	};
+
+	// The first guarded clause whose guard holds runs instead of the unguarded definition
+	let clauses = guarded_clauses(&call.name);
+	let mut evaluated_args = None;
+	if !clauses.is_empty() {
+		let arg_values = evaluate_arguments(call)?;
+		if let Some(clause) = select_clause(&clauses, &arg_values)? {
+			return evaluate_function_at_runtime(&clause, &arg_values);
+		}
+		if function_opt.is_none() {
+			return Err(format!("No clause of '{}' matches the arguments", call.name).into());
+		}
+		evaluated_args = Some(arg_values);
+	}
+
+	if let Some(function) = function_opt {
+		let arg_values = match evaluated_args {
+			Some(arg_values) => arg_values,
+			None if settings().strict_types => evaluate_typed_arguments(call, &function)?,
+			None => evaluate_arguments(call)?,
+		};
+
+		// Check argument count matches function parameters
+		if arg_values.len() != function.parameters.len() {
 			return Err(format!(
 				"Function '{}' expects {} arguments, got {}",
 				call.name,
@@ -865,19 +2340,17 @@ fn execute_function_call(call: &parse::LangFunctionCall) -> Result<f64, Box<dyn
 			return evaluate_function_at_runtime(&function, &arg_values);
 		}
 
-		// Create a new LLVM context and compile the function for execution
-		let context = Context::create();
-		let mut codegen = LLVMCodeGen::new(&context)?;
-
-		// Try to compile the function - if it fails due to function calls, fall back to runtime
-		let _llvm_function = match codegen.compile_function(&call.name, &function) {
-			Ok(f) => f,
+		// Compile the function for execution in this thread's LLVM context, or reuse the
+		// code from an earlier call - if it fails due to function calls, fall back to runtime
+		let compiled = match compiled_function(&call.name, &function) {
+			Ok(compiled) => compiled,
 			Err(e) if e.to_string().contains("use runtime evaluation") => {
 				// Fall back to runtime evaluation
 				return evaluate_function_at_runtime(&function, &arg_values);
 			}
 			Err(e) => return Err(e),
 		};
+		let codegen = &compiled.codegen;
 
 		// Get JIT function pointer and execute based on argument count
 		unsafe {
@@ -887,44 +2360,28 @@ fn execute_function_call(call: &parse::LangFunctionCall) -> Result<f64, Box<dyn
 					let jit_fn: inkwell::execution_engine::JitFunction<Func0> =
 						codegen.execution_engine.get_function(&call.name)?;
 					let result = jit_fn.call();
-					if result.is_nan() {
-						Err("Division by zero".into())
-					} else {
-						Ok(result)
-					}
+					jit_result(result)
 				}
 				1 => {
 					type Func1 = unsafe extern "C" fn(f64) -> f64;
 					let jit_fn: inkwell::execution_engine::JitFunction<Func1> =
 						codegen.execution_engine.get_function(&call.name)?;
 					let result = jit_fn.call(arg_values[0]);
-					if result.is_nan() {
-						Err("Division by zero".into())
-					} else {
-						Ok(result)
-					}
+					jit_result(result)
 				}
 				2 => {
 					type Func2 = unsafe extern "C" fn(f64, f64) -> f64;
 					let jit_fn: inkwell::execution_engine::JitFunction<Func2> =
 						codegen.execution_engine.get_function(&call.name)?;
 					let result = jit_fn.call(arg_values[0], arg_values[1]);
-					if result.is_nan() {
-						Err("Division by zero".into())
-					} else {
-						Ok(result)
-					}
+					jit_result(result)
 				}
 				3 => {
 					type Func3 = unsafe extern "C" fn(f64, f64, f64) -> f64;
 					let jit_fn: inkwell::execution_engine::JitFunction<Func3> =
 						codegen.execution_engine.get_function(&call.name)?;
 					let result = jit_fn.call(arg_values[0], arg_values[1], arg_values[2]);
-					if result.is_nan() {
-						Err("Division by zero".into())
-					} else {
-						Ok(result)
-					}
+					jit_result(result)
 				}
 				4 => {
 					type Func4 = unsafe extern "C" fn(f64, f64, f64, f64) -> f64;
@@ -932,11 +2389,7 @@ fn execute_function_call(call: &parse::LangFunctionCall) -> Result<f64, Box<dyn
 						codegen.execution_engine.get_function(&call.name)?;
 					let result =
 						jit_fn.call(arg_values[0], arg_values[1], arg_values[2], arg_values[3]);
-					if result.is_nan() {
-						Err("Division by zero".into())
-					} else {
-						Ok(result)
-					}
+					jit_result(result)
 				}
 				5 => {
 					type Func5 = unsafe extern "C" fn(f64, f64, f64, f64, f64) -> f64;
@@ -949,11 +2402,7 @@ fn execute_function_call(call: &parse::LangFunctionCall) -> Result<f64, Box<dyn
 						arg_values[3],
 						arg_values[4],
 					);
-					if result.is_nan() {
-						Err("Division by zero".into())
-					} else {
-						Ok(result)
-					}
+					jit_result(result)
 				}
 				_ => Err(format!(
 					"Functions with {} parameters not supported yet (max 5)",
@@ -962,11 +2411,236 @@ fn execute_function_call(call: &parse::LangFunctionCall) -> Result<f64, Box<dyn
 				.into()),
 			}
 		}
+	} else if let Some(builtin) = find_builtin(&call.name) {
+		let arg_values = evaluate_arguments(call)?;
+		if arg_values.len() != builtin.arity {
+			return Err(format!(
+				"Function '{}' expects {} arguments, got {}",
+				call.name,
+				builtin.arity,
+				arg_values.len()
+			)
+			.into());
+		}
+		Ok((builtin.function)(&arg_values))
+	} else if let Some(builtin) = find_higher_order_builtin(&call.name) {
+		Ok(call_higher_order_builtin(builtin, call)?.as_number()?)
+	} else if let Some(builtin) = find_value_builtin(&call.name) {
+		match call_value_builtin(builtin, call)? {
+			Some(value) => Ok(value.as_number()?),
+			None => Err(format!("'{}' has no value to use in an expression", call.name).into()),
+		}
+	} else {
+		Err(format!("Function '{}' not found", call.name).into())
+	}
+}
+
+/// Call a function by name with already evaluated arguments
+fn call_function_by_name(name: &str, arg_values: &[f64]) -> Result<f64, Box<dyn Error>> {
+	let call = parse::LangFunctionCall {
+		name: name.to_string(),
+		arguments: arg_values
+			.iter()
+			.map(|&value| {
+				vec![Token::Number(lex::LangNumber::RealNumber(
+					lex::LangRealNumber { value },
+				))]
+			})
+			.collect(),
+	};
+	execute_function_call(&call)
+}
+
+/// Call a built-in like `deriv` whose first argument names the function it works on
+fn call_higher_order_builtin(
+	builtin: &builtins::HigherOrderBuiltin,
+	call: &parse::LangFunctionCall,
+) -> Result<Value, Box<dyn Error>> {
+	if call.arguments.len() != builtin.arity {
+		return Err(format!(
+			"Function '{}' expects {} arguments, got {}",
+			call.name,
+			builtin.arity,
+			call.arguments.len()
+		)
+		.into());
+	}
+	let function_name = match call.arguments[0].as_slice() {
+		[Token::Symbol(symbol)] if is_user_defined_function_global(&symbol.value) => {
+			symbol.value.clone()
+		}
+		_ => {
+			return Err(format!(
+				"'{}' expects the name of a function as its first argument",
+				call.name
+			)
+			.into());
+		}
+	};
+
+	let mut arg_values = Vec::new();
+	for arg_tokens in &call.arguments[1..] {
+		match evaluate_value(arg_tokens)? {
+			Some(value) => arg_values.push(value),
+			None => return Err("Argument expression evaluation failed".into()),
+		}
+	}
+
+	let mut function =
+		|args: &[f64]| call_function_by_name(&function_name, args).map_err(|e| e.to_string());
+	Ok((builtin.function)(&mut function, &arg_values)?)
+}
+
+/// Evaluate an expression `times` times for `bench`, returning the average time one
+/// evaluation took in nanoseconds. The expression is lexed once, outside the timing.
+fn time_evaluations(expression: &str, times: usize) -> Result<f64, String> {
+	let tokens = lex(expression);
+	let start = std::time::Instant::now();
+	for _ in 0..times {
+		evaluate_value(&tokens).map_err(|e| e.to_string())?;
+	}
+	Ok(start.elapsed().as_nanos() as f64 / times as f64)
+}
+
+/// Call a built-in that accepts strings, evaluating its arguments as values of any kind
+fn call_value_builtin(
+	builtin: &builtins::ValueBuiltin,
+	call: &parse::LangFunctionCall,
+) -> Result<Option<Value>, Box<dyn Error>> {
+	let mut arg_values = Vec::new();
+	for arg_tokens in &call.arguments {
+		match evaluate_value(arg_tokens)? {
+			Some(value) => arg_values.push(value),
+			None => return Err("Argument expression evaluation failed".into()),
+		}
+	}
+	if arg_values.len() != builtin.arity {
+		return Err(format!(
+			"Function '{}' expects {} arguments, got {}",
+			call.name,
+			builtin.arity,
+			arg_values.len()
+		)
+		.into());
+	}
+	Ok((builtin.function)(&arg_values)?)
+}
+
+/// Run a call on its own line, where calls made only for their effect, like `print`,
+/// are allowed. Returns the call's value, if it has one.
+fn execute_statement_call(call: &parse::LangFunctionCall) -> Result<Option<Value>, Box<dyn Error>> {
+	if !calls_value_builtin(&call.name) {
+		return execute_function_call(call).map(|value| Some(Value::Number(value)));
+	}
+	execute_value_builtin_call(call)
+}
+
+/// Whether a call by this name runs a built-in that may return values of any kind rather
+/// than only numbers. User functions and guarded clauses of the same name take precedence.
+fn calls_value_builtin(name: &str) -> bool {
+	let is_user_function = match FUNCTIONS.lock() {
+		Ok(functions) => functions.contains_key(name),
+		Err(poisoned) => poisoned.into_inner().contains_key(name),
+	};
+	if is_user_function || !guarded_clauses(name).is_empty() {
+		return false;
+	}
+	find_value_builtin(name).is_some() || find_higher_order_builtin(name).is_some()
+}
+
+/// Run a call to a built-in that may return values of any kind
+fn execute_value_builtin_call(
+	call: &parse::LangFunctionCall,
+) -> Result<Option<Value>, Box<dyn Error>> {
+	count_profiled(|counts| counts.calls += 1);
+	if let Some(builtin) = find_higher_order_builtin(&call.name) {
+		call_higher_order_builtin(builtin, call).map(Some)
+	} else if let Some(builtin) = find_value_builtin(&call.name) {
+		call_value_builtin(builtin, call)
 	} else {
 		Err(format!("Function '{}' not found", call.name).into())
 	}
 }
 
+/// Turn a value back into tokens so it can take the place of the call that produced it.
+/// Lists become list literals.
+fn value_tokens(value: Value) -> Result<Vec<Token>, Box<dyn Error>> {
+	let operator = |value: &str| {
+		Token::Operator(lex::LangOperator {
+			value: value.to_string(),
+		})
+	};
+	Ok(match value {
+		Value::Integer(value) => vec![Token::Number(lex::LangNumber::Integer(lex::LangInteger {
+			value,
+		}))],
+		Value::String(value) => vec![Token::String(lex::LangString { value })],
+		Value::List(items) => {
+			let mut tokens = vec![operator("[")];
+			for (i, item) in items.into_iter().enumerate() {
+				if i > 0 {
+					tokens.push(operator(","));
+				}
+				tokens.extend(value_tokens(item)?);
+			}
+			tokens.push(operator("]"));
+			tokens
+		}
+		number => vec![Token::Number(lex::LangNumber::RealNumber(
+			lex::LangRealNumber {
+				value: number.as_number()?,
+			},
+		))],
+	})
+}
+
+/// Evaluate the argument expressions of a function call to get actual values
+fn evaluate_arguments(call: &parse::LangFunctionCall) -> Result<Vec<f64>, Box<dyn Error>> {
+	let mut arg_values = Vec::new();
+	for arg_tokens in &call.arguments {
+		match evaluate_tokens(arg_tokens)? {
+			Some(value) => arg_values.push(value),
+			None => return Err("Argument expression evaluation failed".into()),
+		}
+	}
+	Ok(arg_values)
+}
+
+/// Evaluate the arguments of a call like `evaluate_arguments`, checking each one against the
+/// type its parameter is annotated with: `int` takes integers and `real` any number
+fn evaluate_typed_arguments(
+	call: &parse::LangFunctionCall,
+	function: &parse::LangFunction,
+) -> Result<Vec<f64>, Box<dyn Error>> {
+	let mut arg_values = Vec::new();
+	for (i, arg_tokens) in call.arguments.iter().enumerate() {
+		let value = evaluate_value(arg_tokens)?.ok_or("Argument expression evaluation failed")?;
+		let parameter_type = function.parameter_types.get(i).cloned().flatten();
+		let matches = match parameter_type.as_deref() {
+			// Variables and parameters hold whole numbers as reals, so those count too
+			Some("int") => match value {
+				Value::Integer(_) => true,
+				Value::Number(n) => n.fract() == 0.0,
+				_ => false,
+			},
+			Some(_) => value.as_number().is_ok(),
+			None => true,
+		};
+		if !matches {
+			return Err(format!(
+				"Parameter '{}' of '{}' expects {}, got {}",
+				function.parameters[i],
+				call.name,
+				parameter_type.unwrap_or_default(),
+				value
+			)
+			.into());
+		}
+		arg_values.push(value.as_number()?);
+	}
+	Ok(arg_values)
+}
+
 /// Preprocess tokens to handle function calls in expressions
 fn preprocess_tokens_for_function_calls(tokens: &[Token]) -> Result<Vec<Token>, Box<dyn Error>> {
 	let mut result = Vec::new();
@@ -980,6 +2654,7 @@ fn preprocess_tokens_for_function_calls(tokens: &[Token]) -> Result<Vec<Token>,
 					// Found a function call pattern, parse arguments
 					let mut j = i + 2; // Start after the opening parenthesis
 					let mut paren_count = 1;
+					let mut bracket_depth = 0;
 					let mut arg_tokens = Vec::new();
 					let mut current_arg = Vec::new();
 
@@ -1000,7 +2675,14 @@ fn preprocess_tokens_for_function_calls(tokens: &[Token]) -> Result<Vec<Token>,
 									current_arg.push(tokens[j].clone());
 								}
 							}
-							Token::Operator(op) if op.value == "," && paren_count == 1 => {
+							Token::Operator(op) if op.value == "[" || op.value == "]" => {
+								// Commas inside list literals don't separate arguments
+								bracket_depth += if op.value == "[" { 1 } else { -1 };
+								current_arg.push(tokens[j].clone());
+							}
+							Token::Operator(op)
+								if op.value == "," && paren_count == 1 && bracket_depth == 0 =>
+							{
 								// Argument separator at top level
 								if !current_arg.is_empty() {
 									arg_tokens.push(current_arg.clone());
@@ -1014,27 +2696,30 @@ fn preprocess_tokens_for_function_calls(tokens: &[Token]) -> Result<Vec<Token>,
 						j += 1;
 					}
 
-					// Recursively preprocess arguments for nested function calls
-					let mut processed_arg_tokens = Vec::new();
-					for arg in arg_tokens {
-						match preprocess_tokens_for_function_calls(&arg) {
-							Ok(processed_arg) => processed_arg_tokens.push(processed_arg),
-							Err(e) => {
+					// Execute the function call and replace with the result.
+					// Nested calls in the arguments are evaluated along with each argument.
+					let function_call = parse::LangFunctionCall {
+						name: func_name.value.clone(),
+						arguments: arg_tokens,
+					};
+
+					// Built-ins that accept strings and lists may also return them
+					if calls_value_builtin(&function_call.name) {
+						match execute_value_builtin_call(&function_call) {
+							Ok(Some(value)) => result.extend(value_tokens(value)?),
+							Ok(None) => {
 								return Err(format!(
-									"Error preprocessing nested function call: {}",
-									e
+									"Function call error: '{}' has no value to use in an expression",
+									function_call.name
 								)
 								.into());
 							}
+							Err(e) => return Err(format!("Function call error: {}", e).into()),
 						}
+						i = j;
+						continue;
 					}
 
-					// Execute the function call and replace with the result
-					let function_call = parse::LangFunctionCall {
-						name: func_name.value.clone(),
-						arguments: processed_arg_tokens,
-					};
-
 					match execute_function_call(&function_call) {
 						Ok(result_value) => {
 							// Replace the function call with its result as a number token
@@ -1063,26 +2748,40 @@ fn preprocess_tokens_for_function_calls(tokens: &[Token]) -> Result<Vec<Token>,
 	Ok(result)
 }
 
-fn execute_postfix_tokens(tokens: &[Token]) -> Result<Option<f64>, Box<dyn Error>> {
-	// For assignment operations, we need to handle them at runtime rather than compile time
-	// So we'll evaluate the postfix expression directly without LLVM for now
-	let mut value_stack: Vec<f64> = Vec::new();
-	let mut variable_stack: Vec<String> = Vec::new(); // For tracking variable names in assignment
+/// Evaluate a postfix expression, keeping the kind of value it produces
+fn execute_postfix_value(tokens: &[Token]) -> Result<Option<Value>, Box<dyn Error>> {
+	execute_postfix_traced(tokens, None)
+}
+
+/// Evaluate a postfix expression, recording a line in `trace` for each operation performed
+fn execute_postfix_traced(
+	tokens: &[Token],
+	mut trace: Option<&mut Vec<String>>,
+) -> Result<Option<Value>, Box<dyn Error>> {
+	// Assignments are handled before postfix conversion, so this only evaluates values
+	let mut value_stack: Vec<Value> = Vec::new();
+	let (exact, overflow_errors, profile, integer_division) = {
+		let settings = settings();
+		(
+			settings.exact,
+			settings.overflow_errors,
+			settings.profile,
+			settings.integer_division,
+		)
+	};
 
 	for token in tokens {
 		match token {
 			Token::Number(lex::LangNumber::Integer(int_val)) => {
-				value_stack.push(int_val.value as f64);
-				variable_stack.push(String::new()); // Empty string for non-variables
+				value_stack.push(Value::Integer(int_val.value));
 			}
 			Token::Number(lex::LangNumber::RealNumber(real_val)) => {
-				value_stack.push(real_val.value);
-				variable_stack.push(String::new()); // Empty string for non-variables
+				value_stack.push(Value::Number(real_val.value));
+			}
+			Token::String(string_val) => {
+				value_stack.push(Value::String(string_val.value.clone()));
 			}
 			Token::Symbol(symbol) => {
-				// Always track the symbol name for potential assignment
-				variable_stack.push(symbol.value.clone());
-
 				// Check if this symbol is a variable, if so push its value
 				let value = match VARIABLES.lock() {
 					Ok(variables) => variables.get(&symbol.value).copied(),
@@ -1092,136 +2791,248 @@ fn execute_postfix_tokens(tokens: &[Token]) -> Result<Option<f64>, Box<dyn Error
 					}
 				};
 
-				if let Some(value) = value {
-					value_stack.push(value);
-				} else {
-					// For new variables, push 0 as placeholder
-					value_stack.push(0.0);
-				}
+				let value = match value {
+					Some(value) => Value::Number(value),
+					None => match list_variable(&symbol.value) {
+						Some(items) => Value::List(items),
+						None => Value::Number(undefined_variable(&symbol.value)?),
+					},
+				};
+				value_stack.push(value);
 			}
-			Token::Operator(op) => match op.value.as_str() {
-				"=" => {
-					if value_stack.len() >= 2 && variable_stack.len() >= 2 {
-						let value = value_stack.pop().unwrap();
-						let _var_placeholder = value_stack.pop().unwrap(); // Remove placeholder
-
-						// Pop variable names (value operand first, then variable name)
-						variable_stack.pop(); // Pop the variable name for the value
-						let var_name = variable_stack.pop().unwrap(); // Pop the variable name for assignment target
+			Token::Operator(op) if is_binary_operator(&op.value) && value_stack.len() < 2 => {
+				return Err(missing_operand_error(&op.value));
+			}
+			Token::Operator(op) => {
+				// Keep the stack before the operation to describe it in the trace
+				let before = trace.is_some().then(|| value_stack.clone());
+				let arithmetic = op.value == UNARY_MINUS
+					|| (is_binary_operator(&op.value) && !is_comparison_operator(&op.value));
+				if profile && arithmetic {
+					profile_counts().arithmetic += 1;
+				}
 
-						if !var_name.is_empty() {
-							// Assign value to variable
-							match VARIABLES.lock() {
-								Ok(mut variables) => {
-									variables.insert(var_name.clone(), value);
-								}
-								Err(poisoned) => {
-									let mut variables = poisoned.into_inner();
-									variables.insert(var_name.clone(), value);
-								}
-							}
-							// Push the assigned value back for potential chaining
-							value_stack.push(value);
-							variable_stack.push(String::new()); // Push placeholder for result
-						} else {
-							return Err("Assignment requires a variable name".into());
-						}
-					} else {
-						return Err("Assignment requires two operands".into());
+				match op.value.as_str() {
+					"=" => {
+						// Valid assignments start with the variable name and are handled before
+						// postfix conversion, so any `=` left here has no name to assign to
+						return Err("Assignment requires a variable name".into());
 					}
-				}
-				"+" => {
-					if value_stack.len() >= 2 {
+					"+" => {
+						// What `+` means depends on the kinds of its operands
 						let b = value_stack.pop().unwrap();
 						let a = value_stack.pop().unwrap();
-						let result = a + b;
-						value_stack.push(result);
-						// Clean up variable_stack for the two operands consumed and push placeholder for result
-						if variable_stack.len() >= 2 {
-							variable_stack.pop();
-							variable_stack.pop();
-							variable_stack.push(String::new()); // Placeholder for result
+						if overflow_errors {
+							Value::check_integer_overflow("+", &a, &b)?;
 						}
-					}
-				}
-				"-" => {
-					if value_stack.len() >= 2 {
-						let b = value_stack.pop().unwrap();
-						let a = value_stack.pop().unwrap();
-						let result = a - b;
-						value_stack.push(result);
-						// Clean up variable_stack for the two operands consumed and push placeholder for result
-						if variable_stack.len() >= 2 {
-							variable_stack.pop();
-							variable_stack.pop();
-							variable_stack.push(String::new()); // Placeholder for result
+						if exact {
+							value_stack.push(Value::exact_arithmetic("+", &a, &b)?);
+						} else {
+							value_stack.push(Value::add(&a, &b)?);
 						}
 					}
-				}
-				"*" => {
-					if value_stack.len() >= 2 {
+					"-" | "*" | "/" | "%" | "^" | "div" => {
 						let b = value_stack.pop().unwrap();
 						let a = value_stack.pop().unwrap();
-						let result = a * b;
-						value_stack.push(result);
-						// Clean up variable_stack for the two operands consumed and push placeholder for result
-						if variable_stack.len() >= 2 {
-							variable_stack.pop();
-							variable_stack.pop();
-							variable_stack.push(String::new()); // Placeholder for result
+						// With `:intdiv floor`, `/` on two integers works like `div`
+						let op_name = match (op.value.as_str(), &a, &b) {
+							("/", Value::Integer(_), Value::Integer(_))
+								if integer_division == IntegerDivision::Floor =>
+							{
+								"div"
+							}
+							(op_name, ..) => op_name,
+						};
+						if overflow_errors {
+							Value::check_integer_overflow(op_name, &a, &b)?;
 						}
-					}
-				}
-				"/" => {
-					if value_stack.len() >= 2 {
-						let b = value_stack.pop().unwrap();
-						let a = value_stack.pop().unwrap();
-						if b != 0.0 {
-							let result = a / b;
-							value_stack.push(result);
+						if exact {
+							value_stack.push(Value::exact_arithmetic(op_name, &a, &b)?);
 						} else {
-							return Err("Division by zero".into());
+							value_stack.push(Value::arithmetic(op_name, &a, &b)?);
+						}
+					}
+					UNARY_MINUS => match value_stack.pop() {
+						Some(value) => {
+							if overflow_errors {
+								Value::check_negation_overflow(&value)?;
+							}
+							value_stack.push(value.negate()?)
+						}
+						None => return Err(missing_operand_error("-")),
+					},
+					NOT => match value_stack.pop() {
+						Some(value) => {
+							let negated = !is_truthy(value.as_number()?);
+							value_stack.push(Value::Number(if negated { 1.0 } else { 0.0 }));
 						}
-						// Clean up variable_stack for the two operands consumed and push placeholder for result
-						if variable_stack.len() >= 2 {
-							variable_stack.pop();
-							variable_stack.pop();
-							variable_stack.push(String::new()); // Placeholder for result
+						None => return Err(missing_operand_error(NOT)),
+					},
+					LIST_LITERAL => {
+						let count = match value_stack.pop() {
+							Some(Value::Integer(count)) => count as usize,
+							_ => return Err("Syntax error: malformed list".into()),
+						};
+						if value_stack.len() < count {
+							return Err("Syntax error: malformed list".into());
 						}
+						let items = value_stack.split_off(value_stack.len() - count);
+						value_stack.push(Value::List(items));
+					}
+					INDEX => match (value_stack.pop(), value_stack.pop()) {
+						(Some(index), Some(list)) => value_stack.push(list.index(&index)?),
+						_ => return Err(missing_operand_error("[]")),
+					},
+					"[" => return Err("Syntax error: missing ']'".into()),
+					"]" => return Err("Syntax error: unexpected ']'".into()),
+					"==" | "!=" | "<" | ">" | "<=" | ">=" => {
+						if value_stack.len() >= 2 {
+							let b = value_stack.pop().unwrap();
+							let a = value_stack.pop().unwrap();
+							let result = compare_values(&op.value, &a, &b)?;
+							value_stack.push(Value::Number(if result { 1.0 } else { 0.0 }));
+						}
+					}
+					"," => {
+						// Commas should be handled in function call preprocessing,
+						// but if they reach here, just ignore them
+						continue;
+					}
+					"?" | ":" => {
+						// Complete conditionals are resolved before postfix evaluation
+						return Err(
+							"Syntax error: incomplete conditional, expected cond ? a : b".into(),
+						);
+					}
+					"=>" => {
+						// Valid lambdas are turned into functions by the parser, so any arrow left
+						// in an expression is misplaced
+						return Err(
+							"Syntax error: '=>' is only allowed in a lambda definition like (x) => { ... }"
+								.into(),
+						);
+					}
+					"." => {
+						// A dot only belongs inside a number; there is no member access yet
+						return Err("Syntax error: unexpected '.' outside a number".into());
+					}
+					_ => {
+						println!("Warning: Operator '{}' not supported yet", op.value);
 					}
 				}
-				"," => {
-					// Commas should be handled in function call preprocessing,
-					// but if they reach here, just ignore them
-					continue;
-				}
-				_ => {
-					println!("Warning: Operator '{}' not supported yet", op.value);
+
+				if let (Some(trace), Some(before)) = (trace.as_deref_mut(), before) {
+					trace.push(describe_reduction(&op.value, &before, &value_stack));
 				}
-			},
-			Token::String(_) => {
-				println!("Warning: Strings not supported in arithmetic evaluation");
 			}
 		}
 	}
 
-	// Return the final result if it's not an assignment
-	if let Some(result) = value_stack.last() {
-		if !tokens
-			.iter()
-			.any(|t| matches!(t, Token::Operator(op) if op.value == "="))
-		{
-			// println!("{}", result);
-			Ok(Some(*result))
-		} else {
-			Ok(Some(*result))
+	Ok(value_stack.pop())
+}
+
+/// Describe one operation of a postfix evaluation, given the stack before and after it,
+/// e.g. `3 * 4 = 12    stack: [2, 12]`
+fn describe_reduction(op: &str, before: &[Value], after: &[Value]) -> String {
+	let result = after
+		.last()
+		.map_or(String::new(), |value| value.to_string());
+	let operation = match (op, before) {
+		(UNARY_MINUS, [.., operand]) => format!("-({}) = {}", operand, result),
+		(NOT, [.., operand]) => format!("not {} = {}", operand, result),
+		(INDEX, [.., list, index]) => format!("{}[{}] = {}", list, index, result),
+		(_, [.., a, b]) => format!("{} {} {} = {}", a, op, b, result),
+		_ => op.to_string(),
+	};
+	let stack: Vec<String> = after.iter().map(|value| value.to_string()).collect();
+	format!("{:<20}stack: [{}]", operation, stack.join(", "))
+}
+
+/// Show how an expression is evaluated: its tokens, the postfix form produced by the
+/// Shunting Yard conversion, and each reduction of the evaluation stack
+fn explain(input: &str) -> Result<Vec<String>, Box<dyn Error>> {
+	let input_tokens = lex(input);
+	let mut lines = vec![format!(
+		"Infix:   {}",
+		parse::tokens_to_string(&input_tokens)
+	)];
+
+	// Assignments are explained as their value followed by the assignment itself
+	let mut targets = Vec::new();
+	let mut value_tokens = input_tokens.as_slice();
+	while let Some((name, rest)) = split_assignment(value_tokens) {
+		targets.push(name);
+		value_tokens = rest;
+	}
+	let mut tokens = value_tokens.to_vec();
+
+	// Conditionals, logical operators and function calls are evaluated before the postfix
+	// stage, so show their results in place
+	loop {
+		let resolved = match resolve_ternary(&tokens)? {
+			Some(resolved) => Some(resolved),
+			None => resolve_logical(&tokens)?,
+		};
+		match resolved {
+			Some(resolved) => tokens = resolved,
+			None => break,
 		}
-	} else {
-		Ok(None)
 	}
+	let processed_tokens = preprocess_tokens_for_function_calls(&tokens)?;
+	let postfix_tokens = infix_to_postfix(&preprocess_unary_minus(&processed_tokens));
+	lines.push(format!(
+		"Postfix: {}",
+		parse::tokens_to_string(&postfix_tokens)
+	));
+
+	let mut trace = Vec::new();
+	let result = execute_postfix_traced(&postfix_tokens, Some(&mut trace))?;
+	lines.extend(trace.into_iter().map(|step| format!("  {}", step)));
+	if let Some(result) = result {
+		// Chained assignments take effect right to left
+		for name in targets.iter().rev() {
+			assign_variable(name, &result)?;
+			lines.push(format!("  {} = {}", name, result));
+		}
+		lines.push(format!("Result:  {}", result));
+	}
+	Ok(lines)
+}
+
+/// Apply a comparison operator to two values, yielding whether it holds
+fn compare_values(op: &str, a: &Value, b: &Value) -> Result<bool, Box<dyn Error>> {
+	let ordering = a.compare(b)?;
+	Ok(match op {
+		"==" => ordering == Some(Ordering::Equal),
+		"!=" => ordering != Some(Ordering::Equal),
+		"<" => ordering == Some(Ordering::Less),
+		">" => ordering == Some(Ordering::Greater),
+		"<=" => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+		">=" => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+		_ => return Err(format!("Unknown comparison operator: {}", op).into()),
+	})
+}
+
+/// Whether a value counts as true in a condition: any non-zero number, but not NaN
+fn is_truthy(value: f64) -> bool {
+	value != 0.0 && !value.is_nan()
 }
 
-/// Preprocess tokens to handle unary minus by converting patterns like "- number" to "0 - number"
+/// Operator token standing for unary minus after preprocessing
+const UNARY_MINUS: &str = "neg";
+
+/// Operator token for logical negation, written `not`
+const NOT: &str = "not";
+
+/// Operator token that builds a list in postfix form, from the element count on top of the
+/// stack and the elements below it
+const LIST_LITERAL: &str = "list";
+
+/// Operator token that picks an item out of a list, written `a[i]`
+const INDEX: &str = "index";
+
+/// Preprocess tokens to handle unary minus by turning a minus that has no left operand
+/// into the `UNARY_MINUS` prefix operator
 fn preprocess_unary_minus(tokens: &[Token]) -> Vec<Token> {
 	let mut result = Vec::new();
 	let mut i = 0;
@@ -1237,22 +3048,26 @@ fn preprocess_unary_minus(tokens: &[Token]) -> Vec<Token> {
 					// Check if previous token indicates this should be unary
 					match &tokens[i - 1] {
 						Token::Operator(prev_op) if prev_op.value == "(" => true,
+						Token::Operator(prev_op) if prev_op.value == "[" => true,
 						Token::Operator(prev_op) if prev_op.value == "," => true,
 						Token::Operator(prev_op) if prev_op.value == "=" => true,
 						Token::Operator(prev_op) if prev_op.value == "+" => true,
 						Token::Operator(prev_op) if prev_op.value == "-" => true,
 						Token::Operator(prev_op) if prev_op.value == "*" => true,
 						Token::Operator(prev_op) if prev_op.value == "/" => true,
+						Token::Operator(prev_op) if prev_op.value == "%" => true,
+						Token::Operator(prev_op) if prev_op.value == "^" => true,
+						Token::Operator(prev_op) if prev_op.value == "div" => true,
+						Token::Operator(prev_op) if prev_op.value == NOT => true,
+						Token::Operator(prev_op) if is_comparison_operator(&prev_op.value) => true,
 						_ => false,
 					}
 				};
 
 				if is_unary {
-					// Convert unary minus to "0 - number"
-					result.push(Token::Number(lex::LangNumber::Integer(lex::LangInteger {
-						value: 0,
-					})));
-					result.push(tokens[i].clone()); // The minus operator
+					result.push(Token::Operator(lex::LangOperator {
+						value: UNARY_MINUS.to_string(),
+					}));
 				} else {
 					// Regular binary minus
 					result.push(tokens[i].clone());
@@ -1269,45 +3084,498 @@ fn preprocess_unary_minus(tokens: &[Token]) -> Vec<Token> {
 	result
 }
 
-fn eval_line(line: &LangLine) -> Option<f64> {
-	// println!("Evaluating line:");
+/// Print an evaluation error, remembering that one occurred for the REPL prompt
+fn report_error(message: &str) {
+	ERROR_REPORTED.store(true, AtomicOrdering::Relaxed);
+	println!("{}", message);
+}
 
-	// First preprocess tokens to handle function calls
-	let processed_tokens = match preprocess_tokens_for_function_calls(&line.tokens) {
-		Ok(tokens) => tokens,
-		Err(e) => {
-			println!("Error preprocessing function calls: {}", e);
-			return None;
+/// Evaluate the items of a list literal statement into a list
+fn evaluate_list(list: &parse::LangList) -> Result<Value, Box<dyn Error>> {
+	let mut items = Vec::new();
+	for item in &list.items {
+		items.push(evaluate_value(item)?.ok_or("List item evaluation failed")?);
+	}
+	Ok(Value::List(items))
+}
+
+/// Evaluate a statement line, keeping the kind of value it produces
+fn eval_line_value(line: &LangLine) -> Option<Value> {
+	match evaluate_value(&line.tokens) {
+		Ok(result) => result,
+		Err(e) => {
+			report_error(&format!("Error: {}", e));
+			None
 		}
-	};
+	}
+}
 
-	// Preprocess tokens to handle unary minus
-	let unary_processed_tokens = preprocess_unary_minus(&processed_tokens);
+/// Evaluate an infix expression: conditionals, then function calls and unary minus,
+/// then the Shunting Yard conversion to postfix
+fn evaluate_tokens(tokens: &[Token]) -> Result<Option<f64>, Box<dyn Error>> {
+	match evaluate_value(tokens)? {
+		Some(result) => Ok(Some(result.as_number()?)),
+		None => Ok(None),
+	}
+}
 
-	// Debug output
-	// println!("Original tokens: {:?}", line.tokens);
-	// println!("Processed tokens: {:?}", processed_tokens);
+/// Evaluate an infix expression like `evaluate_tokens`, keeping the kind of value it produces
+fn evaluate_value(tokens: &[Token]) -> Result<Option<Value>, Box<dyn Error>> {
+	if let Some(keyword) = tokens.iter().find_map(|t| match t {
+		Token::Symbol(symbol) if symbol.value == "if" || symbol.value == "else" => {
+			Some(symbol.value.as_str())
+		}
+		_ => None,
+	}) {
+		return Err(format!("Syntax error: unexpected '{}'", keyword).into());
+	}
 
-	// Convert infix to postfix using Shunting Yard algorithm
+	if let Some((names, value_parts)) = split_tuple_assignment(tokens) {
+		if names.len() != value_parts.len() {
+			return Err(format!(
+				"Cannot assign {} values to {} variables",
+				value_parts.len(),
+				names.len()
+			)
+			.into());
+		}
+		// Evaluate every value before assigning any, so `x, y = y, x` swaps
+		let mut values = Vec::new();
+		for value_tokens in value_parts {
+			values.push(evaluate_value(value_tokens)?.ok_or("Assignment requires a value")?);
+		}
+		for (name, value) in names.iter().zip(&values) {
+			assign_variable(name, value)?;
+		}
+		return Ok(values.pop());
+	}
+
+	if let Some((name, value_tokens)) = split_assignment(tokens) {
+		let value = evaluate_value(value_tokens)?.ok_or("Assignment requires a value")?;
+		assign_variable(name, &value)?;
+		return Ok(Some(value));
+	}
+
+	if let Some((name, value_tokens)) = split_default_assignment(tokens) {
+		let current = match VARIABLES.lock() {
+			Ok(variables) => variables.get(name).copied(),
+			Err(poisoned) => poisoned.into_inner().get(name).copied(),
+		};
+		// A variable that is set keeps its value, and the new one isn't evaluated at all
+		if let Some(current) = current.filter(|&current| current != 0.0) {
+			return Ok(Some(Value::Number(current)));
+		}
+		let value = evaluate_value(value_tokens)?.ok_or("Assignment requires a value")?;
+		assign_variable(name, &value)?;
+		return Ok(Some(value));
+	}
+
+	if let Some(resolved_tokens) = resolve_pipeline(tokens)? {
+		return evaluate_value(&resolved_tokens);
+	}
+
+	// Conditionals and logical operators are resolved first so only the operands
+	// that decide the result get evaluated
+	if let Some(resolved_tokens) = resolve_ternary(tokens)? {
+		return evaluate_value(&resolved_tokens);
+	}
+	if let Some(resolved_tokens) = resolve_logical(tokens)? {
+		return evaluate_value(&resolved_tokens);
+	}
+	if let Some(resolved_tokens) = resolve_application(tokens)? {
+		return evaluate_value(&resolved_tokens);
+	}
+
+	let processed_tokens = preprocess_tokens_for_function_calls(tokens)?;
+	let unary_processed_tokens = preprocess_unary_minus(&processed_tokens);
 	let postfix_tokens = infix_to_postfix(&unary_processed_tokens);
+	execute_postfix_value(&postfix_tokens)
+}
 
-	// println!("Original tokens: {:?}", line.tokens);
-	// println!("Postfix tokens: {:?}", postfix_tokens);
+/// Locate the first top-level conditional `cond ? a : b` in an expression, returning the
+/// positions of the `?` and its matching `:`. The else branch runs to the end of the
+/// expression, which makes chained conditionals right-associative.
+fn find_ternary(tokens: &[Token]) -> Option<(usize, usize)> {
+	let mut paren_depth = 0;
+	let mut question = None;
+	let mut nested = 0;
+
+	for (i, token) in tokens.iter().enumerate() {
+		if let Token::Operator(op) = token {
+			match op.value.as_str() {
+				"(" => paren_depth += 1,
+				")" => paren_depth -= 1,
+				"?" if paren_depth == 0 => {
+					if question.is_none() {
+						question = Some(i);
+					} else {
+						nested += 1;
+					}
+				}
+				":" if paren_depth == 0 && question.is_some() => {
+					if nested == 0 {
+						return question.map(|q| (q, i));
+					}
+					nested -= 1;
+				}
+				_ => {}
+			}
+		}
+	}
+	None
+}
 
-	match execute_postfix_tokens(&postfix_tokens) {
-		Ok(result) => result,
-		Err(e) => {
-			println!("Error: {}", e);
-			None
+/// Evaluate the first conditional in an expression and replace it with the selected
+/// branch's value. Returns None if the expression contains no conditional.
+fn resolve_ternary(tokens: &[Token]) -> Result<Option<Vec<Token>>, Box<dyn Error>> {
+	if let Some((question, colon)) = find_ternary(tokens) {
+		let condition = evaluate_tokens(&tokens[..question])?
+			.ok_or("Syntax error: missing condition before '?'")?;
+		let branch = if is_truthy(condition) {
+			&tokens[question + 1..colon]
+		} else {
+			&tokens[colon + 1..]
+		};
+		let value = evaluate_tokens(branch)?.ok_or("Syntax error: missing value in conditional")?;
+
+		return Ok(Some(vec![Token::Number(lex::LangNumber::RealNumber(
+			lex::LangRealNumber { value },
+		))]));
+	}
+
+	// Conditionals, logical operators, assignments and pipelines inside grouping
+	// parentheses are evaluated with their group. Function call arguments are left alone, they are
+	// evaluated when the call runs.
+	let mut i = 0;
+	while i < tokens.len() {
+		let is_group = matches!(&tokens[i], Token::Operator(op) if op.value == "(")
+			&& !(i > 0 && matches!(&tokens[i - 1], Token::Symbol(_)));
+		if is_group {
+			let mut paren_depth = 0;
+			let mut close = None;
+			for (j, token) in tokens.iter().enumerate().skip(i) {
+				if let Token::Operator(op) = token {
+					if op.value == "(" {
+						paren_depth += 1;
+					} else if op.value == ")" {
+						paren_depth -= 1;
+						if paren_depth == 0 {
+							close = Some(j);
+							break;
+						}
+					}
+				}
+			}
+
+			if let Some(close) = close {
+				let inner = &tokens[i + 1..close];
+				if inner.iter().any(
+					|t| matches!(t, Token::Operator(op) if matches!(op.value.as_str(), "?" | "&&" | "||" | "=" | "?=" | "|>")),
+				) {
+					let value = evaluate_tokens(inner)?.ok_or("Syntax error: empty parentheses")?;
+					let mut resolved_tokens = tokens[..i].to_vec();
+					resolved_tokens.push(Token::Number(lex::LangNumber::RealNumber(
+						lex::LangRealNumber { value },
+					)));
+					resolved_tokens.extend_from_slice(&tokens[close + 1..]);
+					return Ok(Some(resolved_tokens));
+				}
+				i = close;
+			}
+		}
+		i += 1;
+	}
+
+	Ok(None)
+}
+
+/// Split an assignment `name = value` into the variable name and the value's tokens.
+/// The value may itself be an assignment, as in `x = y = 5`.
+fn split_assignment(tokens: &[Token]) -> Option<(&str, &[Token])> {
+	match tokens {
+		[Token::Symbol(name), Token::Operator(op), value @ ..] if op.value == "=" => {
+			Some((name.value.as_str(), value))
 		}
+		_ => None,
 	}
 }
 
+/// Split a default assignment `name ?= value`, which only assigns when the variable is
+/// undefined or zero, into the variable name and the value's tokens
+fn split_default_assignment(tokens: &[Token]) -> Option<(&str, &[Token])> {
+	match tokens {
+		[Token::Symbol(name), Token::Operator(op), value @ ..] if op.value == "?=" => {
+			Some((name.value.as_str(), value))
+		}
+		_ => None,
+	}
+}
+
+/// Split a tuple assignment `x, y = 1, 2` into the variable names and the value
+/// expressions. Only commas outside parentheses separate targets or values, so in
+/// `x = f(1, 2)` the commas belong to the call and the assignment isn't a tuple.
+fn split_tuple_assignment(tokens: &[Token]) -> Option<(Vec<&str>, Vec<&[Token]>)> {
+	let sides = split_top_level(tokens, "=")?;
+	let [targets, values] = sides.as_slice() else {
+		return None;
+	};
+	let targets = split_top_level(targets, ",");
+	let values = split_top_level(values, ",");
+	if targets.is_none() && values.is_none() {
+		return None;
+	}
+
+	let names = targets
+		.unwrap_or_else(|| vec![sides[0]])
+		.into_iter()
+		.map(|target| match target {
+			[Token::Symbol(name)] => Some(name.value.as_str()),
+			_ => None,
+		})
+		.collect::<Option<Vec<&str>>>()?;
+	Some((names, values.unwrap_or_else(|| vec![sides[1]])))
+}
+
+/// Store a value in a global variable
+fn assign_variable(name: &str, value: &Value) -> Result<(), Box<dyn Error>> {
+	// A name holds one kind of value at a time, so storing a list forgets any number it had
+	// and the other way round
+	if let Value::List(items) = value {
+		match VARIABLES.lock() {
+			Ok(mut variables) => variables.remove(name),
+			Err(poisoned) => poisoned.into_inner().remove(name),
+		};
+		match LIST_VARIABLES.lock() {
+			Ok(mut lists) => lists.insert(name.to_string(), items.clone()),
+			Err(poisoned) => poisoned
+				.into_inner()
+				.insert(name.to_string(), items.clone()),
+		};
+		return Ok(());
+	}
+	let number = value.as_number()?;
+	match LIST_VARIABLES.lock() {
+		Ok(mut lists) => lists.remove(name),
+		Err(poisoned) => poisoned.into_inner().remove(name),
+	};
+	match VARIABLES.lock() {
+		Ok(mut variables) => {
+			variables.insert(name.to_string(), number);
+		}
+		Err(poisoned) => {
+			let mut variables = poisoned.into_inner();
+			variables.insert(name.to_string(), number);
+		}
+	}
+	Ok(())
+}
+
+/// The items of a variable holding a list, if there is one by this name
+fn list_variable(name: &str) -> Option<Vec<Value>> {
+	match LIST_VARIABLES.lock() {
+		Ok(lists) => lists.get(name).cloned(),
+		Err(poisoned) => poisoned.into_inner().get(name).cloned(),
+	}
+}
+
+/// Split an expression at the occurrences of `operator` outside parentheses.
+/// Returns None if there are none.
+fn split_top_level<'a>(tokens: &'a [Token], operator: &str) -> Option<Vec<&'a [Token]>> {
+	let mut parts = Vec::new();
+	let mut paren_depth = 0;
+	let mut part_start = 0;
+	for (i, token) in tokens.iter().enumerate() {
+		if let Token::Operator(op) = token {
+			match op.value.as_str() {
+				"(" | "[" => paren_depth += 1,
+				")" | "]" => paren_depth -= 1,
+				value if value == operator && paren_depth == 0 => {
+					parts.push(&tokens[part_start..i]);
+					part_start = i + 1;
+				}
+				_ => {}
+			}
+		}
+	}
+	if parts.is_empty() {
+		return None;
+	}
+	parts.push(&tokens[part_start..]);
+	Some(parts)
+}
+
+/// Rewrite a pipeline `x |> f |> g` into the call `g(f(x))`. `|>` is left-associative and
+/// binds more loosely than any other operator, so the whole expression before the first
+/// `|>` is the value fed in. Returns None if there is no top-level `|>`.
+fn resolve_pipeline(tokens: &[Token]) -> Result<Option<Vec<Token>>, Box<dyn Error>> {
+	let Some(stages) = split_top_level(tokens, "|>") else {
+		return Ok(None);
+	};
+	if stages[0].is_empty() {
+		return Err("Syntax error: '|>' needs a value on its left".into());
+	}
+
+	let mut piped = stages[0].to_vec();
+	for stage in &stages[1..] {
+		let name = match stage {
+			[Token::Symbol(name)] if is_user_defined_function_global(&name.value) => name,
+			[Token::Symbol(name)] => {
+				return Err(
+					format!("'{}' is not a function and can't be piped into", name.value).into(),
+				);
+			}
+			_ => return Err("Syntax error: '|>' needs a function name on its right".into()),
+		};
+		let mut call = vec![
+			Token::Symbol(name.clone()),
+			Token::Operator(lex::LangOperator {
+				value: "(".to_string(),
+			}),
+		];
+		call.append(&mut piped);
+		call.push(Token::Operator(lex::LangOperator {
+			value: ")".to_string(),
+		}));
+		piped = call;
+	}
+	Ok(Some(piped))
+}
+
+/// Rewrite every application `f @ x` into the call `f(x)`. `@` binds as tightly as a call:
+/// it takes the single operand on its right, so `square @ 1 + 2` is `square(1) + 2`, and
+/// it is right-associative, so `f @ g @ x` is `f(g(x))`. Returns None if there is no `@`.
+fn resolve_application(tokens: &[Token]) -> Result<Option<Vec<Token>>, Box<dyn Error>> {
+	let is_apply = |token: &Token| matches!(token, Token::Operator(op) if op.value == "@");
+	if !tokens.iter().any(is_apply) {
+		return Ok(None);
+	}
+
+	let mut tokens = tokens.to_vec();
+	// The rightmost application is rewritten first so it becomes the operand of the next
+	while let Some(at) = tokens.iter().rposition(is_apply) {
+		match at.checked_sub(1).map(|i| &tokens[i]) {
+			Some(Token::Symbol(name)) if is_user_defined_function_global(&name.value) => {}
+			Some(Token::Symbol(name)) => {
+				return Err(
+					format!("'{}' is not a function and can't be applied", name.value).into(),
+				);
+			}
+			_ => return Err("Syntax error: '@' needs a function name on its left".into()),
+		}
+		let end = operand_end(&tokens, at + 1)
+			.ok_or("Syntax error: '@' needs an operand on its right")?;
+		tokens[at] = Token::Operator(lex::LangOperator {
+			value: "(".to_string(),
+		});
+		tokens.insert(
+			end,
+			Token::Operator(lex::LangOperator {
+				value: ")".to_string(),
+			}),
+		);
+	}
+	Ok(Some(tokens))
+}
+
+/// Find where the single operand starting at `start` ends: a literal, a variable, a call,
+/// a parenthesized group or a negated operand. Returns the index just past it.
+fn operand_end(tokens: &[Token], start: usize) -> Option<usize> {
+	let closing_paren = |open: usize| {
+		let mut paren_depth = 0;
+		for (i, token) in tokens.iter().enumerate().skip(open) {
+			if let Token::Operator(op) = token {
+				if op.value == "(" {
+					paren_depth += 1;
+				} else if op.value == ")" {
+					paren_depth -= 1;
+					if paren_depth == 0 {
+						return Some(i + 1);
+					}
+				}
+			}
+		}
+		None
+	};
+
+	match tokens.get(start)? {
+		Token::Number(_) | Token::String(_) => Some(start + 1),
+		Token::Symbol(_) => match tokens.get(start + 1) {
+			Some(Token::Operator(op)) if op.value == "(" => closing_paren(start + 1),
+			_ => Some(start + 1),
+		},
+		Token::Operator(op) if op.value == "(" => closing_paren(start),
+		Token::Operator(op) if op.value == "-" => operand_end(tokens, start + 1),
+		Token::Operator(_) => None,
+	}
+}
+
+/// Evaluate a top-level chain of `&&` and `||` and replace it with its value, 1 or 0.
+/// Operands are evaluated left to right and only while the result still depends on them.
+/// Returns None if the expression contains no top-level logical operator.
+fn resolve_logical(tokens: &[Token]) -> Result<Option<Vec<Token>>, Box<dyn Error>> {
+	// `&&` binds tighter than `||`, so split on `||` first
+	let disjuncts = match split_top_level(tokens, "||") {
+		Some(disjuncts) => disjuncts,
+		None if split_top_level(tokens, "&&").is_some() => vec![tokens],
+		None => return Ok(None),
+	};
+
+	let mut value = false;
+	for disjunct in disjuncts {
+		let conjuncts = split_top_level(disjunct, "&&").unwrap_or_else(|| vec![disjunct]);
+		let mut all_true = true;
+		for conjunct in conjuncts {
+			let operand = evaluate_tokens(conjunct)?
+				.ok_or("Syntax error: '&&' and '||' need an operand on each side")?;
+			if !is_truthy(operand) {
+				all_true = false;
+				break;
+			}
+		}
+		if all_true {
+			value = true;
+			break;
+		}
+	}
+
+	Ok(Some(vec![Token::Number(lex::LangNumber::RealNumber(
+		lex::LangRealNumber {
+			value: if value { 1.0 } else { 0.0 },
+		},
+	))]))
+}
+
 fn infix_to_postfix(tokens: &[Token]) -> Vec<Token> {
 	let mut output: Vec<Token> = Vec::new();
 	let mut operator_stack: Vec<Token> = Vec::new();
+	// Element counts of the list literals currently open: commas seen and whether any
+	// element has started. Brackets opening an index expression have no count.
+	let mut open_lists: Vec<Option<(usize, bool)>> = Vec::new();
+	let mut after_operand = false;
+
+	// Move operators to the output up to the innermost open bracket, leaving it on the stack
+	fn pop_to_bracket(operator_stack: &mut Vec<Token>, output: &mut Vec<Token>) {
+		while let Some(Token::Operator(stack_op)) = operator_stack.last() {
+			if stack_op.value == "[" {
+				break;
+			}
+			output.push(operator_stack.pop().unwrap());
+		}
+	}
 
 	for token in tokens {
+		if let Some(Some((_, started))) = open_lists.last_mut() {
+			*started |= !matches!(token, Token::Operator(op) if op.value == "]");
+		}
+		// A `[` straight after an operand indexes it rather than starting a list literal
+		let indexes = std::mem::replace(
+			&mut after_operand,
+			match token {
+				Token::Number(_) | Token::Symbol(_) | Token::String(_) => true,
+				Token::Operator(op) => op.value == ")" || op.value == "]",
+			},
+		);
+
 		match token {
 			Token::Number(_) | Token::Symbol(_) | Token::String(_) => {
 				// Operands go directly to output
@@ -1315,42 +3583,49 @@ fn infix_to_postfix(tokens: &[Token]) -> Vec<Token> {
 			}
 			Token::Operator(op) => {
 				match op.value.as_str() {
-					"=" => {
-						// Assignment has lowest precedence, right associative
-						while let Some(Token::Operator(stack_op)) = operator_stack.last() {
-							if get_precedence(&stack_op.value) > get_precedence("=") {
-								output.push(operator_stack.pop().unwrap());
-							} else {
-								break;
-							}
-						}
+					UNARY_MINUS | NOT => {
+						// Prefix operator: it has no left operand to pop for
 						operator_stack.push(token.clone());
 					}
-					"+" | "-" => {
-						// Left associative, precedence 1
-						while let Some(Token::Operator(stack_op)) = operator_stack.last() {
-							if get_precedence(&stack_op.value) >= get_precedence(&op.value) {
-								output.push(operator_stack.pop().unwrap());
-							} else {
-								break;
-							}
-						}
+					"(" => {
 						operator_stack.push(token.clone());
 					}
-					"*" | "/" => {
-						// Left associative, precedence 2
-						while let Some(Token::Operator(stack_op)) = operator_stack.last() {
-							if get_precedence(&stack_op.value) >= get_precedence(&op.value) {
-								output.push(operator_stack.pop().unwrap());
-							} else {
-								break;
-							}
-						}
+					"[" => {
 						operator_stack.push(token.clone());
+						open_lists.push(if indexes { None } else { Some((0, false)) });
 					}
-					"(" => {
-						operator_stack.push(token.clone());
+					"," if matches!(open_lists.last(), Some(Some(_))) => {
+						// Finish the previous element of the list literal
+						pop_to_bracket(&mut operator_stack, &mut output);
+						if let Some(Some((commas, _))) = open_lists.last_mut() {
+							*commas += 1;
+						}
 					}
+					"]" => match open_lists.pop() {
+						// The index is worked out first, then picks from the operand before it
+						Some(None) => {
+							pop_to_bracket(&mut operator_stack, &mut output);
+							operator_stack.pop();
+							output.push(Token::Operator(lex::LangOperator {
+								value: INDEX.to_string(),
+							}));
+						}
+						// The literal becomes its element count followed by the list operator
+						Some(Some((commas, started))) => {
+							pop_to_bracket(&mut operator_stack, &mut output);
+							operator_stack.pop();
+							let count = if started { commas + 1 } else { 0 };
+							output.push(Token::Number(lex::LangNumber::Integer(
+								lex::LangInteger {
+									value: count as i64,
+								},
+							)));
+							output.push(Token::Operator(lex::LangOperator {
+								value: LIST_LITERAL.to_string(),
+							}));
+						}
+						None => output.push(token.clone()),
+					},
 					")" => {
 						// Pop operators until we find the opening parenthesis
 						while let Some(stack_token) = operator_stack.pop() {
@@ -1362,10 +3637,30 @@ fn infix_to_postfix(tokens: &[Token]) -> Vec<Token> {
 							output.push(stack_token);
 						}
 					}
-					_ => {
-						// For any other operators, treat as normal operators
-						output.push(token.clone());
-					}
+					_ => match get_precedence(&op.value) {
+						Some((precedence, associativity)) => {
+							// Pop operators that bind tighter, or equally tight for left associativity
+							while let Some(Token::Operator(stack_op)) = operator_stack.last() {
+								let pops = match get_precedence(&stack_op.value) {
+									Some((stack_precedence, _)) => {
+										stack_precedence > precedence
+											|| (stack_precedence == precedence
+												&& associativity == Associativity::Left)
+									}
+									None => false,
+								};
+								if !pops {
+									break;
+								}
+								output.push(operator_stack.pop().unwrap());
+							}
+							operator_stack.push(token.clone());
+						}
+						None => {
+							// For any other operators, treat as normal operators
+							output.push(token.clone());
+						}
+					},
 				}
 			}
 		}
@@ -1379,12 +3674,333 @@ fn infix_to_postfix(tokens: &[Token]) -> Vec<Token> {
 	output
 }
 
-fn get_precedence(op: &str) -> i32 {
-	match op {
-		"=" => 0,       // Assignment (lowest precedence)
-		"+" | "-" => 1, // Addition and subtraction
-		"*" | "/" => 2, // Multiplication and division (highest precedence)
-		_ => -1,        // Unknown operators
+/// How a chain of operators with equal precedence groups
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Associativity {
+	/// `a - b - c` is `(a - b) - c`
+	Left,
+	/// `a ^ b ^ c` is `a ^ (b ^ c)`
+	Right,
+}
+
+/// Operators ordered from loosest to tightest binding. Exponentiation binds tighter than
+/// unary minus, so `-2 ^ 2` is `-(2 ^ 2)`, while unary minus binds tighter than `*` and `/`.
+/// `not` binds looser than comparisons, so `not a == b` is `not (a == b)`.
+/// `&&` and `||` are normally resolved before the postfix stage but are ranked here too.
+const PRECEDENCE_TABLE: &[(&[&str], Associativity)] = &[
+	(&["="], Associativity::Right),
+	(&["||"], Associativity::Left),
+	(&["&&"], Associativity::Left),
+	(&[NOT], Associativity::Right),
+	(&["==", "!="], Associativity::Left),
+	(&["<", ">", "<=", ">="], Associativity::Left),
+	(&["+", "-"], Associativity::Left),
+	(&["*", "/", "%", "div"], Associativity::Left),
+	(&[UNARY_MINUS], Associativity::Right),
+	(&["^"], Associativity::Right),
+];
+
+/// Operator precedence (higher binds tighter) and associativity, from `PRECEDENCE_TABLE`.
+/// Returns None for tokens that are not operators with a precedence, like parentheses.
+fn get_precedence(op: &str) -> Option<(usize, Associativity)> {
+	PRECEDENCE_TABLE
+		.iter()
+		.position(|(operators, _)| operators.contains(&op))
+		.map(|precedence| (precedence, PRECEDENCE_TABLE[precedence].1))
+}
+
+/// Check if an operator is a comparison, which evaluates to 1 (true) or 0 (false)
+fn is_comparison_operator(op: &str) -> bool {
+	matches!(op, "==" | "!=" | "<" | ">" | "<=" | ">=")
+}
+
+/// Check if an operator is an arithmetic or comparison operator taking two operands
+fn is_binary_operator(op: &str) -> bool {
+	matches!(op, "+" | "-" | "*" | "/" | "%" | "^" | "div") || is_comparison_operator(op)
+}
+
+/// Find arithmetic whose meaning relies on operator precedence, like `a + b * c` or `-2 ^ 2`,
+/// describing each place where parentheses would make the order explicit. Operators are
+/// compared within one parenthesized group and one expression, so commas, comparisons,
+/// logical operators and line breaks start afresh.
+fn precedence_warnings(tokens: &[Token]) -> Vec<String> {
+	let level = |op: &str| get_precedence(op).map(|(precedence, _)| precedence);
+	let mut warnings = Vec::new();
+	// Arithmetic operators seen in each open group, innermost last
+	let mut groups: Vec<Vec<&str>> = vec![Vec::new()];
+
+	for (i, token) in tokens.iter().enumerate() {
+		let Token::Operator(op) = token else {
+			continue;
+		};
+		let unary = match i.checked_sub(1).map(|previous| &tokens[previous]) {
+			None => true,
+			Some(Token::Operator(previous)) => previous.value != ")" && previous.value != "]",
+			Some(_) => false,
+		};
+		match op.value.as_str() {
+			"(" | "[" => groups.push(Vec::new()),
+			")" | "]" => {
+				groups.pop();
+				if groups.is_empty() {
+					groups.push(Vec::new());
+				}
+			}
+			"-" if unary => {
+				let before_power =
+					matches!(tokens.get(i + 1), Some(Token::Number(_) | Token::Symbol(_)))
+						&& matches!(tokens.get(i + 2), Some(Token::Operator(next)) if next.value == "^");
+				if before_power {
+					warnings.push(
+						"'-' is applied after '^', so -a ^ b means -(a ^ b); add parentheses to make the order explicit"
+							.to_string(),
+					);
+				}
+			}
+			operator if is_binary_operator(operator) && !is_comparison_operator(operator) => {
+				let group = groups.last_mut().unwrap();
+				let other = group
+					.iter()
+					.copied()
+					.find(|other| level(other) != level(operator));
+				match other {
+					Some(other) => {
+						let (tighter, looser) = if level(other) > level(operator) {
+							(other, operator)
+						} else {
+							(operator, other)
+						};
+						warnings.push(format!(
+							"'{}' is applied before '{}'; add parentheses to make the order explicit",
+							tighter, looser
+						));
+						group.clear();
+					}
+					None => group.push(operator),
+				}
+			}
+			_ => groups.last_mut().unwrap().clear(),
+		}
+	}
+	warnings
+}
+
+/// Error for a binary operator that lacks one of its operands, like `2 +`
+fn missing_operand_error(op: &str) -> Box<dyn Error> {
+	format!("Syntax error: operator '{}' is missing an operand", op).into()
+}
+
+/// Evaluate the condition of an if statement. Besides an expression it may be a block, as in
+/// `if ({ a = f(); a }) { ... }`, whose last value is the condition. The block runs in a
+/// child scope, so variables it assigns are gone afterwards.
+fn evaluate_condition(tokens: &[Token]) -> Result<Option<f64>, Box<dyn Error>> {
+	if !tokens
+		.iter()
+		.any(|token| matches!(token, Token::Operator(op) if op.value == "{"))
+	{
+		return evaluate_tokens(tokens);
+	}
+
+	// Variables a block creates only last for the condition, but assignments to existing
+	// ones are kept
+	let scalar_names: Vec<String> = match VARIABLES.lock() {
+		Ok(vars) => vars.keys().cloned().collect(),
+		Err(poisoned) => poisoned.into_inner().keys().cloned().collect(),
+	};
+	let list_names: Vec<String> = match LIST_VARIABLES.lock() {
+		Ok(lists) => lists.keys().cloned().collect(),
+		Err(poisoned) => poisoned.into_inner().keys().cloned().collect(),
+	};
+	let result = substitute_block_values(tokens).and_then(|tokens| evaluate_tokens(&tokens));
+	match VARIABLES.lock() {
+		Ok(mut vars) => vars.retain(|name, _| scalar_names.contains(name)),
+		Err(poisoned) => poisoned
+			.into_inner()
+			.retain(|name, _| scalar_names.contains(name)),
+	}
+	match LIST_VARIABLES.lock() {
+		Ok(mut lists) => lists.retain(|name, _| list_names.contains(name)),
+		Err(poisoned) => poisoned
+			.into_inner()
+			.retain(|name, _| list_names.contains(name)),
+	}
+	result
+}
+
+/// Evaluate each `{ ... }` block in an expression, replacing it with its value as a number
+/// token so it can be used as an operand
+fn substitute_block_values(tokens: &[Token]) -> Result<Vec<Token>, Box<dyn Error>> {
+	let mut result = Vec::new();
+	let mut i = 0;
+	while i < tokens.len() {
+		if !matches!(&tokens[i], Token::Operator(op) if op.value == "{") {
+			result.push(tokens[i].clone());
+			i += 1;
+			continue;
+		}
+
+		// Find the brace closing this block
+		let mut depth = 0;
+		let mut end = i;
+		for (j, token) in tokens.iter().enumerate().skip(i) {
+			match token {
+				Token::Operator(op) if op.value == "{" => depth += 1,
+				Token::Operator(op) if op.value == "}" => depth -= 1,
+				_ => {}
+			}
+			if depth == 0 {
+				end = j;
+				break;
+			}
+		}
+		if depth != 0 {
+			return Err("Syntax error: missing '}'".into());
+		}
+
+		let block = parse::parse_tokens(tokens[i..=end].to_vec())?;
+		let value = eval_block_with_function_preprocessing(&block)
+			.ok_or("Block in condition has no value")?;
+		result.push(Token::Number(lex::LangNumber::RealNumber(
+			lex::LangRealNumber { value },
+		)));
+		i = end + 1;
+	}
+	Ok(result)
+}
+
+/// Evaluate an if/else item, running the branch selected by its condition with `eval_branch`
+fn eval_if(if_item: &parse::LangIf, eval_branch: fn(&LangBlock) -> Option<f64>) -> Option<f64> {
+	let condition = match evaluate_condition(&if_item.condition) {
+		Ok(Some(value)) => value,
+		Ok(None) => {
+			report_error("Error: Missing condition in if statement");
+			return None;
+		}
+		Err(e) => {
+			report_error(&format!("Error: {}", e));
+			return None;
+		}
+	};
+
+	let branch = if is_truthy(condition) {
+		&if_item.then_block
+	} else if let Some(else_block) = &if_item.else_block {
+		else_block
+	} else {
+		return None;
+	};
+
+	let Some(name) = &if_item.assign_to else {
+		return eval_branch(branch);
+	};
+	// Like any assignment, an if used as a value doesn't print the value it assigns
+	let value = eval_block_with_function_preprocessing(branch)?;
+	match assign_variable(name, &Value::Number(value)) {
+		Ok(()) => Some(value),
+		Err(e) => {
+			report_error(&format!("Error: {}", e));
+			None
+		}
+	}
+}
+
+/// Name under which an anonymous function is stored: its parameter count and a hash of
+/// its source, so the same function always gets the same name and is stored only once
+fn anonymous_function_name(function: &parse::LangFunction) -> String {
+	let source = format!(
+		"({}) => {}",
+		parse::parameters_to_string(&function.parameters, &function.parameter_types),
+		parse::block_to_source(&function.body, 0)
+	);
+	// FNV-1a, which unlike the standard library's hasher is fixed across releases
+	let hash = source
+		.bytes()
+		.fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+			(hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+		});
+	format!("func_{}_{:016x}", function.parameters.len(), hash)
+}
+
+/// The number a match arm's case literal stands for
+fn match_pattern_value(pattern: &lex::LangNumber) -> f64 {
+	match pattern {
+		lex::LangNumber::Integer(integer) => integer.value as f64,
+		lex::LangNumber::RealNumber(real) => real.value,
+	}
+}
+
+/// Evaluate a match item, running the first arm whose case literal equals the subject
+/// (or the `_` arm) with `eval_branch`. No matching arm produces no value.
+fn eval_match(
+	match_item: &parse::LangMatch,
+	eval_branch: fn(&LangBlock) -> Option<f64>,
+) -> Option<f64> {
+	let subject = match evaluate_tokens(&match_item.subject) {
+		Ok(Some(value)) => value,
+		Ok(None) => {
+			report_error("Error: Missing value in match statement");
+			return None;
+		}
+		Err(e) => {
+			report_error(&format!("Error: {}", e));
+			return None;
+		}
+	};
+
+	let arm = match_item.arms.iter().find(|arm| match &arm.pattern {
+		Some(pattern) => match_pattern_value(pattern) == subject,
+		None => true,
+	})?;
+	eval_branch(&arm.body)
+}
+
+/// Confirmations like `x = 5` for the variables an assignment line has just stored, to
+/// print in place of its value. There are none unless `:echo` is on.
+fn assignment_echoes(tokens: &[Token]) -> Vec<String> {
+	if !settings().echo_assignments {
+		return Vec::new();
+	}
+	// Follow chained assignments like `p = q = 3` the way evaluation does, so every name in
+	// the chain is confirmed
+	let mut names = Vec::new();
+	let mut rest = tokens;
+	loop {
+		if let Some((tuple_names, _)) = split_tuple_assignment(rest) {
+			names.extend(tuple_names);
+			break;
+		}
+		match split_assignment(rest) {
+			Some((name, value_tokens)) => {
+				names.push(name);
+				rest = value_tokens;
+			}
+			None => break,
+		}
+	}
+	let variables = match VARIABLES.lock() {
+		Ok(variables) => variables,
+		Err(poisoned) => poisoned.into_inner(),
+	};
+	names
+		.iter()
+		.filter_map(|name| {
+			let value = match variables.get(*name) {
+				Some(value) => format_value(*value),
+				None => Value::List(list_variable(name)?).to_string(),
+			};
+			Some(format!("{} = {}", name, value))
+		})
+		.collect()
+}
+
+/// Print a statement's result, adding it to the `:stats` summary when recording is on and
+/// the result is a number
+fn print_result(value: &Value) {
+	println!("{}", value);
+	if settings().record_stats
+		&& let Ok(number) = value.as_number()
+	{
+		result_stats().record(number);
 	}
 }
 
@@ -1392,15 +4008,14 @@ fn eval_block(block: &LangBlock) -> Option<f64> {
 	// println!("Evaluating block:");
 
 	let mut last_result = None;
-	let mut has_function_definitions = false;
 
 	for item in &block.items {
 		match item {
 			parse::LangBlockItem::Line(line) => {
-				let result = eval_line(line);
+				let result = eval_line_value(line);
 
 				// Print result for non-assignment expressions
-				if let Some(value) = result {
+				if let Some(value) = &result {
 					// Check if this line contains an assignment operator
 					let has_assignment = line
 						.tokens
@@ -1408,11 +4023,16 @@ fn eval_block(block: &LangBlock) -> Option<f64> {
 						.any(|t| matches!(t, Token::Operator(op) if op.value == "="));
 
 					if !has_assignment {
-						println!("{}", value);
+						print_result(value);
+					} else {
+						for echo in assignment_echoes(&line.tokens) {
+							println!("{}", echo);
+						}
 					}
 				}
 
-				last_result = result;
+				// Strings and lists are printed, but only numbers are a block's result
+				last_result = result.and_then(|value| value.as_number().ok());
 			}
 			parse::LangBlockItem::Block(nested_block) => {
 				let result = eval_block(nested_block);
@@ -1420,21 +4040,15 @@ fn eval_block(block: &LangBlock) -> Option<f64> {
 			}
 			parse::LangBlockItem::Function(function) => {
 				// Store the function definition and compile with LLVM
-				let func_name = match FUNCTIONS.lock() {
-					Ok(functions) => {
-						format!("func_{}_{}", function.parameters.len(), functions.len())
-					}
-					Err(poisoned) => {
-						let functions = poisoned.into_inner();
-						format!("func_{}_{}", function.parameters.len(), functions.len())
-					}
-				};
+				let func_name = anonymous_function_name(function);
 
 				// Convert to named function for storage
 				let named_function = parse::LangNamedFunction {
 					name: func_name.clone(),
 					parameters: function.parameters.clone(),
+					parameter_types: function.parameter_types.clone(),
 					body: function.body.clone(),
+					guard: None,
 				};
 
 				// Try to compile the function with LLVM, but store it regardless
@@ -1471,18 +4085,41 @@ fn eval_block(block: &LangBlock) -> Option<f64> {
 						// println!("Function stored for runtime evaluation");
 					}
 				}
-				has_function_definitions = true;
+				record_dependencies(&func_name);
+				// Definitions have no value of their own, but don't hide the values of
+				// expressions after them in the block
+				last_result = None;
+			}
+			parse::LangBlockItem::NamedFunction(named_function)
+				if named_function.guard.is_some() =>
+			{
+				// Guarded clauses are chosen per call, so they are always interpreted
+				warn_unknown_symbols(named_function);
+				add_guarded_clause(named_function);
+				record_dependencies(&named_function.name);
 				last_result = None;
 			}
 			parse::LangBlockItem::NamedFunction(named_function) => {
 				// Store the named function definition and compile with LLVM
+				warn_unknown_symbols(named_function);
 
 				// Convert to LangFunction for storage compatibility
 				let function = parse::LangFunction {
 					parameters: named_function.parameters.clone(),
+					parameter_types: named_function.parameter_types.clone(),
 					body: named_function.body.clone(),
 				};
 
+				// A new unguarded definition replaces the function whole, so guarded clauses
+				// from before it would otherwise still take priority. The first one, or the
+				// same one again, is their fallback instead.
+				if function_ast(&named_function.name).is_some_and(|existing| existing != function) {
+					match FUNCTION_CLAUSES.lock() {
+						Ok(mut all_clauses) => all_clauses.remove(&named_function.name),
+						Err(poisoned) => poisoned.into_inner().remove(&named_function.name),
+					};
+				}
+
 				// Try to compile the function with LLVM, but store it regardless
 				match compile_and_store_named_function(named_function) {
 					Ok(_) => {
@@ -1509,44 +4146,301 @@ fn eval_block(block: &LangBlock) -> Option<f64> {
 						// println!("Function stored for runtime evaluation");
 					}
 				}
-				has_function_definitions = true;
+				record_dependencies(&named_function.name);
+				// Definitions have no value of their own, but don't hide the values of
+				// expressions after them in the block
 				last_result = None;
 			}
 			parse::LangBlockItem::FunctionCall(call) => {
 				// Execute function call using LLVM
-				match execute_function_call(call) {
-					Ok(result) => {
-						println!("{}", result);
-						last_result = Some(result);
+				match execute_statement_call(call) {
+					Ok(Some(result)) => {
+						print_result(&result);
+						last_result = result.as_number().ok();
+					}
+					Ok(None) => {
+						last_result = None;
 					}
 					Err(e) => {
-						println!("Error calling function: {}", e);
+						report_error(&format!("Error calling function: {}", e));
 						last_result = None;
 					}
 				}
 			}
+			parse::LangBlockItem::List(list) => {
+				match evaluate_list(list) {
+					Ok(value) => print_result(&value),
+					Err(e) => report_error(&format!("Error: {}", e)),
+				}
+				// Only numbers are a block's result
+				last_result = None;
+			}
+			parse::LangBlockItem::If(if_item) => {
+				last_result = eval_if(if_item, eval_block);
+			}
+			parse::LangBlockItem::Match(match_item) => {
+				last_result = eval_match(match_item, eval_block);
+			}
 		}
 	}
 
-	// If there were function definitions in this block, return None
-	if has_function_definitions {
-		None
+	last_result
+}
+
+/// Format a number with at most `precision` decimal places, trimming trailing zeros and
+/// rounding the last decimal place in the given direction
+fn format_rounded(value: f64, precision: usize, rounding: RoundingMode) -> String {
+	// Start from the nearest decimal and move one place away from it if it's on the wrong
+	// side of the value. Scaling by a power of ten instead would misround values like 2.67
+	// that print exactly but aren't exact in binary.
+	let nearest = format!("{:.*}", precision, value);
+	let rounded: f64 = nearest.parse().unwrap_or(value);
+	let step = 10f64.powi(-(precision as i32));
+	let adjusted = match rounding {
+		RoundingMode::Up if rounded < value => Some(rounded + step),
+		RoundingMode::Down if rounded > value => Some(rounded - step),
+		RoundingMode::TowardZero if rounded.abs() > value.abs() => {
+			Some(rounded - step.copysign(value))
+		}
+		_ => None,
+	};
+	let formatted = match adjusted {
+		Some(adjusted) => format!("{:.*}", precision, adjusted),
+		None => nearest,
+	};
+	let trimmed = trim_decimal_zeros(&formatted);
+	if trimmed != "0" && trimmed != "-0" {
+		trimmed.to_string()
+	} else if value != 0.0 && matches!(rounding, RoundingMode::Nearest) {
+		// Too small to show at this precision: show its significant digits instead of 0
+		format_significant(value, precision.max(1))
+	} else {
+		// Negative values that round to zero shouldn't print a sign
+		"0".to_string()
+	}
+}
+
+/// Trim trailing zeros after a decimal point, and the point itself if nothing is left after it
+fn trim_decimal_zeros(formatted: &str) -> &str {
+	if formatted.contains('.') {
+		formatted.trim_end_matches('0').trim_end_matches('.')
+	} else {
+		formatted
+	}
+}
+
+/// Format a number to `digits` significant digits the way C's `%g` does, so the REPL and
+/// compiled executables print small results identically
+fn format_significant(value: f64, digits: usize) -> String {
+	let scientific = format!("{:.*e}", digits - 1, value);
+	let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+	let exponent: i32 = exponent.parse().unwrap_or(0);
+	if exponent < -4 || exponent >= digits as i32 {
+		let sign = if exponent < 0 { '-' } else { '+' };
+		format!(
+			"{}e{}{:02}",
+			trim_decimal_zeros(mantissa),
+			sign,
+			exponent.unsigned_abs()
+		)
 	} else {
-		last_result
+		let decimals = (digits as i32 - 1 - exponent) as usize;
+		trim_decimal_zeros(&format!("{:.*}", decimals, value)).to_string()
+	}
+}
+
+/// Format a result for display using the configured precision and rounding
+fn format_value(value: f64) -> String {
+	let settings = settings();
+	format_rounded(value, settings.precision, settings.rounding)
+}
+
+/// Lex input, rejecting it if a line has more tokens than the `:maxtokens` limit allows
+fn lex_within_limit(input: &str) -> Result<Vec<Token>, parse::ParseError> {
+	let tokens = lex(input);
+	if let Some(limit) = settings().max_tokens {
+		parse::check_token_limit(&tokens, limit)?;
 	}
+	Ok(tokens)
 }
 
 fn run(line: &str) -> Option<f64> {
-	// println!("Tokenizing: {}", line);
-	let tokens = lex(line);
+	// Reject overly long lines before doing any work on them
+	let tokens = match lex_within_limit(line) {
+		Ok(tokens) => tokens,
+		Err(e) => {
+			report_error(&format!("Error: {}", e));
+			return None;
+		}
+	};
 
-	// Parse tokens into a LangBlock with support for nested blocks
-	let mut token_iter = tokens.into_iter().peekable();
-	let block = parse_block(&mut token_iter);
+	if settings().precedence_lint {
+		for warning in precedence_warnings(&tokens) {
+			println!("Warning: {}", warning);
+		}
+	}
+
+	// Parse into a LangBlock with support for nested blocks, reporting every malformed
+	// statement rather than just the first
+	let (mut block, diagnostics) = parse::parse_program_with_recovery(tokens);
+	if !diagnostics.is_empty() {
+		for e in diagnostics {
+			report_error(&format!("Error: {}", e));
+		}
+		return None;
+	}
 
 	// println!("Parsed block:\n{}", block);
 
-	eval_block(&block)
+	if settings().hoist_functions {
+		hoist_function_definitions(&mut block);
+	}
+
+	if !settings().profile {
+		return eval_block(&block);
+	}
+
+	// Profile each top-level statement on its own
+	let mut last_result = None;
+	for item in block.items {
+		*profile_counts() = ProfileCounts::ZERO;
+		last_result = eval_block(&LangBlock { items: vec![item] });
+		println!("{}", format_profile(&profile_counts()));
+	}
+	last_result
+}
+
+/// Run a file of statements like input typed at the prompt, and summarise the functions and
+/// variables it defined, e.g. `loaded 2 functions (add, sub) and 1 variable (tau)`
+fn load_file(path: &str) -> Result<String, Box<dyn Error>> {
+	let program = fs::read_to_string(path).map_err(|e| format!("Error reading {}: {}", path, e))?;
+
+	let functions_before = match FUNCTIONS.lock() {
+		Ok(functions) => functions.clone(),
+		Err(poisoned) => poisoned.into_inner().clone(),
+	};
+	let clauses_before = match FUNCTION_CLAUSES.lock() {
+		Ok(clauses) => clauses.clone(),
+		Err(poisoned) => poisoned.into_inner().clone(),
+	};
+	let variables_before = match VARIABLES.lock() {
+		Ok(variables) => variables.clone(),
+		Err(poisoned) => poisoned.into_inner().clone(),
+	};
+	let lists_before = match LIST_VARIABLES.lock() {
+		Ok(lists) => lists.clone(),
+		Err(poisoned) => poisoned.into_inner().clone(),
+	};
+
+	run(&program);
+
+	// Anything new or changed counts as defined by the file. Anonymous functions are left
+	// out, since the file can't refer to them by name.
+	let mut functions: Vec<String> = match FUNCTIONS.lock() {
+		Ok(functions) => functions.clone(),
+		Err(poisoned) => poisoned.into_inner().clone(),
+	}
+	.into_iter()
+	.filter(|(name, function)| {
+		functions_before.get(name) != Some(function) && *name != anonymous_function_name(function)
+	})
+	.map(|(name, _)| name)
+	.collect();
+	let clauses = match FUNCTION_CLAUSES.lock() {
+		Ok(clauses) => clauses.clone(),
+		Err(poisoned) => poisoned.into_inner().clone(),
+	};
+	for (name, function_clauses) in clauses {
+		if clauses_before.get(&name) != Some(&function_clauses) && !functions.contains(&name) {
+			functions.push(name);
+		}
+	}
+	let mut variables: Vec<String> = match VARIABLES.lock() {
+		Ok(variables) => variables.clone(),
+		Err(poisoned) => poisoned.into_inner().clone(),
+	}
+	.into_iter()
+	.filter(|(name, value)| variables_before.get(name) != Some(value))
+	.map(|(name, _)| name)
+	.collect();
+	let lists = match LIST_VARIABLES.lock() {
+		Ok(lists) => lists.clone(),
+		Err(poisoned) => poisoned.into_inner().clone(),
+	};
+	variables.extend(
+		lists
+			.into_iter()
+			.filter(|(name, items)| lists_before.get(name) != Some(items))
+			.map(|(name, _)| name),
+	);
+
+	// List the names in the order the file first mentions them
+	let tokens = lex(&program);
+	let first_mention = |name: &String| {
+		tokens
+			.iter()
+			.position(|token| matches!(token, Token::Symbol(symbol) if symbol.value == *name))
+	};
+	functions.sort_by_key(first_mention);
+	variables.sort_by_key(first_mention);
+
+	Ok(format!(
+		"loaded {} and {}",
+		count_names(&functions, "function"),
+		count_names(&variables, "variable")
+	))
+}
+
+/// Count names for a summary, listing them after the count: `2 functions (add, sub)`
+fn count_names(names: &[String], noun: &str) -> String {
+	match names.len() {
+		0 => format!("0 {}s", noun),
+		1 => format!("1 {} ({})", noun, names[0]),
+		count => format!("{} {}s ({})", count, noun, names.join(", ")),
+	}
+}
+
+/// Move the named function definitions of a block in front of its other statements, keeping
+/// their order, so a statement can call a function defined further down
+fn hoist_function_definitions(block: &mut LangBlock) {
+	let (definitions, statements): (Vec<_>, Vec<_>) = std::mem::take(&mut block.items)
+		.into_iter()
+		.partition(|item| matches!(item, parse::LangBlockItem::NamedFunction(_)));
+	block.items = definitions;
+	block.items.extend(statements);
+}
+
+/// Split the arguments of `:run <function> [args...]` into the function's name and the
+/// numbers to call it with
+fn parse_run_arguments(args: &str) -> Result<(&str, Vec<f64>), String> {
+	let mut parts = args.split_whitespace();
+	let name = parts
+		.next()
+		.ok_or("Expected the name of a function to run")?;
+	let arg_values = parts
+		.map(|part| {
+			part.parse::<f64>()
+				.map_err(|_| format!("Invalid argument '{}': all arguments must be numbers", part))
+		})
+		.collect::<Result<_, _>>()?;
+	Ok((name, arg_values))
+}
+
+/// Call a function with numeric arguments for `:run`, compiled or interpreted just as a call
+/// in an expression would be
+fn run_function(name: &str, args: &[f64]) -> Result<f64, Box<dyn Error>> {
+	let arguments = args
+		.iter()
+		.map(|&value| {
+			vec![Token::Number(lex::LangNumber::RealNumber(
+				lex::LangRealNumber { value },
+			))]
+		})
+		.collect();
+	execute_function_call(&parse::LangFunctionCall {
+		name: name.to_string(),
+		arguments,
+	})
 }
 
 /// Create an executable binary from a user-defined function
@@ -1566,9 +4460,8 @@ fn create_executable_from_function(
 
 	let function = function_opt.ok_or(format!("Function '{}' not found", function_name))?;
 
-	// Create LLVM context and code generator for binary generation
-	let context = Context::create();
-	let mut codegen = LLVMCodeGen::new_for_binary_gen(&context)?;
+	// Create code generator for binary generation
+	let mut codegen = LLVMCodeGen::new_for_binary_gen(llvm_context())?;
 
 	// Compile the user function to LLVM IR
 	codegen.compile_function(function_name, &function)?;
@@ -1595,28 +4488,65 @@ fn try_evaluate_as_constant(block: &parse::LangBlock) -> Option<f64> {
 
 			if !has_variables {
 				// Try to evaluate the expression directly
-				let unary_processed = preprocess_unary_minus(&line.tokens);
-				let postfix = infix_to_postfix(&unary_processed);
-				return execute_postfix_tokens(&postfix).ok().flatten();
+				return evaluate_tokens(&line.tokens).ok().flatten();
 			}
 		}
 	}
 	None
 }
 
+/// JIT-evaluate an expression and also return the LLVM IR generated for it.
+/// Note that the IR builder folds operations on constants, so e.g. `2 + 3` shows up as `5`.
+fn compile_expr_ir(input: &str) -> Result<(f64, String), Box<dyn Error>> {
+	// Parse the expression
+	let block = parse::parse_tokens(lex_within_limit(input)?)?;
+
+	// Compile the expression as the body of a parameterless function
+	let context = llvm_context();
+	let mut codegen = LLVMCodeGen::new(context)?;
+	let fn_type = codegen.float_type.fn_type(&[], false);
+	let function = codegen.module.add_function("expr", fn_type, None);
+	let basic_block = context.append_basic_block(function, "entry");
+	codegen.builder.position_at_end(basic_block);
+
+	let empty_vars = HashMap::new();
+	let result = codegen.compile_block(&block, &empty_vars)?;
+	codegen.builder.build_return(Some(&result)).unwrap();
+
+	let ir = codegen.module.print_to_string().to_string();
+
+	// Run the compiled expression
+	let value = unsafe {
+		type ExprFunc = unsafe extern "C" fn() -> f64;
+		let jit_fn: inkwell::execution_engine::JitFunction<ExprFunc> =
+			codegen.execution_engine.get_function("expr")?;
+		jit_fn.call()
+	};
+
+	Ok((jit_result(value)?, ir))
+}
+
+/// Check the result of running compiled code. Compiled division returns NaN when dividing by
+/// zero, so NaN is reported as that error; infinities are ordinary results.
+fn jit_result(value: f64) -> Result<f64, Box<dyn Error>> {
+	if value.is_nan() {
+		Err("Division by zero".into())
+	} else {
+		Ok(value)
+	}
+}
+
 /// Create a simple executable that evaluates an expression
 fn create_executable_from_expression(
 	expression: &str,
 	output_name: &str,
 ) -> Result<(), Box<dyn Error>> {
 	// Parse the expression
-	let tokens = lex(expression);
-	let mut token_iter = tokens.into_iter().peekable();
-	let block = parse_block(&mut token_iter);
+	let block = parse::parse_tokens(lex_within_limit(expression)?)?;
 
-	// Create LLVM context and code generator
-	let context = Context::create();
-	let mut codegen = LLVMCodeGen::new_for_binary_gen(&context)?;
+	// Create code generator
+	let context = llvm_context();
+	let mut codegen = LLVMCodeGen::new_for_binary_gen(context)?;
 
 	// Create main function that evaluates the expression and returns the result
 	let i32_type = context.i32_type();
@@ -1626,25 +4556,12 @@ fn create_executable_from_expression(
 	let basic_block = context.append_basic_block(main_function, "entry");
 	codegen.builder.position_at_end(basic_block);
 
-	// Declare printf function
-	let printf_fn = codegen.declare_printf();
-
-	// Create format string for printing floating point numbers
-	let format_ptr = codegen.create_global_string_ptr("%.15g\n", "fmt_float");
-
 	// Try to compile the expression
 	let empty_vars = HashMap::new();
 	match codegen.compile_block(&block, &empty_vars) {
 		Ok(result) => {
 			// Expression compiled successfully, print the result
-			codegen
-				.builder
-				.build_call(
-					printf_fn,
-					&[format_ptr.into(), result.into()],
-					"printf_call",
-				)
-				.unwrap();
+			codegen.build_print_result(result);
 
 			let return_val = i32_type.const_int(0, false);
 			codegen.builder.build_return(Some(&return_val)).unwrap();
@@ -1657,14 +4574,7 @@ fn create_executable_from_expression(
 			if let Some(result_value) = try_evaluate_as_constant(&block) {
 				// Create a constant with the result and print it
 				let result_const = codegen.float_type.const_float(result_value);
-				codegen
-					.builder
-					.build_call(
-						printf_fn,
-						&[format_ptr.into(), result_const.into()],
-						"printf_call",
-					)
-					.unwrap();
+				codegen.build_print_result(result_const);
 
 				let return_val = i32_type.const_int(0, false);
 				codegen.builder.build_return(Some(&return_val)).unwrap();
@@ -1682,14 +4592,88 @@ fn create_executable_from_expression(
 	Ok(())
 }
 
+/// Evaluates a program one top-level statement at a time, for `:step`
+struct Stepper {
+	statements: std::vec::IntoIter<parse::LangBlockItem>,
+}
+
+/// A statement run by `Stepper` and what came of it
+#[derive(Debug, Clone, PartialEq)]
+struct Step {
+	/// The statement, reconstructed from its syntax tree
+	source: String,
+	/// The statement's value, if it has one
+	result: Option<f64>,
+	/// Whether evaluating the statement reported an error
+	failed: bool,
+}
+
+impl Stepper {
+	/// Parse a whole program up front, so syntax errors show before anything runs
+	fn new(program: &str) -> Result<Self, parse::ParseError> {
+		Ok(Stepper {
+			statements: parse_program(program)?.items.into_iter(),
+		})
+	}
+
+	/// Source of the statement the next step will run
+	fn next_source(&self) -> Option<String> {
+		self.statements
+			.as_slice()
+			.first()
+			.map(|item| statement_source(item.clone()))
+	}
+}
+
+impl Iterator for Stepper {
+	type Item = Step;
+
+	fn next(&mut self) -> Option<Step> {
+		let item = self.statements.next()?;
+		let source = statement_source(item.clone());
+		ERROR_REPORTED.store(false, AtomicOrdering::Relaxed);
+		let result = eval_block(&LangBlock { items: vec![item] });
+		Some(Step {
+			source,
+			result,
+			failed: ERROR_REPORTED.load(AtomicOrdering::Relaxed),
+		})
+	}
+}
+
+/// Source text of a single top-level statement
+fn statement_source(item: parse::LangBlockItem) -> String {
+	let block = LangBlock { items: vec![item] };
+	parse::block_to_source(&block, 0).trim_end().to_string()
+}
+
+/// Evaluate a whole program read from stdin, printing results as the REPL would
+fn run_stdin() -> std::io::Result<()> {
+	let mut input = String::new();
+	std::io::stdin().read_to_string(&mut input)?;
+	run(&input);
+	Ok(())
+}
+
 fn main() {
+	// Piped input (no TTY attached) is evaluated as a program instead of starting the REPL
+	if !std::io::stdin().is_terminal() {
+		if let Err(e) = run_stdin() {
+			eprintln!("Error reading stdin: {}", e);
+			std::process::exit(1);
+		}
+		return;
+	}
+
 	println!("Fast Calculator");
 	println!("===============");
 	println!("Features:");
-	println!("  • Basic arithmetic: 2 + 3 * 4");
+	println!("  • Basic arithmetic: 2 + 3 * 4, 2 ^ 10, -2 ^ 2 (= -(2 ^ 2))");
 	println!("  • Variables: x = 5; y = x * 2");
 	println!("  • Functions: fn increment(x) {{ x + 1 }}");
 	println!("  • Function calls: increment(5)");
+	println!("  • Conditionals: if (x < 0) {{ -x }} else {{ x }}, x < 0 ? -x : x, a && b || c");
+	println!("  • Built-in functions: sqrt(2), min(a, b), ... (list them with :builtins)");
 	println!("  • Binary generation: :compile <function_name> <output_name> [args...]");
 	println!("  • Expression compilation: :compile_expr <expression> <output_name>");
 	println!("");
@@ -1697,82 +4681,757 @@ fn main() {
 	let _ = repl();
 }
 
+/// Names offered by tab completion for a partially typed word: built-ins and user functions
+fn completion_candidates(prefix: &str) -> Vec<String> {
+	if prefix.is_empty() {
+		return Vec::new();
+	}
+	let mut candidates: Vec<String> = builtins::builtin_names().map(String::from).collect();
+	match FUNCTIONS.lock() {
+		Ok(functions) => candidates.extend(functions.keys().cloned()),
+		Err(poisoned) => candidates.extend(poisoned.into_inner().keys().cloned()),
+	}
+	candidates.retain(|name| name.starts_with(prefix));
+	candidates.sort();
+	candidates.dedup();
+	candidates
+}
+
+/// Line editor helper providing tab completion of function names
+struct ReplHelper;
+
+impl rustyline::completion::Completer for ReplHelper {
+	type Candidate = String;
+
+	fn complete(
+		&self,
+		line: &str,
+		pos: usize,
+		_ctx: &rustyline::Context<'_>,
+	) -> rustyline::Result<(usize, Vec<String>)> {
+		// Complete the word that ends at the cursor
+		let start = line[..pos]
+			.char_indices()
+			.rev()
+			.find(|(_, ch)| !ch.is_alphanumeric() && *ch != '_')
+			.map_or(0, |(i, ch)| i + ch.len_utf8());
+		Ok((start, completion_candidates(&line[start..pos])))
+	}
+}
+
+impl rustyline::hint::Hinter for ReplHelper {
+	type Hint = String;
+}
+
+impl rustyline::highlight::Highlighter for ReplHelper {}
+
+impl rustyline::validate::Validator for ReplHelper {}
+
+impl rustyline::Helper for ReplHelper {}
+
+/// What the REPL is waiting for, which decides the prompt shown
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PromptState {
+	/// Ready for new input
+	Normal,
+	/// Inside a block or parentheses left open on a previous line
+	Continuation,
+	/// Ready for new input after the previous input reported an error
+	AfterError,
+}
+
+/// Select the prompt for the REPL state
+fn prompt_for(state: PromptState) -> &'static str {
+	match state {
+		PromptState::Normal => ">> ",
+		PromptState::Continuation => "... ",
+		PromptState::AfterError => "!> ",
+	}
+}
+
+/// Check if input leaves a block or parentheses open, so more lines are needed
+fn needs_continuation(input: &str) -> bool {
+	let mut depth = 0;
+	for token in lex(input) {
+		if let Token::Operator(op) = token {
+			match op.value.as_str() {
+				"(" | "{" => depth += 1,
+				")" | "}" => depth -= 1,
+				_ => {}
+			}
+		}
+	}
+	depth > 0
+}
+
+/// File the REPL's line history is kept in between sessions
+const HISTORY_FILE: &str = "repl_history.txt";
+
+/// Most history entries kept, the oldest are dropped first
+const HISTORY_LIMIT: usize = 1000;
+
+/// Line editor settings, with history capped at `HISTORY_LIMIT` entries both when it's loaded
+/// and as lines are added
+fn editor_config() -> rustyline::Result<rustyline::Config> {
+	Ok(rustyline::Config::builder()
+		.max_history_size(HISTORY_LIMIT)?
+		.build())
+}
+
+/// Line editor used by the REPL
+type ReplEditor = rustyline::Editor<ReplHelper, rustyline::history::DefaultHistory>;
+
+/// What the REPL does after running a command
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CommandOutcome {
+	/// Read the next line
+	Done,
+	/// Show the command's usage, since its arguments were not understood
+	ShowUsage,
+	/// Leave the REPL
+	Quit,
+}
+
+/// A REPL command, run by typing `:name` followed by its arguments
+struct ReplCommand {
+	/// Name typed after the `:`
+	name: &'static str,
+	/// The command with its arguments, as shown by `:help` and on misuse
+	usage: &'static str,
+	/// One-line summary for `:help`
+	description: &'static str,
+	/// Run the command with the text after its name
+	run: fn(&str, &mut ReplEditor) -> CommandOutcome,
+}
+
+impl ReplCommand {
+	/// Whether the usage has a required `<argument>`, so the command can't run without one
+	fn requires_arguments(&self) -> bool {
+		self.usage.contains(" <")
+	}
+}
+
+/// Parse an `on|off` argument
+fn parse_switch(arg: &str) -> Option<bool> {
+	match arg {
+		"on" => Some(true),
+		"off" => Some(false),
+		_ => None,
+	}
+}
+
+/// All REPL commands, in the order `:help` lists them
+static REPL_COMMANDS: &[ReplCommand] = &[
+	ReplCommand {
+		name: "compile",
+		usage: ":compile <function_name> <output_name> [args...]",
+		description: "Compile function to executable",
+		run: |args, _| {
+			let parts: Vec<&str> = args.split_whitespace().collect();
+			if parts.len() < 2 {
+				return CommandOutcome::ShowUsage;
+			}
+			let args: Result<Vec<f64>, _> = parts[2..].iter().map(|s| s.parse()).collect();
+			match args {
+				Ok(arg_values) => {
+					match create_executable_from_function(parts[0], parts[1], &arg_values) {
+						Ok(_) => println!("✓ Executable created successfully"),
+						Err(e) => println!("✗ Error creating executable: {}", e),
+					}
+				}
+				Err(_) => {
+					println!("Error: Invalid argument values. All arguments must be numbers.");
+				}
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "run",
+		usage: ":run <function_name> [args...]",
+		description: "Run a function with the given arguments",
+		run: |args, _| {
+			let result = parse_run_arguments(args)
+				.map_err(Box::<dyn Error>::from)
+				.and_then(|(name, arg_values)| run_function(name, &arg_values));
+			match result {
+				Ok(result) => println!("{}", format_value(result)),
+				Err(e) => report_error(&format!("Error: {}", e)),
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "compile_expr",
+		usage: ":compile_expr <expression> <output_name>",
+		description: "Compile expression to executable",
+		run: |args, _| {
+			let Some((expression, output_name)) = args.split_once(' ') else {
+				return CommandOutcome::ShowUsage;
+			};
+			match create_executable_from_expression(expression, output_name) {
+				Ok(_) => println!("✓ Executable created successfully"),
+				Err(e) => println!("✗ Error creating executable: {}", e),
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "linker",
+		usage: ":linker [<compiler> [flags...]]",
+		description: "Show or set the linker for :compile",
+		run: |args, _| {
+			let mut parts = args.split_whitespace();
+			let mut settings = settings();
+			if let Some(linker) = parts.next() {
+				settings.linker = linker.to_string();
+				settings.linker_flags = parts.map(String::from).collect();
+			}
+			println!(
+				"Linker: {} {}",
+				settings.linker,
+				settings.linker_flags.join(" ")
+			);
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "ir",
+		usage: ":ir <expression>",
+		description: "Show LLVM IR and value of expression",
+		run: |expression, _| {
+			match compile_expr_ir(expression) {
+				Ok((value, ir)) => {
+					println!("{}", ir);
+					println!("{}", format_value(value));
+				}
+				Err(e) => report_error(&format!("Error: {}", e)),
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "explain",
+		usage: ":explain <expression>",
+		description: "Show the evaluation steps of expression",
+		run: |expression, _| {
+			match explain(expression) {
+				Ok(lines) => {
+					for explain_line in lines {
+						println!("{}", explain_line);
+					}
+				}
+				Err(e) => report_error(&format!("Error: {}", e)),
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "precision",
+		usage: ":precision <decimal_places>",
+		description: "Set maximum decimal places shown",
+		run: |arg, _| {
+			let Ok(precision) = arg.trim().parse::<usize>() else {
+				return CommandOutcome::ShowUsage;
+			};
+			settings().precision = precision;
+			println!("Precision set to {} decimal places", precision);
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "rounding",
+		usage: ":rounding nearest|up|down|toward_zero",
+		description: "Set how shown results are rounded",
+		run: |arg, _| {
+			let rounding = match arg.trim() {
+				"nearest" => RoundingMode::Nearest,
+				"up" => RoundingMode::Up,
+				"down" => RoundingMode::Down,
+				"toward_zero" => RoundingMode::TowardZero,
+				_ => return CommandOutcome::ShowUsage,
+			};
+			settings().rounding = rounding;
+			println!("Results are now rounded {}", arg.trim().replace('_', " "));
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "intdiv",
+		usage: ":intdiv float|floor",
+		description: "Set whether integer / gives a real or floors (variables hold reals)",
+		run: |arg, _| {
+			let integer_division = match arg.trim() {
+				"float" => IntegerDivision::Float,
+				"floor" => IntegerDivision::Floor,
+				_ => return CommandOutcome::ShowUsage,
+			};
+			settings().integer_division = integer_division;
+			match integer_division {
+				IntegerDivision::Float => println!("Dividing integers now gives a real number"),
+				IntegerDivision::Floor => println!(
+					"Dividing integers now rounds down; variables and parameters hold reals, \
+					so dividing them stays exact"
+				),
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "builtins",
+		usage: ":builtins",
+		description: "List built-in functions",
+		run: |_, _| {
+			println!("Built-in functions:");
+			println!("{}", format_builtins());
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "functions",
+		usage: ":functions",
+		description: "List functions as compiled or runtime",
+		run: |_, _| {
+			for (name, status) in function_status() {
+				let status = match status {
+					FunctionStatus::Compiled => "compiled",
+					FunctionStatus::Runtime => "runtime",
+				};
+				println!("  {:<16} {}", name, status);
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "graph",
+		usage: ":graph",
+		description: "Show which functions call which",
+		run: |_, _| {
+			let graph = call_graph();
+			if graph.is_empty() {
+				println!("No functions defined");
+			} else {
+				println!("{}", format_call_graph(&graph));
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "history",
+		usage: ":history clear",
+		description: "Clear the saved line history",
+		run: |arg, rl| {
+			if arg.trim() != "clear" {
+				return CommandOutcome::ShowUsage;
+			}
+			// Forget this session's lines and the saved ones from earlier sessions
+			let cleared = rl
+				.clear_history()
+				.and_then(|_| rl.save_history(HISTORY_FILE));
+			match cleared {
+				Ok(()) => println!("History cleared"),
+				Err(e) => report_error(&format!("Error: {}", e)),
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "step",
+		usage: ":step <path>",
+		description: "Run a script one statement at a time",
+		run: |path, rl| {
+			let stepper = fs::read_to_string(path.trim())
+				.map_err(|e| format!("Error reading {}: {}", path.trim(), e))
+				.and_then(|program| Stepper::new(&program).map_err(|e| format!("Error: {}", e)));
+			match stepper {
+				Ok(mut stepper) => {
+					let mut number = 1;
+					while let Some(source) = stepper.next_source() {
+						println!("[{}] {}", number, source);
+						match rl.readline("(Enter to run, q to stop) ") {
+							Ok(answer) if answer.trim() != "q" => {}
+							_ => break,
+						}
+						stepper.next();
+						number += 1;
+					}
+					println!("Stepping finished");
+				}
+				Err(e) => report_error(&e),
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "load",
+		usage: ":load <path>",
+		description: "Run a file and list what it defined",
+		run: |path, _| {
+			match load_file(path.trim()) {
+				Ok(summary) => println!("{}", summary),
+				Err(e) => report_error(&e.to_string()),
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "source",
+		usage: ":source <function_name>",
+		description: "Show the source of a function",
+		run: |name, _| {
+			match function_source(name.trim()) {
+				Some(source) => println!("{}", source),
+				None => report_error(&format!("Error: Unknown function '{}'", name.trim())),
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "seed",
+		usage: ":seed <n>",
+		description: "Seed random() for reproducible runs",
+		run: |arg, _| {
+			let Ok(seed) = arg.trim().parse::<u64>() else {
+				return CommandOutcome::ShowUsage;
+			};
+			builtins::seed_random(seed);
+			println!("Random seed set to {}", seed);
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "exact",
+		usage: ":exact on|off",
+		description: "Keep exact fractions for integer arithmetic",
+		run: |arg, _| {
+			let Some(exact) = parse_switch(arg.trim()) else {
+				return CommandOutcome::ShowUsage;
+			};
+			settings().exact = exact;
+			if exact {
+				println!("Exact mode on: integer arithmetic keeps exact fractions");
+			} else {
+				println!("Exact mode off");
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "default",
+		usage: ":default <n>",
+		description: "Set the value of undefined variables",
+		run: |arg, _| {
+			let Ok(value) = arg.trim().parse::<f64>() else {
+				return CommandOutcome::ShowUsage;
+			};
+			settings().undefined_default = value;
+			println!("Undefined variables now read as {}", format_value(value));
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "strict",
+		usage: ":strict on|off",
+		description: "Make undefined variables an error",
+		run: |arg, _| {
+			let Some(strict) = parse_switch(arg.trim()) else {
+				return CommandOutcome::ShowUsage;
+			};
+			settings().strict_variables = strict;
+			if strict {
+				println!("Undefined variables are now an error");
+			} else {
+				println!("Undefined variables read as the :default value");
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "overflow",
+		usage: ":overflow error|promote",
+		description: "Choose how integer overflow is handled",
+		run: |arg, _| {
+			match arg.trim() {
+				"error" => {
+					settings().overflow_errors = true;
+					println!("Integer overflow is now an error");
+				}
+				"promote" => {
+					settings().overflow_errors = false;
+					println!("Integer overflow now gives a real result");
+				}
+				_ => return CommandOutcome::ShowUsage,
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "fastmath",
+		usage: ":fastmath on|off",
+		description: "Compile arithmetic with fast-math flags",
+		run: |arg, _| {
+			let Some(fast_math) = parse_switch(arg.trim()) else {
+				return CommandOutcome::ShowUsage;
+			};
+			settings().fast_math = fast_math;
+			if fast_math {
+				println!("Fast math on: compiled arithmetic may be reordered");
+			} else {
+				println!("Fast math off");
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "typecheck",
+		usage: ":typecheck on|off",
+		description: "Check arguments against parameter types",
+		run: |arg, _| {
+			let Some(strict_types) = parse_switch(arg.trim()) else {
+				return CommandOutcome::ShowUsage;
+			};
+			settings().strict_types = strict_types;
+			if strict_types {
+				println!("Arguments are now checked against parameter types");
+			} else {
+				println!("Parameter types are no longer checked");
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "checknames",
+		usage: ":checknames on|off",
+		description: "Warn about unknown names in new functions",
+		run: |arg, _| {
+			let Some(check_names) = parse_switch(arg.trim()) else {
+				return CommandOutcome::ShowUsage;
+			};
+			settings().check_names = check_names;
+			if check_names {
+				println!("New functions now warn about names that aren't defined");
+			} else {
+				println!("Names in new functions are no longer checked");
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "hoist",
+		usage: ":hoist on|off",
+		description: "Define an input's functions before its statements",
+		run: |arg, _| {
+			let Some(hoist_functions) = parse_switch(arg.trim()) else {
+				return CommandOutcome::ShowUsage;
+			};
+			settings().hoist_functions = hoist_functions;
+			if hoist_functions {
+				println!("Functions are now defined before the statements that use them");
+			} else {
+				println!("Statements and definitions now run in order");
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "lint",
+		usage: ":lint on|off",
+		description: "Warn about expressions that rely on precedence",
+		run: |arg, _| {
+			let Some(precedence_lint) = parse_switch(arg.trim()) else {
+				return CommandOutcome::ShowUsage;
+			};
+			settings().precedence_lint = precedence_lint;
+			if precedence_lint {
+				println!("Expressions relying on precedence now get a warning");
+			} else {
+				println!("Precedence warnings off");
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "echo",
+		usage: ":echo on|off",
+		description: "Print the values that assignments store",
+		run: |arg, _| {
+			let Some(echo_assignments) = parse_switch(arg.trim()) else {
+				return CommandOutcome::ShowUsage;
+			};
+			settings().echo_assignments = echo_assignments;
+			if echo_assignments {
+				println!("Assignments now print the values they store");
+			} else {
+				println!("Assignments print nothing");
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "stats",
+		usage: ":stats [on|off|reset]",
+		description: "Report count, min, max, mean of results",
+		run: |arg, _| {
+			match arg.trim() {
+				"" => {
+					if !settings().record_stats {
+						println!("Recording is off, enable it with :stats on");
+					}
+					println!("{}", format_stats(&result_stats()));
+				}
+				"on" => {
+					settings().record_stats = true;
+					println!("Recording results for :stats");
+				}
+				"off" => {
+					settings().record_stats = false;
+					println!("Stopped recording results");
+				}
+				"reset" => {
+					*result_stats() = ResultStats::EMPTY;
+					println!("Recorded results cleared");
+				}
+				_ => return CommandOutcome::ShowUsage,
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "profile",
+		usage: ":profile on|off",
+		description: "Count operations for each statement",
+		run: |arg, _| {
+			let Some(profile) = parse_switch(arg.trim()) else {
+				return CommandOutcome::ShowUsage;
+			};
+			settings().profile = profile;
+			if profile {
+				println!("Profiling on: operations are counted for each statement");
+			} else {
+				println!("Profiling off");
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "maxtokens",
+		usage: ":maxtokens <n>|off",
+		description: "Limit the number of tokens on a line",
+		run: |arg, _| {
+			match arg.trim() {
+				"off" => {
+					settings().max_tokens = None;
+					println!("Lines may have any number of tokens");
+				}
+				limit => match limit.parse::<usize>() {
+					Ok(limit) => {
+						settings().max_tokens = Some(limit);
+						println!("Lines are now limited to {} tokens", limit);
+					}
+					Err(_) => return CommandOutcome::ShowUsage,
+				},
+			}
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "help",
+		usage: ":help",
+		description: "Show this help",
+		run: |_, _| {
+			print!("{}", help_text());
+			CommandOutcome::Done
+		},
+	},
+	ReplCommand {
+		name: "quit",
+		usage: ":quit",
+		description: "Exit the REPL",
+		run: |_, _| CommandOutcome::Quit,
+	},
+];
+
+/// Look up a REPL command by the name typed after the `:`
+fn find_repl_command(name: &str) -> Option<&'static ReplCommand> {
+	REPL_COMMANDS.iter().find(|command| command.name == name)
+}
+
+/// The `:help` text, listing every REPL command with its usage
+fn help_text() -> String {
+	let mut text = String::from("Available commands:\n");
+	for command in REPL_COMMANDS {
+		text.push_str(&format!(
+			"  {:<48} - {}\n",
+			command.usage, command.description
+		));
+	}
+	text
+}
+
 #[allow(dead_code)]
 fn repl() -> rustyline::Result<()> {
-	let mut rl = rustyline::DefaultEditor::new()?;
-	let _ = rl.load_history("repl_history.txt").is_err();
+	let mut rl = ReplEditor::with_config(editor_config()?)?;
+	rl.set_helper(Some(ReplHelper));
+	let _ = rl.load_history(HISTORY_FILE).is_err();
+	let mut state = PromptState::Normal;
+	let mut pending = String::new();
 	loop {
-		let readline = rl.readline(">> ");
+		let readline = rl.readline(prompt_for(state));
 		match readline {
 			Ok(line) => {
 				let _ = rl.add_history_entry(line.as_str());
 
-				// Check for special commands
-				if line.starts_with(":compile_expr ") {
-					// Parse command: :compile_expr <expression> <output_name>
-					let parts: Vec<&str> = line[14..].splitn(2, ' ').collect();
-					if parts.len() == 2 {
-						let expression = parts[0];
-						let output_name = parts[1];
-						match create_executable_from_expression(expression, output_name) {
-							Ok(_) => println!("✓ Executable created successfully"),
-							Err(e) => println!("✗ Error creating executable: {}", e),
-						}
-					} else {
-						println!("Usage: :compile_expr <expression> <output_name>");
-					}
-				} else if line.starts_with(":compile ") {
-					// Parse command: :compile <function_name> <output_name> [args...]
-					let parts: Vec<&str> = line[9..].split_whitespace().collect();
-					if parts.len() >= 2 {
-						let function_name = parts[0];
-						let output_name = parts[1];
-						let args: Result<Vec<f64>, _> =
-							parts[2..].iter().map(|s| s.parse()).collect();
-
-						match args {
-							Ok(arg_values) => {
-								match create_executable_from_function(
-									function_name,
-									output_name,
-									&arg_values,
-								) {
-									Ok(_) => println!("✓ Executable created successfully"),
-									Err(e) => println!("✗ Error creating executable: {}", e),
-								}
-							}
-							Err(_) => {
-								println!(
-									"Error: Invalid argument values. All arguments must be numbers."
-								);
+				// Keep reading while a block or parentheses are left open
+				if !pending.is_empty() {
+					pending.push('\n');
+				}
+				pending.push_str(&line);
+				if !pending.starts_with(':') && needs_continuation(&pending) {
+					state = PromptState::Continuation;
+					continue;
+				}
+				let line = std::mem::take(&mut pending);
+				ERROR_REPORTED.store(false, AtomicOrdering::Relaxed);
+
+				if let Some(command_line) = line.strip_prefix(':') {
+					let (name, args) = command_line
+						.split_once(char::is_whitespace)
+						.unwrap_or((command_line, ""));
+					match find_repl_command(name) {
+						Some(command) => {
+							let args = args.trim_start();
+							let outcome = if command.requires_arguments() && args.is_empty() {
+								CommandOutcome::ShowUsage
+							} else {
+								(command.run)(args, &mut rl)
+							};
+							match outcome {
+								CommandOutcome::Done => {}
+								CommandOutcome::ShowUsage => println!("Usage: {}", command.usage),
+								CommandOutcome::Quit => break,
 							}
 						}
-					} else {
-						println!("Usage: :compile <function_name> <output_name> [args...]");
+						None => {
+							report_error(&format!("Error: Unknown command ':{}', see :help", name))
+						}
 					}
-				} else if line.starts_with(":help") {
-					println!("Available commands:");
-					println!(
-						"  :compile <function_name> <output_name> [args...]  - Compile function to executable"
-					);
-					println!(
-						"  :compile_expr <expression> <output_name>         - Compile expression to executable"
-					);
-					println!("  :help                                            - Show this help");
-					println!("  :quit                                            - Exit the REPL");
-				} else if line.starts_with(":quit") {
-					break;
 				} else {
 					// Regular expression evaluation
 					let _result = run(line.as_str());
 				}
+
+				state = if ERROR_REPORTED.load(AtomicOrdering::Relaxed) {
+					PromptState::AfterError
+				} else {
+					PromptState::Normal
+				};
 			}
 			Err(_) => {
 				break;
 			}
 		}
 	}
-	let _ = rl.save_history("repl_history.txt");
+	let _ = rl.save_history(HISTORY_FILE);
 	Ok(())
 }
 
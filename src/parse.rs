@@ -3,51 +3,239 @@ use std::fmt;
 use std::iter::Peekable;
 use std::vec::IntoIter;
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct LangLine {
 	pub tokens: Vec<lex::Token>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct LangBlock {
 	pub items: Vec<LangBlockItem>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct LangFunction {
 	pub parameters: Vec<String>,
+	pub parameter_types: Vec<Option<String>>, // Annotation of each parameter, like `x: int`
 	pub body: LangBlock,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct LangNamedFunction {
 	pub name: String,
 	pub parameters: Vec<String>,
+	pub parameter_types: Vec<Option<String>>, // Annotation of each parameter, like `x: int`
 	pub body: LangBlock,
+	pub guard: Option<Vec<lex::Token>>, // Condition after `when` for a guarded clause
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct LangFunctionCall {
 	pub name: String,
 	pub arguments: Vec<Vec<lex::Token>>, // Each argument is a list of tokens forming an expression
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
+pub struct LangList {
+	pub items: Vec<Vec<lex::Token>>, // Each item is a list of tokens forming an expression
+}
+
+#[derive(Clone, PartialEq)]
+pub struct LangIf {
+	pub condition: Vec<lex::Token>,
+	pub then_block: LangBlock,
+	pub else_block: Option<LangBlock>, // An `else if` is an If item nested in this block
+	pub assign_to: Option<String>,     // Variable given the chosen branch's value, for `y = if ...`
+}
+
+#[derive(Clone, PartialEq)]
+pub struct LangMatchArm {
+	pub pattern: Option<lex::LangNumber>, // Case literal, or None for the `_` default
+	pub body: LangBlock,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct LangMatch {
+	pub subject: Vec<lex::Token>,
+	pub arms: Vec<LangMatchArm>,
+}
+
+#[derive(Clone, PartialEq)]
 pub enum LangBlockItem {
 	Line(LangLine),
 	Block(LangBlock),
 	Function(LangFunction),
 	NamedFunction(LangNamedFunction),
 	FunctionCall(LangFunctionCall),
+	List(LangList),
+	If(LangIf),
+	Match(LangMatch),
 }
 
+/// Types a parameter can be annotated with
+pub const PARAMETER_TYPES: &[&str] = &["int", "real"];
+
+/// Error found while parsing, such as unbalanced braces
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+	pub message: String,
+}
+
+impl ParseError {
+	fn new(message: &str) -> Self {
+		ParseError {
+			message: message.to_string(),
+		}
+	}
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.message)
+	}
+}
+
+impl std::error::Error for ParseError {}
+
 pub struct DisplayBlock<'a> {
 	block: &'a LangBlock,
 	indent_level: usize,
 }
 
-pub fn parse_block(tokens: &mut Peekable<IntoIter<lex::Token>>) -> LangBlock {
-	let mut block_items: Vec<LangBlockItem> = Vec::new();
+/// Check that no line of lexed input has more than `limit` tokens
+pub fn check_token_limit(tokens: &[lex::Token], limit: usize) -> Result<(), ParseError> {
+	let lines = tokens.split(|token| matches!(token, lex::Token::Operator(op) if op.value == "\n"));
+	for (number, line) in lines.enumerate() {
+		if line.len() > limit {
+			return Err(ParseError::new(&format!(
+				"Line {} has {} tokens, more than the limit of {}",
+				number + 1,
+				line.len(),
+				limit
+			)));
+		}
+	}
+	Ok(())
+}
+
+/// Check that every `[` is closed by a `]` before the bracket around it closes. List
+/// literals and index expressions are built from the brackets only after parsing, so a
+/// malformed one is reported here instead of surfacing as a confusing evaluation error.
+pub fn check_brackets(tokens: &[lex::Token]) -> Result<(), ParseError> {
+	let mut open: Vec<&str> = Vec::new();
+	for token in tokens {
+		let lex::Token::Operator(op) = token else {
+			continue;
+		};
+		match op.value.as_str() {
+			"(" | "[" => open.push(&op.value),
+			"]" if open.pop() != Some("[") => {
+				return Err(ParseError::new("Syntax error: unexpected ']'"));
+			}
+			")" if open.pop() == Some("[") => {
+				return Err(ParseError::new("Syntax error: missing ']'"));
+			}
+			_ => {}
+		}
+	}
+	match open.contains(&"[") {
+		true => Err(ParseError::new("Syntax error: missing ']'")),
+		false => Ok(()),
+	}
+}
+
+/// Lex and parse source text into its syntax tree, without evaluating anything
+pub fn parse_program(input: &str) -> Result<LangBlock, ParseError> {
+	parse_tokens(lex::lex(input))
+}
+
+/// Parse already lexed source text into its syntax tree
+pub fn parse_tokens(tokens: Vec<lex::Token>) -> Result<LangBlock, ParseError> {
+	check_brackets(&tokens)?;
+	parse_top_level_block(&mut tokens.into_iter().peekable())
+}
+
+/// Parse a whole lexed program like `parse_tokens`, but carry on past malformed statements.
+/// Each one is recorded as a diagnostic and skipped up to the next `;`, newline or `}` outside
+/// any braces it opened, so the statements around it still make it into the returned block.
+pub fn parse_program_with_recovery(tokens: Vec<lex::Token>) -> (LangBlock, Vec<ParseError>) {
+	let mut diagnostics: Vec<ParseError> = check_brackets(&tokens).err().into_iter().collect();
+	let all_tokens = tokens.clone();
+	let mut tokens = tokens.into_iter().peekable();
+	let mut items = Vec::new();
+
+	loop {
+		let start = all_tokens.len() - tokens.len();
+		match parse_items(&mut tokens, &mut items) {
+			Ok(false) => break,
+			Ok(true) => diagnostics.push(ParseError::new("Syntax error: unmatched '}'")),
+			Err(e) => {
+				diagnostics.push(e);
+				// The statements parsed before the error are balanced, so the braces still
+				// open are those of the failing statement. Skip the rest of them along with
+				// it, so the lines of its body aren't mistaken for top-level statements.
+				let consumed = &all_tokens[start..all_tokens.len() - tokens.len()];
+				let mut brace_depth = consumed
+					.iter()
+					.map(|token| match token {
+						lex::Token::Operator(op) if op.value == "{" => 1,
+						lex::Token::Operator(op) if op.value == "}" => -1,
+						_ => 0,
+					})
+					.sum::<i32>()
+					.max(0);
+				for token in tokens.by_ref() {
+					let lex::Token::Operator(op) = &token else {
+						continue;
+					};
+					match op.value.as_str() {
+						"{" => brace_depth += 1,
+						"}" if brace_depth > 0 => brace_depth -= 1,
+						"\n" | ";" | "}" if brace_depth == 0 => break,
+						_ => {}
+					}
+				}
+			}
+		}
+	}
+	(LangBlock { items }, diagnostics)
+}
+
+/// Parse the rest of a nested block after its opening `{`, up to the matching `}`
+pub fn parse_block(tokens: &mut Peekable<IntoIter<lex::Token>>) -> Result<LangBlock, ParseError> {
+	match parse_block_contents(tokens)? {
+		(block, true) => Ok(block),
+		(_, false) => Err(ParseError::new("Syntax error: missing '}'")),
+	}
+}
+
+/// Parse a whole program. Unlike `parse_block`, which stops at the `}` closing a nested
+/// block, a `}` here has no matching `{` and is reported instead of dropping the rest.
+pub fn parse_top_level_block(
+	tokens: &mut Peekable<IntoIter<lex::Token>>,
+) -> Result<LangBlock, ParseError> {
+	match parse_block_contents(tokens)? {
+		(block, false) => Ok(block),
+		(_, true) => Err(ParseError::new("Syntax error: unmatched '}'")),
+	}
+}
+
+/// Parse block items until a closing `}` or the end of input, returning the block and
+/// whether it ended at a `}`
+fn parse_block_contents(
+	tokens: &mut Peekable<IntoIter<lex::Token>>,
+) -> Result<(LangBlock, bool), ParseError> {
+	let mut items = Vec::new();
+	let closed = parse_items(tokens, &mut items)?;
+	Ok((LangBlock { items }, closed))
+}
+
+/// Parse items into `block_items` until a closing `}` or the end of input, returning whether
+/// it stopped at a `}`. Items parsed before an error are kept, for recovery.
+fn parse_items(
+	tokens: &mut Peekable<IntoIter<lex::Token>>,
+	block_items: &mut Vec<LangBlockItem>,
+) -> Result<bool, ParseError> {
 	let mut current_line_tokens: Vec<lex::Token> = Vec::new();
 
 	while let Some(token) = tokens.next() {
@@ -62,13 +250,15 @@ pub fn parse_block(tokens: &mut Peekable<IntoIter<lex::Token>>) -> LangBlock {
 						// Expect opening parenthesis
 						if let Some(lex::Token::Operator(op)) = tokens.next() {
 							if op.value == "(" {
-								// Parse parameters
-								let parameters = parse_function_parameters_until_paren(tokens);
+								// Parse parameters and the optional `when` guard
+								let (parameters, parameter_types) =
+									parse_function_parameters_until_paren(tokens)?;
+								let guard = parse_guard(tokens)?;
 
 								// Expect opening brace
 								if let Some(lex::Token::Operator(brace)) = tokens.next() {
 									if brace.value == "{" {
-										let body = parse_block(tokens);
+										let body = parse_block(tokens)?;
 
 										if !current_line_tokens.is_empty() {
 											let lang_line = LangLine {
@@ -82,7 +272,9 @@ pub fn parse_block(tokens: &mut Peekable<IntoIter<lex::Token>>) -> LangBlock {
 										let named_function = LangNamedFunction {
 											name: function_name,
 											parameters,
+											parameter_types,
 											body,
+											guard,
 										};
 										block_items
 											.push(LangBlockItem::NamedFunction(named_function));
@@ -95,6 +287,30 @@ pub fn parse_block(tokens: &mut Peekable<IntoIter<lex::Token>>) -> LangBlock {
 					// If we get here, it wasn't a valid function, treat as regular token
 					current_line_tokens.push(token);
 				}
+				// Check if this is an if statement: if (condition) { ... } else { ... }
+				else if symbol.value == "if" && current_line_tokens.is_empty() {
+					block_items.push(LangBlockItem::If(parse_if(tokens)?));
+				}
+				// Check if this is a match statement: match subject { 1 => { ... }; _ => { ... } }
+				else if symbol.value == "match"
+					&& current_line_tokens.is_empty()
+					&& starts_match(tokens)
+				{
+					block_items.push(LangBlockItem::Match(parse_match(tokens)?));
+				}
+				// Check if this is an if used as a value: symbol = if (condition) { ... } else { ... }
+				else if current_line_tokens.is_empty() && assigns_if(tokens) {
+					tokens.next(); // consume '='
+					tokens.next(); // consume 'if'
+					let mut if_item = parse_if(tokens)?;
+					if if_item.else_block.is_none() {
+						return Err(ParseError::new(
+							"Syntax error: an if used as a value needs an else branch",
+						));
+					}
+					if_item.assign_to = Some(symbol.value.clone());
+					block_items.push(LangBlockItem::If(if_item));
+				}
 				// Check if this is a function assignment: symbol = (params) => { body }
 				else if let Some(lex::Token::Operator(op)) = tokens.peek() {
 					if op.value == "=" {
@@ -149,7 +365,8 @@ pub fn parse_block(tokens: &mut Peekable<IntoIter<lex::Token>>) -> LangBlock {
 							tokens.next(); // consume '('
 
 							// Parse function parameters and body
-							let parameters = parse_function_parameters_until_paren(tokens);
+							let (parameters, parameter_types) =
+								parse_function_parameters_until_paren(tokens)?;
 
 							// Expect '=>'
 							if let Some(lex::Token::Operator(arrow)) = tokens.next() {
@@ -157,7 +374,7 @@ pub fn parse_block(tokens: &mut Peekable<IntoIter<lex::Token>>) -> LangBlock {
 									// Expect '{'
 									if let Some(lex::Token::Operator(brace)) = tokens.next() {
 										if brace.value == "{" {
-											let body = parse_block(tokens);
+											let body = parse_block(tokens)?;
 
 											if !current_line_tokens.is_empty() {
 												let lang_line = LangLine {
@@ -171,7 +388,9 @@ pub fn parse_block(tokens: &mut Peekable<IntoIter<lex::Token>>) -> LangBlock {
 											let named_function = LangNamedFunction {
 												name: symbol.value.clone(),
 												parameters,
+												parameter_types,
 												body,
+												guard: None,
 											};
 											block_items
 												.push(LangBlockItem::NamedFunction(named_function));
@@ -191,8 +410,9 @@ pub fn parse_block(tokens: &mut Peekable<IntoIter<lex::Token>>) -> LangBlock {
 						}
 					} else if op.value == "(" {
 						// This could be a function call
-						// If we have accumulated tokens, treat this as part of an expression
-						if !current_line_tokens.is_empty() {
+						// If we have accumulated tokens, or more follow the call on this line,
+						// treat this as part of an expression
+						if !current_line_tokens.is_empty() || !call_ends_line(tokens) {
 							// Add the symbol and parentheses as tokens to the current expression
 							current_line_tokens.push(token);
 							current_line_tokens.push(tokens.next().unwrap()); // consume the '('
@@ -271,14 +491,14 @@ pub fn parse_block(tokens: &mut Peekable<IntoIter<lex::Token>>) -> LangBlock {
 				if found_arrow {
 					// This is a function - parse it
 					lookahead_tokens.extend(temp_tokens.clone());
-					let parameters =
-						parse_parameters(&lookahead_tokens[1..lookahead_tokens.len() - 2]); // exclude parens and arrow
+					let (parameters, parameter_types) =
+						parse_parameters(&lookahead_tokens[1..lookahead_tokens.len() - 2])?; // exclude parens and arrow
 
 					// Parse the function body (expect a '{' followed by a block)
 					if let Some(lex::Token::Operator(op)) = tokens.peek() {
 						if op.value == "{" {
 							tokens.next(); // consume the '{'
-							let body = parse_block(tokens);
+							let body = parse_block(tokens)?;
 
 							if !current_line_tokens.is_empty() {
 								let lang_line = LangLine {
@@ -287,8 +507,11 @@ pub fn parse_block(tokens: &mut Peekable<IntoIter<lex::Token>>) -> LangBlock {
 								block_items.push(LangBlockItem::Line(lang_line));
 								current_line_tokens = Vec::new();
 							}
-							block_items
-								.push(LangBlockItem::Function(LangFunction { parameters, body }));
+							block_items.push(LangBlockItem::Function(LangFunction {
+								parameters,
+								parameter_types,
+								body,
+							}));
 						} else {
 							// No function body, treat as regular tokens
 							current_line_tokens.push(token);
@@ -311,6 +534,13 @@ pub fn parse_block(tokens: &mut Peekable<IntoIter<lex::Token>>) -> LangBlock {
 					}
 				}
 			}
+			lex::Token::Operator(op)
+				if op.value == "[" && current_line_tokens.is_empty() && list_ends_line(tokens) =>
+			{
+				// A list literal that is a whole statement, like `[1, 2, 3]`
+				let items = parse_separated_expressions(tokens, "]");
+				block_items.push(LangBlockItem::List(LangList { items }));
+			}
 			lex::Token::Operator(op) if op.value == "{" => {
 				// Start of nested block - first finish current line if any
 				if !current_line_tokens.is_empty() {
@@ -322,7 +552,7 @@ pub fn parse_block(tokens: &mut Peekable<IntoIter<lex::Token>>) -> LangBlock {
 				}
 
 				// Parse nested block recursively
-				let nested_block = parse_block(tokens);
+				let nested_block = parse_block(tokens)?;
 				block_items.push(LangBlockItem::Block(nested_block));
 			}
 			lex::Token::Operator(op) if op.value == "}" => {
@@ -333,7 +563,7 @@ pub fn parse_block(tokens: &mut Peekable<IntoIter<lex::Token>>) -> LangBlock {
 					};
 					block_items.push(LangBlockItem::Line(lang_line));
 				}
-				return LangBlock { items: block_items };
+				return Ok(true);
 			}
 			lex::Token::Operator(op) if op.value == "\n" || op.value == ";" => {
 				// End of line - create LangLine and add to block
@@ -360,40 +590,464 @@ pub fn parse_block(tokens: &mut Peekable<IntoIter<lex::Token>>) -> LangBlock {
 		block_items.push(LangBlockItem::Line(lang_line));
 	}
 
-	LangBlock { items: block_items }
+	Ok(false)
 }
 
-fn parse_parameters(tokens: &[lex::Token]) -> Vec<String> {
-	let mut parameters = Vec::new();
+/// Check whether the call whose `(` is next ends its line, as in `f(1)` but not `f(1) + 2`
+fn call_ends_line(tokens: &Peekable<IntoIter<lex::Token>>) -> bool {
+	let mut lookahead = tokens.clone();
+	let mut paren_depth = 0;
+	for token in lookahead.by_ref() {
+		match &token {
+			lex::Token::Operator(op) if op.value == "(" => paren_depth += 1,
+			lex::Token::Operator(op) if op.value == ")" => {
+				paren_depth -= 1;
+				if paren_depth == 0 {
+					break;
+				}
+			}
+			_ => {}
+		}
+	}
+	match lookahead.peek() {
+		None => true,
+		Some(lex::Token::Operator(op)) => matches!(op.value.as_str(), "\n" | ";" | "}"),
+		Some(_) => false,
+	}
+}
 
-	for token in tokens {
-		if let lex::Token::Symbol(symbol) = token {
-			parameters.push(symbol.value.clone());
+/// Check whether the list literal whose `[` was just read ends its line, as in `[1, 2]` but
+/// not `[1, 2][0]`
+fn list_ends_line(tokens: &Peekable<IntoIter<lex::Token>>) -> bool {
+	let mut lookahead = tokens.clone();
+	let mut bracket_depth = 1;
+	for token in lookahead.by_ref() {
+		match &token {
+			lex::Token::Operator(op) if op.value == "[" => bracket_depth += 1,
+			lex::Token::Operator(op) if op.value == "]" => {
+				bracket_depth -= 1;
+				if bracket_depth == 0 {
+					break;
+				}
+			}
+			_ => {}
 		}
 	}
+	bracket_depth == 0
+		&& match lookahead.peek() {
+			None => true,
+			Some(lex::Token::Operator(op)) => matches!(op.value.as_str(), "\n" | ";" | "}"),
+			Some(_) => false,
+		}
+}
 
+/// Parse the guard of a clause like `fn abs(x) when x < 0 { -x }`: the condition from
+/// `when` up to the opening brace of the body. Returns None if there is no `when`.
+fn parse_guard(
+	tokens: &mut Peekable<IntoIter<lex::Token>>,
+) -> Result<Option<Vec<lex::Token>>, ParseError> {
+	match tokens.peek() {
+		Some(lex::Token::Symbol(symbol)) if symbol.value == "when" => {
+			tokens.next();
+		}
+		_ => return Ok(None),
+	}
+
+	let mut guard = Vec::new();
+	let mut paren_depth = 0;
+	loop {
+		match tokens.peek() {
+			Some(lex::Token::Operator(op)) if op.value == "{" && paren_depth == 0 => break,
+			Some(lex::Token::Operator(op)) if op.value == "(" => paren_depth += 1,
+			Some(lex::Token::Operator(op)) if op.value == ")" => paren_depth -= 1,
+			Some(_) => {}
+			None => return Err(ParseError::new("Syntax error: expected '{' after guard")),
+		}
+		guard.push(tokens.next().unwrap());
+	}
+	if guard.is_empty() {
+		return Err(ParseError::new("Syntax error: 'when' needs a condition"));
+	}
+	Ok(Some(guard))
+}
+
+/// Parse the rest of an if statement after the `if` keyword
+fn parse_if(tokens: &mut Peekable<IntoIter<lex::Token>>) -> Result<LangIf, ParseError> {
+	// The condition runs until the opening brace of the then-block
+	let mut condition = Vec::new();
+	let mut paren_depth = 0;
+	loop {
+		match tokens.next() {
+			Some(lex::Token::Operator(op)) if op.value == "{" && paren_depth == 0 => break,
+			Some(token) => {
+				if let lex::Token::Operator(op) = &token {
+					if op.value == "(" {
+						paren_depth += 1;
+					} else if op.value == ")" {
+						paren_depth -= 1;
+					}
+				}
+				condition.push(token);
+			}
+			None => {
+				return Err(ParseError::new(
+					"Syntax error: expected '{' after if condition",
+				));
+			}
+		}
+	}
+
+	let then_block = parse_block(tokens)?;
+
+	// Look for an `else`, which may follow on a later line
+	let mut lookahead = tokens.clone();
+	let mut newlines = 0;
+	while let Some(lex::Token::Operator(op)) = lookahead.peek() {
+		if op.value != "\n" {
+			break;
+		}
+		lookahead.next();
+		newlines += 1;
+	}
+
+	let else_block = match lookahead.next() {
+		Some(lex::Token::Symbol(symbol)) if symbol.value == "else" => {
+			// Consume the newlines and the `else`
+			for _ in 0..=newlines {
+				tokens.next();
+			}
+
+			match tokens.next() {
+				Some(lex::Token::Operator(op)) if op.value == "{" => Some(parse_block(tokens)?),
+				Some(lex::Token::Symbol(symbol)) if symbol.value == "if" => {
+					// `else if` is an if statement nested in the else branch
+					let nested_if = parse_if(tokens)?;
+					Some(LangBlock {
+						items: vec![LangBlockItem::If(nested_if)],
+					})
+				}
+				_ => {
+					return Err(ParseError::new(
+						"Syntax error: expected '{' or 'if' after else",
+					));
+				}
+			}
+		}
+		_ => None,
+	};
+
+	Ok(LangIf {
+		condition,
+		then_block,
+		else_block,
+		assign_to: None,
+	})
+}
+
+/// Whether the tokens after `match` reach a `{` before the end of the line, so that a
+/// variable or function named `match` still works in an expression
+fn starts_match(tokens: &Peekable<IntoIter<lex::Token>>) -> bool {
+	let mut paren_depth = 0;
+	for token in tokens.clone() {
+		match &token {
+			lex::Token::Operator(op) if op.value == "{" && paren_depth == 0 => return true,
+			lex::Token::Operator(op) if op.value == "(" => paren_depth += 1,
+			lex::Token::Operator(op) if op.value == ")" => paren_depth -= 1,
+			lex::Token::Operator(op) if matches!(op.value.as_str(), "\n" | ";" | "=" | "}") => {
+				return false;
+			}
+			_ => {}
+		}
+	}
+	false
+}
+
+/// Parse the rest of a match statement after the `match` keyword: the subject, then arms
+/// of the form `literal => { ... }` or `_ => { ... }` separated by `;` or newlines
+fn parse_match(tokens: &mut Peekable<IntoIter<lex::Token>>) -> Result<LangMatch, ParseError> {
+	// The subject runs until the opening brace of the arms
+	let mut subject = Vec::new();
+	let mut paren_depth = 0;
+	loop {
+		match tokens.next() {
+			Some(lex::Token::Operator(op)) if op.value == "{" && paren_depth == 0 => break,
+			Some(token) => {
+				if let lex::Token::Operator(op) = &token {
+					if op.value == "(" {
+						paren_depth += 1;
+					} else if op.value == ")" {
+						paren_depth -= 1;
+					}
+				}
+				subject.push(token);
+			}
+			None => {
+				return Err(ParseError::new(
+					"Syntax error: expected '{' after match subject",
+				));
+			}
+		}
+	}
+	if subject.is_empty() {
+		return Err(ParseError::new(
+			"Syntax error: 'match' needs a value to match",
+		));
+	}
+
+	let mut arms = Vec::new();
+	loop {
+		let pattern = match tokens.next() {
+			Some(lex::Token::Operator(op)) if op.value == "\n" || op.value == ";" => continue,
+			Some(lex::Token::Operator(op)) if op.value == "}" => break,
+			Some(lex::Token::Number(number)) => Some(number),
+			Some(lex::Token::Symbol(symbol)) if symbol.value == "_" => None,
+			Some(_) => {
+				return Err(ParseError::new(
+					"Syntax error: a match arm must start with a number or '_'",
+				));
+			}
+			None => return Err(ParseError::new("Syntax error: missing '}'")),
+		};
+		match (tokens.next(), tokens.next()) {
+			(Some(lex::Token::Operator(arrow)), Some(lex::Token::Operator(brace)))
+				if arrow.value == "=>" && brace.value == "{" =>
+			{
+				let body = parse_block(tokens)?;
+				arms.push(LangMatchArm { pattern, body });
+			}
+			_ => {
+				return Err(ParseError::new(
+					"Syntax error: expected '=> {' after match pattern",
+				));
+			}
+		}
+	}
+
+	Ok(LangMatch { subject, arms })
+}
+
+/// Whether the tokens after a name are `= if`, assigning the value of an if/else
+fn assigns_if(tokens: &Peekable<IntoIter<lex::Token>>) -> bool {
+	let mut lookahead = tokens.clone();
+	matches!(lookahead.next(), Some(lex::Token::Operator(op)) if op.value == "=")
+		&& matches!(lookahead.next(), Some(lex::Token::Symbol(symbol)) if symbol.value == "if")
+}
+
+/// Render tokens back to source-like text, separated by spaces
+pub fn tokens_to_string(tokens: &[lex::Token]) -> String {
+	tokens
+		.iter()
+		.map(|t| match t {
+			lex::Token::Number(lex::LangNumber::Integer(n)) => n.value.to_string(),
+			// Whole reals keep their decimal point so they lex as reals again
+			lex::Token::Number(lex::LangNumber::RealNumber(n)) if n.value.fract() == 0.0 => {
+				format!("{:.1}", n.value)
+			}
+			lex::Token::Number(lex::LangNumber::RealNumber(n)) => n.value.to_string(),
+			lex::Token::Symbol(s) => s.value.clone(),
+			lex::Token::String(s) => format!("\"{}\"", s.value),
+			lex::Token::Operator(o) => o.value.clone(),
+		})
+		.collect::<Vec<_>>()
+		.join(" ")
+}
+
+/// Render the items of a list literal as source, separated by commas
+fn list_items_to_string(list: &LangList) -> String {
+	let items: Vec<String> = list
+		.items
+		.iter()
+		.map(|item| tokens_to_string(item))
+		.collect();
+	items.join(", ")
+}
+
+/// Render a block back to source text that parses to the same block, one item per line
+/// indented by `indent` tabs
+pub fn block_to_source(block: &LangBlock, indent: usize) -> String {
+	let tabs = "\t".repeat(indent);
+	let mut source = String::new();
+	for item in &block.items {
+		let item_source = match item {
+			LangBlockItem::Line(line) => tokens_to_string(&line.tokens),
+			LangBlockItem::List(list) => format!("[{}]", list_items_to_string(list)),
+			LangBlockItem::Block(nested_block) => braced_source(nested_block, indent),
+			LangBlockItem::Function(function) => format!(
+				"({}) => {}",
+				parameters_to_string(&function.parameters, &function.parameter_types),
+				braced_source(&function.body, indent)
+			),
+			LangBlockItem::NamedFunction(named_function) => {
+				let guard = match &named_function.guard {
+					Some(guard) => format!(" when {}", tokens_to_string(guard)),
+					None => String::new(),
+				};
+				format!(
+					"fn {}({}){} {}",
+					named_function.name,
+					parameters_to_string(
+						&named_function.parameters,
+						&named_function.parameter_types
+					),
+					guard,
+					braced_source(&named_function.body, indent)
+				)
+			}
+			LangBlockItem::FunctionCall(call) => {
+				let args: Vec<String> = call
+					.arguments
+					.iter()
+					.map(|tokens| tokens_to_string(tokens))
+					.collect();
+				format!("{}({})", call.name, args.join(", "))
+			}
+			LangBlockItem::If(if_item) => {
+				let mut if_source = format!(
+					"if {} {}",
+					tokens_to_string(&if_item.condition),
+					braced_source(&if_item.then_block, indent)
+				);
+				if let Some(name) = &if_item.assign_to {
+					if_source = format!("{} = {}", name, if_source);
+				}
+				if let Some(else_block) = &if_item.else_block {
+					if_source.push_str(" else ");
+					if_source.push_str(&braced_source(else_block, indent));
+				}
+				if_source
+			}
+			LangBlockItem::Match(match_item) => {
+				let arms: Vec<String> = match_item
+					.arms
+					.iter()
+					.map(|arm| {
+						format!(
+							"{}{} => {}\n",
+							"\t".repeat(indent + 1),
+							match_pattern_to_string(&arm.pattern),
+							braced_source(&arm.body, indent + 1)
+						)
+					})
+					.collect();
+				format!(
+					"match {} {{\n{}{}}}",
+					tokens_to_string(&match_item.subject),
+					arms.concat(),
+					tabs
+				)
+			}
+		};
+		source.push_str(&tabs);
+		source.push_str(&item_source);
+		source.push('\n');
+	}
+	source
+}
+
+/// Render a match arm's pattern: its case literal, or `_` for the default arm
+pub fn match_pattern_to_string(pattern: &Option<lex::LangNumber>) -> String {
+	match pattern {
+		Some(number) => tokens_to_string(&[lex::Token::Number(number.clone())]),
+		None => "_".to_string(),
+	}
+}
+
+/// Render a block in braces, its items indented one level deeper than `indent`
+fn braced_source(block: &LangBlock, indent: usize) -> String {
+	format!(
+		"{{\n{}{}}}",
+		block_to_source(block, indent + 1),
+		"\t".repeat(indent)
+	)
+}
+
+/// Render a parameter list, with the annotations of annotated parameters
+pub fn parameters_to_string(parameters: &[String], parameter_types: &[Option<String>]) -> String {
 	parameters
+		.iter()
+		.zip(parameter_types)
+		.map(|(name, parameter_type)| match parameter_type {
+			Some(parameter_type) => format!("{}: {}", name, parameter_type),
+			None => name.clone(),
+		})
+		.collect::<Vec<_>>()
+		.join(", ")
+}
+
+/// Record the annotation after a `:` in a parameter list on the parameter before it
+fn annotate_parameter(
+	parameter_types: &mut [Option<String>],
+	annotation: Option<&lex::Token>,
+) -> Result<(), ParseError> {
+	let Some(parameter_type @ None) = parameter_types.last_mut() else {
+		return Err(ParseError::new(
+			"Syntax error: ':' must follow a parameter name",
+		));
+	};
+	match annotation {
+		Some(lex::Token::Symbol(symbol)) if PARAMETER_TYPES.contains(&symbol.value.as_str()) => {
+			*parameter_type = Some(symbol.value.clone());
+			Ok(())
+		}
+		_ => Err(ParseError::new(&format!(
+			"Syntax error: a parameter type must be one of {}",
+			PARAMETER_TYPES.join(", ")
+		))),
+	}
+}
+
+fn parse_parameters(
+	tokens: &[lex::Token],
+) -> Result<(Vec<String>, Vec<Option<String>>), ParseError> {
+	let mut parameters = Vec::new();
+	let mut parameter_types = Vec::new();
+
+	let mut tokens = tokens.iter();
+	while let Some(token) = tokens.next() {
+		match token {
+			lex::Token::Symbol(symbol) => {
+				parameters.push(symbol.value.clone());
+				parameter_types.push(None);
+			}
+			lex::Token::Operator(op) if op.value == ":" => {
+				annotate_parameter(&mut parameter_types, tokens.next())?;
+			}
+			_ => {}
+		}
+	}
+
+	Ok((parameters, parameter_types))
 }
 
 fn parse_function_arguments(tokens: &mut Peekable<IntoIter<lex::Token>>) -> Vec<Vec<lex::Token>> {
+	parse_separated_expressions(tokens, ")")
+}
+
+/// Parse comma-separated expressions up to the `close` operator that ends them, like the
+/// arguments of a call or the items of a list literal
+fn parse_separated_expressions(
+	tokens: &mut Peekable<IntoIter<lex::Token>>,
+	close: &str,
+) -> Vec<Vec<lex::Token>> {
 	let mut arguments = Vec::new();
 	let mut current_arg_tokens = Vec::new();
 	let mut paren_depth = 0;
 
 	while let Some(token) = tokens.next() {
 		match &token {
-			lex::Token::Operator(op) if op.value == ")" && paren_depth == 0 => {
-				// End of function arguments
+			lex::Token::Operator(op) if op.value == close && paren_depth == 0 => {
+				// End of the expressions
 				if !current_arg_tokens.is_empty() {
 					arguments.push(current_arg_tokens);
 				}
 				break;
 			}
-			lex::Token::Operator(op) if op.value == "(" => {
+			// Commas inside nested calls and list literals belong to them
+			lex::Token::Operator(op) if op.value == "(" || op.value == "[" => {
 				paren_depth += 1;
 				current_arg_tokens.push(token);
 			}
-			lex::Token::Operator(op) if op.value == ")" => {
+			lex::Token::Operator(op) if op.value == ")" || op.value == "]" => {
 				paren_depth -= 1;
 				current_arg_tokens.push(token);
 			}
@@ -415,8 +1069,9 @@ fn parse_function_arguments(tokens: &mut Peekable<IntoIter<lex::Token>>) -> Vec<
 
 fn parse_function_parameters_until_paren(
 	tokens: &mut Peekable<IntoIter<lex::Token>>,
-) -> Vec<String> {
+) -> Result<(Vec<String>, Vec<Option<String>>), ParseError> {
 	let mut parameters = Vec::new();
+	let mut parameter_types = Vec::new();
 
 	while let Some(token) = tokens.next() {
 		match &token {
@@ -426,6 +1081,11 @@ fn parse_function_parameters_until_paren(
 			}
 			lex::Token::Symbol(symbol) => {
 				parameters.push(symbol.value.clone());
+				parameter_types.push(None);
+			}
+			lex::Token::Operator(op) if op.value == ":" => {
+				// `name: type` annotates the parameter
+				annotate_parameter(&mut parameter_types, tokens.next().as_ref())?;
 			}
 			lex::Token::Operator(op) if op.value == "," => {
 				// Parameter separator, continue
@@ -436,7 +1096,7 @@ fn parse_function_parameters_until_paren(
 		}
 	}
 
-	parameters
+	Ok((parameters, parameter_types))
 }
 
 impl<'a> DisplayBlock<'a> {
@@ -470,7 +1130,7 @@ impl<'a> fmt::Display for DisplayBlock<'a> {
 						"{}Function {}: ({}) => {{",
 						indent,
 						i + 1,
-						function.parameters.join(", ")
+						parameters_to_string(&function.parameters, &function.parameter_types)
 					)?;
 					write!(
 						f,
@@ -480,13 +1140,21 @@ impl<'a> fmt::Display for DisplayBlock<'a> {
 					writeln!(f, "{}}}", indent)?;
 				}
 				LangBlockItem::NamedFunction(named_function) => {
+					let guard = match &named_function.guard {
+						Some(guard) => format!(" when {}", tokens_to_string(guard)),
+						None => String::new(),
+					};
 					writeln!(
 						f,
-						"{}Named Function {}: {} ({}) => {{",
+						"{}Named Function {}: {} ({}){} => {{",
 						indent,
 						i + 1,
 						named_function.name,
-						named_function.parameters.join(", ")
+						parameters_to_string(
+							&named_function.parameters,
+							&named_function.parameter_types
+						),
+						guard
 					)?;
 					write!(
 						f,
@@ -499,23 +1167,7 @@ impl<'a> fmt::Display for DisplayBlock<'a> {
 					let args: Vec<String> = call
 						.arguments
 						.iter()
-						.map(|tokens| {
-							tokens
-								.iter()
-								.map(|t| match t {
-									lex::Token::Number(lex::LangNumber::Integer(n)) => {
-										n.value.to_string()
-									}
-									lex::Token::Number(lex::LangNumber::RealNumber(n)) => {
-										n.value.to_string()
-									}
-									lex::Token::Symbol(s) => s.value.clone(),
-									lex::Token::String(s) => format!("\"{}\"", s.value),
-									lex::Token::Operator(o) => o.value.clone(),
-								})
-								.collect::<Vec<_>>()
-								.join(" ")
-						})
+						.map(|tokens| tokens_to_string(tokens))
 						.collect();
 					writeln!(
 						f,
@@ -526,6 +1178,64 @@ impl<'a> fmt::Display for DisplayBlock<'a> {
 						args.join(", ")
 					)?;
 				}
+				LangBlockItem::List(list) => {
+					writeln!(
+						f,
+						"{}List {}: [{}]",
+						indent,
+						i + 1,
+						list_items_to_string(list)
+					)?;
+				}
+				LangBlockItem::If(if_item) => {
+					let target = match &if_item.assign_to {
+						Some(name) => format!("{} = ", name),
+						None => String::new(),
+					};
+					writeln!(
+						f,
+						"{}If {}: {}{} {{",
+						indent,
+						i + 1,
+						target,
+						tokens_to_string(&if_item.condition)
+					)?;
+					write!(
+						f,
+						"{}",
+						DisplayBlock::new(&if_item.then_block, self.indent_level + 1)
+					)?;
+					if let Some(else_block) = &if_item.else_block {
+						writeln!(f, "{}}} else {{", indent)?;
+						write!(
+							f,
+							"{}",
+							DisplayBlock::new(else_block, self.indent_level + 1)
+						)?;
+					}
+					writeln!(f, "{}}}", indent)?;
+				}
+				LangBlockItem::Match(match_item) => {
+					writeln!(
+						f,
+						"{}Match {}: {} {{",
+						indent,
+						i + 1,
+						tokens_to_string(&match_item.subject)
+					)?;
+					let arm_indent = "  ".repeat(self.indent_level + 1);
+					for arm in &match_item.arms {
+						writeln!(
+							f,
+							"{}{} => {{",
+							arm_indent,
+							match_pattern_to_string(&arm.pattern)
+						)?;
+						write!(f, "{}", DisplayBlock::new(&arm.body, self.indent_level + 2))?;
+						writeln!(f, "{}}}", arm_indent)?;
+					}
+					writeln!(f, "{}}}", indent)?;
+				}
 			}
 		}
 		Ok(())
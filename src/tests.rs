@@ -13,6 +13,36 @@ fn clear_variables() {
 			variables.clear();
 		}
 	}
+	match LIST_VARIABLES.lock() {
+		Ok(mut lists) => lists.clear(),
+		Err(poisoned) => {
+			let mut lists = poisoned.into_inner();
+			lists.clear();
+		}
+	}
+	// Compiled code refers to globals by slot, so drop it along with the slots
+	match GLOBAL_SLOTS.lock() {
+		Ok(mut slots) => slots.clear(),
+		Err(poisoned) => {
+			let mut slots = poisoned.into_inner();
+			slots.clear();
+		}
+	}
+	COMPILED_FUNCTIONS.with(|compiled| compiled.borrow_mut().clear());
+}
+
+// Restores the settings a test changed when it ends, even if it fails
+struct SettingsGuard(Settings);
+
+impl Drop for SettingsGuard {
+	fn drop(&mut self) {
+		*settings() = self.0.clone();
+	}
+}
+
+// Helper function to save the settings until the returned guard is dropped
+fn save_settings() -> SettingsGuard {
+	SettingsGuard(settings().clone())
 }
 
 // Helper function to get a variable value
@@ -48,6 +78,21 @@ fn clear_functions() {
 			functions.clear();
 		}
 	}
+	match FUNCTION_CLAUSES.lock() {
+		Ok(mut clauses) => clauses.clear(),
+		Err(poisoned) => {
+			let mut clauses = poisoned.into_inner();
+			clauses.clear();
+		}
+	}
+	match FUNCTION_DEPENDENCIES.lock() {
+		Ok(mut dependencies) => dependencies.clear(),
+		Err(poisoned) => {
+			let mut dependencies = poisoned.into_inner();
+			dependencies.clear();
+		}
+	}
+	COMPILED_FUNCTIONS.with(|compiled| compiled.borrow_mut().clear());
 }
 
 // Helper function to check if a function exists
@@ -1088,8 +1133,9 @@ fn test_function_definition_with_statements() {
 	clear_variables();
 	clear_functions();
 
-	// Test function definition mixed with variable assignments
-	assert_eq!(run("x = 5; fn double(n) { n * 2 }; y = 10"), None);
+	// Test function definition mixed with variable assignments; the block's value is
+	// that of the last line
+	assert_eq!(run("x = 5; fn double(n) { n * 2 }; y = 10"), Some(10.0));
 
 	// Verify variable assignments worked
 	assert_eq!(get_variable("x"), Some(5.0));
@@ -2031,3 +2077,2685 @@ fn test_compile_expr_large_calculation() {
 		Err(e) => panic!("Failed to create executable: {}", e),
 	}
 }
+
+#[test]
+fn test_blank_lines_and_brackets_before_newline() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+
+	// A newline right after a bracket or another newline must not break lexing
+	assert_eq!(run("\nx = 2\n\ny = (x + 1)\ny * 2\n"), Some(6.0));
+	assert_eq!(get_variable("y"), Some(3.0));
+}
+
+#[test]
+fn test_function_redefinition_uses_new_body() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	// JIT-compiled function: the redefined body must be used on the next call
+	run("fn scale(x) { x * 2 }");
+	assert_eq!(run("scale(4)"), Some(8.0));
+	run("fn scale(x) { x * 3 }");
+	assert_eq!(run("scale(4)"), Some(12.0));
+
+	// Runtime-evaluated function calling the redefined one
+	run("fn scale_plus_one(x) { 1 + scale(x) }");
+	assert_eq!(run("scale_plus_one(2)"), Some(7.0));
+	run("fn scale(x) { x * 10 }");
+	assert_eq!(run("scale_plus_one(2)"), Some(21.0));
+}
+
+#[test]
+fn test_string_equality() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+
+	assert_eq!(run("\"a\" == \"a\""), Some(1.0));
+	assert_eq!(run("\"a\" == \"b\""), Some(0.0));
+	assert_eq!(run("\"a\" != \"b\""), Some(1.0));
+	assert_eq!(run("x = \"abc\" == \"abc\""), Some(1.0));
+	assert_eq!(get_variable("x"), Some(1.0));
+
+	// Strings can't be compared with numbers or used in arithmetic
+	assert_eq!(run("\"a\" == 1"), None);
+	assert_eq!(run("\"a\" + 1"), None);
+}
+
+#[test]
+fn test_string_lexicographic_ordering() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+
+	assert_eq!(run("\"apple\" < \"banana\""), Some(1.0));
+	assert_eq!(run("\"banana\" < \"apple\""), Some(0.0));
+	assert_eq!(run("\"abc\" < \"abd\""), Some(1.0));
+	assert_eq!(run("\"ab\" < \"abc\""), Some(1.0));
+	assert_eq!(run("\"b\" >= \"b\""), Some(1.0));
+	assert_eq!(run("\"B\" > \"a\""), Some(0.0)); // Byte order: uppercase sorts first
+}
+
+#[test]
+fn test_numeric_comparisons() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	assert_eq!(run("2 < 3"), Some(1.0));
+	assert_eq!(run("2 + 2 == 4"), Some(1.0));
+	assert_eq!(run("1 > -1"), Some(1.0));
+
+	// Comparisons also work in JIT-compiled functions
+	run("fn is_positive(x) { x > 0 }");
+	assert_eq!(run("is_positive(5)"), Some(1.0));
+	assert_eq!(run("is_positive(-5)"), Some(0.0));
+}
+
+#[test]
+fn test_arrow_outside_lambda_is_error() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	// A bare arrow is a syntax error rather than silently yielding the right operand
+	assert_eq!(run("2 => 3"), None);
+	assert_eq!(run("x = 2 => 3"), None);
+	assert_eq!(get_variable("x"), None);
+
+	// A lambda without a body is not a lambda either
+	assert_eq!(run("(x) => x"), None);
+
+	// Proper lambda definitions still work
+	assert_eq!(run("f = (x) => {x}"), None);
+	assert!(function_exists("f"));
+	assert_eq!(run("f(7)"), Some(7.0));
+}
+
+#[test]
+fn test_stray_dot_is_error() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	// A dot outside a number is rejected instead of being skipped with a warning
+	assert_eq!(run("2 . 3"), None);
+	assert_eq!(run("."), None);
+	assert_eq!(run("a = 1\na.b"), None);
+	assert_eq!(
+		evaluate_value(&lex("2 . 3")).unwrap_err().to_string(),
+		"Syntax error: unexpected '.' outside a number"
+	);
+
+	// Dots inside numbers are untouched
+	assert_eq!(run("1.5 + 2."), Some(3.5));
+}
+
+#[test]
+fn test_format_value_trims_trailing_zeros() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+
+	assert_eq!(format_value(1.5), "1.5");
+	assert_eq!(format_value(2.0), "2");
+	assert_eq!(format_value(0.125), "0.125");
+	assert_eq!(format_value(-40.0), "-40");
+	assert_eq!(format_value(0.1 + 0.2), "0.3");
+}
+
+#[test]
+fn test_format_value_respects_precision() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+
+	let nearest = |value, precision| format_rounded(value, precision, RoundingMode::Nearest);
+	assert_eq!(nearest(1.23456789, 4), "1.2346");
+	assert_eq!(nearest(2.0004, 3), "2");
+	assert_eq!(nearest(-0.0001, 2), "-0.0001");
+	assert_eq!(nearest(0.004, 2), "0.004");
+	assert_eq!(nearest(1e-20, 15), "1e-20");
+	assert_eq!(nearest(-1.5e-300, 15), "-1.5e-300");
+	assert_eq!(nearest(0.0, 2), "0");
+	assert_eq!(nearest(-0.004, 2), "-0.004");
+
+	let _settings = save_settings();
+	settings().precision = 2;
+	assert_eq!(format_value(1.0 / 3.0), "0.33");
+	assert_eq!(format_value(1e-20), "1e-20");
+}
+
+#[test]
+fn test_compiled_output_matches_format_value() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+	let _settings = save_settings();
+
+	let executable_path = std::env::temp_dir().join("test_expr_format");
+	let executable_str = executable_path.to_str().unwrap();
+	let cases = [
+		("1 / 3", 15),
+		("2.5 * 4", 15),
+		("0 - 0.0001", 2),
+		("0.00000000000000000001 * 3", 15),
+		("0 - 1 / 3", 0),
+		("123456.75", 1),
+	];
+	for (expression, precision) in cases {
+		settings().precision = precision;
+		crate::create_executable_from_expression(expression, executable_str)
+			.expect("Failed to create executable");
+		let output = std::process::Command::new(&executable_path)
+			.output()
+			.expect("Failed to execute compiled binary");
+		let stdout = String::from_utf8_lossy(&output.stdout);
+		let expected = format_value(run(expression).unwrap());
+		assert_eq!(
+			stdout.trim(),
+			expected,
+			"{} at precision {}",
+			expression,
+			precision
+		);
+	}
+	let _ = std::fs::remove_file(&executable_path);
+}
+
+#[test]
+fn test_compile_expr_ir() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	let (value, ir) = crate::compile_expr_ir("2 + 3 * 4").expect("Should compile");
+	assert_eq!(value, 14.0);
+	assert!(ir.contains("define double @expr()"));
+	assert!(ir.contains("ret double 1.400000e+01")); // Constant operations are folded
+
+	// Division keeps its zero-check branches and the phi joining them
+	let (value, ir) = crate::compile_expr_ir("10 / 4").expect("Should compile");
+	assert_eq!(value, 2.5);
+	assert!(ir.contains("phi double"));
+	assert!(ir.contains("div_result"));
+
+	assert!(crate::compile_expr_ir("1 / 0").is_err());
+}
+
+#[test]
+fn test_build_link_command() {
+	let output = if cfg!(target_os = "windows") {
+		"app.exe"
+	} else {
+		"app"
+	};
+
+	assert_eq!(
+		build_link_command("gcc", &[], "app", "app.o"),
+		format!("gcc -o {} app.o -lm", output)
+	);
+	assert_eq!(
+		build_link_command(
+			"clang++",
+			&["-static".to_string(), "-O2".to_string()],
+			"app",
+			"app.o"
+		),
+		format!("clang++ -o {} app.o -lm -static -O2", output)
+	);
+}
+
+#[test]
+fn test_nested_ternary_is_right_associative() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+
+	// a ? b : c ? d : e  ==  a ? b : (c ? d : e)
+	assert_eq!(run("1 ? 10 : 1 ? 20 : 30"), Some(10.0));
+	assert_eq!(run("0 ? 10 : 1 ? 20 : 30"), Some(20.0));
+	assert_eq!(run("0 ? 10 : 0 ? 20 : 30"), Some(30.0));
+
+	// Conditionals bind looser than comparisons and arithmetic, tighter than assignment
+	run("x = 5");
+	assert_eq!(run("sign = x < 0 ? -1 : x == 0 ? 0 : 1"), Some(1.0));
+	assert_eq!(get_variable("sign"), Some(1.0));
+	assert_eq!(run("(x > 3 ? 2 : 3) * 10"), Some(20.0));
+
+	// Only the selected branch is evaluated
+	run("y = 0");
+	assert_eq!(run("y == 0 ? 0 : 1 / y"), Some(0.0));
+
+	// A conditional without its ':' is an error
+	assert_eq!(run("1 ? 2"), None);
+}
+
+#[test]
+fn test_if_else_if_chain() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	let program = "if (x < 0) { -1 } else if (x == 0) { 0 } else { 1 }";
+	set_variable("x", -5.0);
+	assert_eq!(run(program), Some(-1.0));
+	set_variable("x", 0.0);
+	assert_eq!(run(program), Some(0.0));
+	set_variable("x", 5.0);
+	assert_eq!(run(program), Some(1.0));
+
+	// else may start on the line after the closing brace
+	assert_eq!(run("if (x > 10) {\n1\n}\nelse {\n2\n}"), Some(2.0));
+
+	// An if without else produces no value when its condition is false
+	assert_eq!(run("if (0) { 1 }"), None);
+}
+
+#[test]
+fn test_conditionals_in_functions() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	// These compile to LLVM branches
+	run("fn classify(x) { if (x < 0) { -1 } else if (x == 0) { 0 } else { 1 } }");
+	assert_eq!(run("classify(-3)"), Some(-1.0));
+	assert_eq!(run("classify(0)"), Some(0.0));
+	assert_eq!(run("classify(3)"), Some(1.0));
+
+	run("fn pick(a, b, c) { a ? b : c ? 2 : 3 }");
+	assert_eq!(run("pick(1, 7, 0)"), Some(7.0));
+	assert_eq!(run("pick(0, 7, 1)"), Some(2.0));
+	assert_eq!(run("pick(0, 7, 0)"), Some(3.0));
+
+	// Runtime-evaluated function using a conditional
+	run("fn safe_inverse(x) { if (x == 0) { 0 } else { 1 / x } }");
+	run("fn inverse_twice(x) { 2 * safe_inverse(x) }");
+	assert_eq!(run("inverse_twice(4)"), Some(0.5));
+	assert_eq!(run("inverse_twice(0)"), Some(0.0));
+}
+
+#[test]
+fn test_unary_minus_in_compiled_functions() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	run("fn negate(x) { -x }");
+	assert_eq!(run("negate(4)"), Some(-4.0));
+	run("fn offset(x) { -x + 10 }");
+	assert_eq!(run("offset(3)"), Some(7.0));
+}
+
+#[test]
+fn test_builtin_registry() {
+	for name in ["abs", "cos", "max", "min", "pow", "sin", "sqrt"] {
+		assert!(
+			builtins::find_builtin(name).is_some(),
+			"missing built-in {}",
+			name
+		);
+	}
+	assert_eq!(builtins::find_builtin("sqrt").unwrap().arity, 1);
+	assert_eq!(builtins::find_builtin("min").unwrap().arity, 2);
+	assert!(builtins::find_builtin("nope").is_none());
+
+	// The listing is sorted by name and shows each arity
+	let listing = format_builtins();
+	let names: Vec<&str> = listing
+		.lines()
+		.map(|line| line.split_whitespace().next().unwrap())
+		.collect();
+	let mut sorted = names.clone();
+	sorted.sort();
+	assert_eq!(names, sorted);
+	assert_eq!(
+		names.len(),
+		builtins::BUILTINS.len()
+			+ builtins::VALUE_BUILTINS.len()
+			+ builtins::HIGHER_ORDER_BUILTINS.len()
+	);
+	assert!(listing.contains("sqrt     1 argument"));
+	assert!(listing.contains("max      2 arguments"));
+}
+
+#[test]
+fn test_builtin_calls() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	assert_eq!(run("sqrt(16)"), Some(4.0));
+	assert_eq!(run("1 + max(2, 3 * 2)"), Some(7.0));
+	assert_eq!(run("min(4)"), None);
+
+	// User-defined functions calling built-ins are evaluated at runtime
+	run("fn hypot(a, b) { sqrt(a * a + b * b) }");
+	assert_eq!(run("hypot(3, 4)"), Some(5.0));
+	run("fn clamp(x) { min(max(x, 0), 10) }");
+	assert_eq!(run("clamp(15)"), Some(10.0));
+
+	// Completion offers built-ins and user functions by prefix
+	assert_eq!(completion_candidates("sq"), vec!["sqrt".to_string()]);
+	assert!(completion_candidates("hy").contains(&"hypot".to_string()));
+	assert!(completion_candidates("").is_empty());
+}
+
+#[test]
+fn test_llvm_context_reused_across_calls() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	run("fn square(x) { x * x }");
+	assert_eq!(run("square(3)"), Some(9.0));
+	let created = CONTEXTS_CREATED.with(|count| count.get());
+	assert_eq!(created, 1);
+
+	// Every further compilation on this thread reuses the same context
+	for i in 0..200 {
+		assert_eq!(run(&format!("square({})", i)), Some((i * i) as f64));
+	}
+	assert_eq!(CONTEXTS_CREATED.with(|count| count.get()), created);
+}
+
+#[test]
+fn test_redefinition_recompiles_only_that_function() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	let compilations = || FUNCTION_COMPILATIONS.with(|count| count.get());
+
+	// Functions are compiled once, when defined, and their calls use that code
+	let before = compilations();
+	run("fn cached_a(x) { x + 1 }");
+	run("fn cached_b(x) { x * 10 }");
+	assert_eq!(compilations(), before + 2);
+	assert_eq!(run("cached_a(1)"), Some(2.0));
+	assert_eq!(run("cached_b(1)"), Some(10.0));
+	assert_eq!(compilations(), before + 2);
+
+	// Repeated calls reuse the compiled code
+	for i in 0..10 {
+		assert_eq!(run(&format!("cached_a({})", i)), Some(i as f64 + 1.0));
+	}
+	assert_eq!(compilations(), before + 2);
+
+	// Redefining one function leaves the other compiled
+	run("fn cached_a(x) { x + 2 }");
+	assert_eq!(compilations(), before + 3);
+	assert_eq!(run("cached_b(2)"), Some(20.0));
+	assert_eq!(run("cached_a(1)"), Some(3.0));
+	assert_eq!(compilations(), before + 3);
+
+	// Globals are still read when the code runs
+	run("fn cached_c() { offset * 2 }");
+	run("offset = 1");
+	assert_eq!(run("cached_c()"), Some(2.0));
+	run("offset = 5");
+	assert_eq!(run("cached_c()"), Some(10.0));
+	assert_eq!(compilations(), before + 4);
+
+	// Redefining a function so it's only interpreted drops its old code
+	let is_cached =
+		|name: &str| COMPILED_FUNCTIONS.with(|compiled| compiled.borrow().contains_key(name));
+	assert!(is_cached("cached_b"));
+	run("fn cached_b(x) { cached_a(x) * 10 }");
+	assert!(!is_cached("cached_b"));
+	assert_eq!(run("cached_b(1)"), Some(30.0));
+}
+
+#[test]
+fn test_nested_block_errors_propagate() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	assert_eq!(run("{ 2 + 3 }"), Some(5.0));
+	assert_eq!(run("{ 2 + }"), None);
+	assert_eq!(run("{ { 4 * } }"), None);
+	assert_eq!(run("2 +"), None);
+	assert_eq!(run("* 3"), None);
+
+	// The same mistake inside a compiled function body is reported too
+	run("fn broken(x) { x + }");
+	assert_eq!(run("broken(1)"), None);
+}
+
+#[test]
+fn test_exponent_and_unary_minus_precedence() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	// Exponentiation binds tighter than unary minus
+	assert_eq!(run("-2 ^ 2"), Some(-4.0));
+	assert_eq!(run("(-2) ^ 2"), Some(4.0));
+	assert_eq!(run("2 ^ 3 ^ 2"), Some(512.0));
+	assert_eq!(run("2 ^ -1"), Some(0.5));
+
+	// Unary minus binds tighter than multiplication
+	assert_eq!(run("3 * -2"), Some(-6.0));
+	assert_eq!(run("-3 * 2 + 1"), Some(-5.0));
+	assert_eq!(run("x = -4"), Some(-4.0));
+	assert_eq!(run("-x ^ 2"), Some(-16.0));
+
+	// Compiled code follows the same rules
+	run("fn neg_square(x) { -x ^ 2 }");
+	assert_eq!(run("neg_square(3)"), Some(-9.0));
+	run("fn scale(x) { x * -2 }");
+	assert_eq!(run("scale(5)"), Some(-10.0));
+	assert_eq!(compile_expr_ir("-2 ^ 2").unwrap().0, -4.0);
+}
+
+#[test]
+fn test_prompt_selection() {
+	assert_eq!(prompt_for(PromptState::Normal), ">> ");
+	assert_eq!(prompt_for(PromptState::Continuation), "... ");
+	assert_eq!(prompt_for(PromptState::AfterError), "!> ");
+
+	assert!(needs_continuation("fn double(x) {"));
+	assert!(needs_continuation("fn double(x) {\n  x * (2"));
+	assert!(!needs_continuation("fn double(x) {\n  x * 2\n}"));
+	assert!(!needs_continuation("1 + 2"));
+	assert!(!needs_continuation("\"{\""));
+	assert!(!needs_continuation("1 // (unclosed in a comment\n"));
+}
+
+#[test]
+fn test_functions_see_current_globals() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	// JIT-compiled function
+	run("fn apply_rate(x) { x * rate }");
+	run("rate = 2");
+	assert_eq!(run("apply_rate(10)"), Some(20.0));
+	run("rate = 3");
+	assert_eq!(run("apply_rate(10)"), Some(30.0));
+
+	// Runtime-evaluated function
+	run("fn apply_rate_plus(x) { 1 + apply_rate(x) }");
+	assert_eq!(run("apply_rate_plus(10)"), Some(31.0));
+	run("rate = 4");
+	assert_eq!(run("apply_rate_plus(10)"), Some(41.0));
+
+	// Code compiled once reads the global when it runs, not when it was compiled
+	let function = FUNCTIONS
+		.lock()
+		.unwrap()
+		.get("apply_rate")
+		.cloned()
+		.unwrap();
+	let mut codegen = LLVMCodeGen::new(llvm_context()).unwrap();
+	codegen.compile_function("apply_rate", &function).unwrap();
+	unsafe {
+		let jit_fn: inkwell::execution_engine::JitFunction<unsafe extern "C" fn(f64) -> f64> =
+			codegen.execution_engine.get_function("apply_rate").unwrap();
+		assert_eq!(jit_fn.call(10.0), 40.0);
+		set_variable("rate", 5.0);
+		assert_eq!(jit_fn.call(10.0), 50.0);
+	}
+
+	// Clearing variables also forgets the slots, and functions compiled afterwards still
+	// read the right global
+	clear_variables();
+	assert!(GLOBAL_SLOTS.lock().unwrap().is_empty());
+	run("offset = 7");
+	run("fn add_offset(x) { x + offset }");
+	run("rate = 6");
+	assert_eq!(run("add_offset(1)"), Some(8.0));
+	assert_eq!(run("apply_rate(10)"), Some(60.0));
+}
+
+#[test]
+fn test_seeded_random_is_reproducible() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	builtins::seed_random(42);
+	let first: Vec<Option<f64>> = (0..5).map(|_| run("random()")).collect();
+	builtins::seed_random(42);
+	let second: Vec<Option<f64>> = (0..5).map(|_| run("random()")).collect();
+	assert_eq!(first, second);
+	for value in &first {
+		let value = value.unwrap();
+		assert!((0.0..1.0).contains(&value));
+	}
+	assert_ne!(first[0], first[1]);
+
+	builtins::seed_random(7);
+	for _ in 0..20 {
+		let value = run("rand_range(5, 10)").unwrap();
+		assert!((5.0..10.0).contains(&value));
+	}
+
+	// Functions using random() are evaluated at runtime
+	run("fn roll() { floor(rand_range(1, 7)) }");
+	builtins::seed_random(1);
+	let rolls: Vec<Option<f64>> = (0..3).map(|_| run("roll()")).collect();
+	builtins::seed_random(1);
+	assert_eq!(rolls, (0..3).map(|_| run("roll()")).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_logical_operators_short_circuit() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	assert_eq!(run("0 && 1 / 0"), Some(0.0));
+	assert_eq!(run("1 || 1 / 0"), Some(1.0));
+	assert_eq!(run("1 && 0 || 2"), Some(1.0));
+	assert_eq!(run("0 || 0"), Some(0.0));
+	assert_eq!(run("(0 || 3) + 1"), Some(2.0));
+	assert_eq!(run("ok = 2 > 1 && 3 > 2"), Some(1.0));
+	assert_eq!(get_variable("ok"), Some(1.0));
+	assert_eq!(run("1 &&"), None);
+
+	// Compiled functions branch around the right side instead of evaluating it
+	run("fn positive_inverse(x) { x != 0 && 1 / x > 0 }");
+	let function = FUNCTIONS
+		.lock()
+		.unwrap()
+		.get("positive_inverse")
+		.cloned()
+		.unwrap();
+	assert!(!function_contains_user_function_calls(&function));
+	let mut codegen = LLVMCodeGen::new(llvm_context()).unwrap();
+	assert!(
+		codegen
+			.compile_function("positive_inverse", &function)
+			.is_ok()
+	);
+	assert_eq!(run("positive_inverse(0)"), Some(0.0));
+	assert_eq!(run("positive_inverse(2)"), Some(1.0));
+	assert_eq!(run("positive_inverse(-2)"), Some(0.0));
+
+	run("fn either_zero(a, b) { a == 0 || b == 0 }");
+	assert_eq!(run("either_zero(1, 0)"), Some(1.0));
+	assert_eq!(run("either_zero(1, 2)"), Some(0.0));
+}
+
+#[test]
+fn test_function_status() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+	let _settings = save_settings();
+
+	run("fn square(x) { x * x }");
+	run("fn sum_squares(a, b) { square(a) + square(b) }");
+	run("fn hypot(a, b) { sqrt(a * a + b * b) }");
+	run("fn sign(x) { x < 0 ? -1 : 1 }");
+
+	// The status comes from what was worked out at definition, without compiling again
+	settings().profile = true;
+	*profile_counts() = ProfileCounts::ZERO;
+	assert_eq!(
+		function_status(),
+		vec![
+			("hypot".to_string(), FunctionStatus::Runtime),
+			("sign".to_string(), FunctionStatus::Compiled),
+			("square".to_string(), FunctionStatus::Compiled),
+			("sum_squares".to_string(), FunctionStatus::Runtime),
+		]
+	);
+	assert_eq!(profile_counts().compilations, 0);
+}
+
+#[test]
+fn test_integer_and_real_arithmetic() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	let value = |input: &str| evaluate_value(&lex(input)).unwrap().unwrap();
+
+	// Integer operands keep integer results when the result is exact
+	assert_eq!(value("9 / 3"), Value::Integer(3));
+	assert_eq!(value("9.0 / 3"), Value::Number(3.0));
+	assert_eq!(value("7 / 2"), Value::Number(3.5));
+	assert_eq!(value("10 % 3"), Value::Integer(1));
+	assert_eq!(value("10 % 3.0"), Value::Number(1.0));
+	assert_eq!(value("2 ^ 10"), Value::Integer(1024));
+	assert_eq!(value("2 ^ -1"), Value::Number(0.5));
+	assert_eq!(value("-(2 + 3) * 4"), Value::Integer(-20));
+
+	// Overflowing integer results become real
+	assert_eq!(
+		value("9223372036854775807 + 1"),
+		Value::Number(9223372036854775808.0)
+	);
+
+	assert_eq!(run("10 % 3"), Some(1.0));
+	assert_eq!(run("7.5 % 2"), Some(1.5));
+	assert_eq!(run("1 + 10 % 4 * 2"), Some(5.0));
+	assert_eq!(run("5 % 0"), None);
+
+	run("fn remainder(a, b) { a % b }");
+	assert_eq!(run("remainder(10, 4)"), Some(2.0));
+}
+
+#[test]
+fn test_explain_traces_evaluation_steps() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	let lines = explain("2 + 3 * 4").unwrap();
+	assert_eq!(lines[0], "Infix:   2 + 3 * 4");
+	assert_eq!(lines[1], "Postfix: 2 3 4 * +");
+	assert!(lines[2].trim_start().starts_with("3 * 4 = 12"));
+	assert!(lines[2].ends_with("stack: [2, 12]"));
+	assert!(lines[3].trim_start().starts_with("2 + 12 = 14"));
+	assert!(lines[3].ends_with("stack: [14]"));
+	assert_eq!(lines[4], "Result:  14");
+	assert_eq!(lines.len(), 5);
+
+	let lines = explain("x = -2 ^ 2").unwrap();
+	let steps: Vec<&str> = lines.iter().map(|line| line.trim_start()).collect();
+	assert!(steps[2].starts_with("2 ^ 2 = 4"));
+	assert!(steps[3].starts_with("-(4) = -4"));
+	assert!(steps[4].starts_with("x = -4"));
+
+	assert!(explain("2 +").is_err());
+}
+
+#[test]
+fn test_precedence_table() {
+	let precedence = |op: &str| get_precedence(op).unwrap().0;
+
+	assert!(precedence("=") < precedence("||"));
+	assert!(precedence("||") < precedence("&&"));
+	assert!(precedence("&&") < precedence("=="));
+	assert!(precedence("==") < precedence("<"));
+	assert!(precedence("<") < precedence("+"));
+	assert_eq!(precedence("+"), precedence("-"));
+	assert!(precedence("+") < precedence("*"));
+	assert_eq!(precedence("*"), precedence("%"));
+	assert!(precedence("*") < precedence(UNARY_MINUS));
+	assert!(precedence(UNARY_MINUS) < precedence("^"));
+
+	assert_eq!(get_precedence("-").unwrap().1, Associativity::Left);
+	assert_eq!(get_precedence("^").unwrap().1, Associativity::Right);
+	assert_eq!(get_precedence("=").unwrap().1, Associativity::Right);
+	assert!(get_precedence("(").is_none());
+
+	let postfix = |input: &str| parse::tokens_to_string(&infix_to_postfix(&lex(input)));
+	assert_eq!(postfix("a - b - c"), "a b - c -");
+	assert_eq!(postfix("a ^ b ^ c"), "a b c ^ ^");
+	assert_eq!(postfix("a = b = c"), "a b c = =");
+	assert_eq!(postfix("a + b < c * d"), "a b + c d * <");
+	assert_eq!(postfix("(a + b) % c"), "a b + c %");
+}
+
+#[test]
+fn test_unmatched_closing_brace_is_error() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	assert_eq!(run("2 + 3 }"), None);
+	assert_eq!(run("x = 1 }\ny = 2"), None);
+	assert_eq!(get_variable("y"), None);
+	assert_eq!(run("{ 2 + 3 }"), Some(5.0));
+	assert!(compile_expr_ir("2 + 3 }").is_err());
+}
+
+#[test]
+fn test_structural_assignment() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	assert_eq!(run("x = 2 + 3"), Some(5.0));
+	assert_eq!(get_variable("x"), Some(5.0));
+
+	assert_eq!(run("a = b = 5"), Some(5.0));
+	assert_eq!(get_variable("a"), Some(5.0));
+	assert_eq!(get_variable("b"), Some(5.0));
+
+	assert_eq!(run("x = x + 1"), Some(6.0));
+	assert_eq!(get_variable("x"), Some(6.0));
+
+	// Values that use the assignment's result keep working
+	assert_eq!(run("y = -x * 2"), Some(-12.0));
+	assert_eq!(run("z = y < 0 ? 1 : 2"), Some(1.0));
+	assert_eq!(run("w = (z = 4) + 1"), Some(5.0));
+	assert_eq!(get_variable("z"), Some(4.0));
+
+	// `=` without a variable name to its left is an error
+	assert_eq!(run("5 = 3"), None);
+	assert_eq!(run("x + 1 = 3"), None);
+	assert_eq!(run("x ="), None);
+}
+
+#[test]
+fn test_self_referencing_assignment_reads_previous_value() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	run("x = 5; x = x + 1");
+	assert_eq!(get_variable("x"), Some(6.0));
+
+	run("x = 5; x = x * x");
+	assert_eq!(get_variable("x"), Some(25.0));
+
+	// The right side is evaluated completely before the variable changes
+	run("x = 5\nx = x * 2 + x");
+	assert_eq!(get_variable("x"), Some(15.0));
+	run("x = 5; y = x = x - 1");
+	assert_eq!(get_variable("x"), Some(4.0));
+	assert_eq!(get_variable("y"), Some(4.0));
+}
+
+#[test]
+fn test_plus_dispatches_on_operand_kinds() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	let value = |input: &str| evaluate_value(&lex(input)).unwrap().unwrap();
+
+	// Numbers add
+	assert_eq!(value("2 + 3"), Value::Integer(5));
+	assert_eq!(value("2 + 0.5"), Value::Number(2.5));
+
+	// Strings concatenate
+	assert_eq!(
+		value("\"foo\" + \"bar\""),
+		Value::String("foobar".to_string())
+	);
+	assert_eq!(
+		value("\"a\" + \"b\" + \"c\" == \"abc\""),
+		Value::Number(1.0)
+	);
+
+	// Lists add element by element
+	let left = Value::List(vec![Value::Integer(1), Value::Integer(2)]);
+	let right = Value::List(vec![Value::Integer(3), Value::Integer(4)]);
+	let sum = Value::add(&left, &right).unwrap();
+	assert_eq!(sum, Value::List(vec![Value::Integer(4), Value::Integer(6)]));
+	assert_eq!(sum.to_string(), "[4, 6]");
+
+	// Mixing kinds is an error rather than a silent conversion
+	assert!(evaluate_value(&lex("\"a\" + 1")).is_err());
+	assert!(Value::add(&left, &Value::String("x".to_string())).is_err());
+	assert_eq!(run("\"a\" + 1"), None);
+}
+
+#[test]
+fn test_result_stats_accumulate() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+	*result_stats() = ResultStats::EMPTY;
+	assert_eq!(format_stats(&result_stats()), "count: 0");
+
+	// Nothing is recorded until recording is turned on
+	run("100");
+	assert_eq!(result_stats().count, 0);
+
+	let settings_guard = save_settings();
+	settings().record_stats = true;
+	run("2 + 2");
+	run("x = 10");
+	run("x * 3");
+	run("-1");
+	run("7");
+	// Errors produce no result to record
+	run("1 / 0");
+	// Function call results are printed and recorded like any other
+	run("fn halve(n) { n / 2 }");
+	run("halve(4)");
+	drop(settings_guard);
+
+	// Assignments aren't printed, so they aren't recorded either
+	let stats = *result_stats();
+	assert_eq!(stats.count, 5);
+	assert_eq!(stats.min, -1.0);
+	assert_eq!(stats.max, 30.0);
+	assert_eq!(stats.mean(), Some(8.4));
+	assert_eq!(
+		format_stats(&stats),
+		"count: 5  min: -1  max: 30  mean: 8.4"
+	);
+
+	*result_stats() = ResultStats::EMPTY;
+}
+
+#[test]
+fn test_integer_division_setting() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	let _settings = save_settings();
+	let value = |input: &str| evaluate_value(&lex(input)).unwrap().unwrap();
+
+	// Dividing integers gives a real number by default
+	assert_eq!(Settings::default().integer_division, IntegerDivision::Float);
+	assert_eq!(run("5 / 2"), Some(2.5));
+	assert_eq!(run("5 / 2 == 2.5"), Some(1.0));
+
+	// Floor division rounds integer quotients down, like div
+	settings().integer_division = IntegerDivision::Floor;
+	assert_eq!(value("5 / 2"), Value::Integer(2));
+	assert_eq!(run("-5 / 2"), Some(-3.0));
+	assert_eq!(run("6 / 3"), Some(2.0));
+	assert_eq!(run("5 / 0"), None);
+
+	// A real operand still divides exactly, and variables and parameters hold reals
+	assert_eq!(value("5.0 / 2"), Value::Number(2.5));
+	assert_eq!(run("5 / 2.0"), Some(2.5));
+	run("x = 5");
+	assert_eq!(run("x / 2"), Some(2.5));
+
+	// Compiled code only has reals, so functions dividing are left to the interpreter
+	run("fn halves() { 5 / 2 }");
+	run("fn h(a, b) { a / b }");
+	assert_eq!(run("halves()"), Some(2.0));
+	assert_eq!(run("h(5, 2)"), Some(2.5));
+	let function = FUNCTIONS.lock().unwrap().get("halves").cloned().unwrap();
+	let mut codegen = LLVMCodeGen::new(llvm_context()).unwrap();
+	let error = codegen.compile_function("halves", &function).unwrap_err();
+	assert!(error.to_string().contains("use runtime evaluation"));
+
+	// Changing the setting back lets them compile again
+	settings().integer_division = IntegerDivision::Float;
+	assert_eq!(value("5 / 2"), Value::Number(2.5));
+	assert_eq!(run("halves()"), Some(2.5));
+	assert_eq!(run("h(5, 2)"), Some(2.5));
+}
+
+#[test]
+fn test_exact_mode_keeps_fractions() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+	let _settings = save_settings();
+
+	let value = |input: &str| evaluate_value(&lex(input)).unwrap().unwrap();
+
+	// Floats lose the exact result
+	assert_eq!(run("1/3 + 1/3 + 1/3 == 1"), Some(1.0));
+	assert_eq!(run("1/10 + 2/10 == 3/10"), Some(0.0));
+
+	settings().exact = true;
+	assert_eq!(value("1/3"), Value::Rational(1, 3));
+	assert_eq!(value("1/3 + 1/3 + 1/3"), Value::Integer(1));
+	assert_eq!(run("1/3 + 1/3 + 1/3 == 1"), Some(1.0));
+	assert_eq!(run("1/10 + 2/10 == 3/10"), Some(1.0));
+	assert_eq!(value("2/4 - 3/4"), Value::Rational(-1, 4));
+	assert_eq!(value("(2/3) * (9/4)"), Value::Rational(3, 2));
+	assert_eq!(value("(1/2) / (1/4)"), Value::Integer(2));
+	assert_eq!(value("(2/3) ^ 2"), Value::Rational(4, 9));
+	assert_eq!(value("(2/3) ^ -2"), Value::Rational(9, 4));
+	assert_eq!(value("(7/2) % 1"), Value::Rational(1, 2));
+	assert_eq!(value("-(1/3)"), Value::Rational(-1, 3));
+	assert_eq!(value("1/3 < 1/2"), Value::Number(1.0));
+	assert_eq!(value("1/3").to_string(), "1/3");
+
+	// Real operands and transcendental functions fall back to floating point
+	assert_eq!(value("1/2 + 0.25"), Value::Number(0.75));
+	assert_eq!(value("4 ^ (1/2)"), Value::Number(2.0));
+	assert_eq!(run("sqrt(1/4)"), Some(0.5));
+	assert_eq!(run("1/0"), None);
+	settings().exact = false;
+
+	assert_eq!(value("1/3"), Value::Number(1.0 / 3.0));
+}
+
+#[test]
+fn test_truthiness_is_consistent() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	assert!(!is_truthy(0.0));
+	assert!(!is_truthy(-0.0));
+	assert!(is_truthy(1.0));
+	assert!(is_truthy(0.5));
+	assert!(is_truthy(-3.0));
+	assert!(is_truthy(f64::INFINITY));
+	assert!(!is_truthy(f64::NAN));
+
+	// NaN is false in every kind of condition
+	run("nan = sqrt(-1)");
+	assert_eq!(run("nan ? 1 : 2"), Some(2.0));
+	assert_eq!(run("nan && 1"), Some(0.0));
+	assert_eq!(run("nan || 0"), Some(0.0));
+	assert_eq!(run("if (nan) { 1 } else { 2 }"), Some(2.0));
+	assert_eq!(run("-2 ? 1 : 2"), Some(1.0));
+
+	// Compiled code agrees with the interpreter
+	run("fn choose(x) { x ? 1 : 2 }");
+	run("fn both(a, b) { a && b }");
+	assert_eq!(run("choose(nan)"), Some(2.0));
+	assert_eq!(run("choose(-2)"), Some(1.0));
+	assert_eq!(run("choose(0)"), Some(2.0));
+	assert_eq!(run("both(nan, 1)"), Some(0.0));
+	assert_eq!(run("both(-1, 3)"), Some(1.0));
+}
+
+#[test]
+fn test_undefined_variable_default_and_strict_mode() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+	let _settings = save_settings();
+
+	// Undefined variables read as 0 unless configured otherwise
+	assert_eq!(run("undefined_var + 5"), Some(5.0));
+
+	settings().undefined_default = 1.0;
+	assert_eq!(run("undefined_var + 5"), Some(6.0));
+	assert_eq!(run("undefined_var + 5 == 6"), Some(1.0));
+	run("fn add_missing(x) { x + missing }");
+	assert_eq!(run("add_missing(5)"), Some(6.0));
+
+	// Strict mode turns undefined reads into errors in both evaluators
+	settings().strict_variables = true;
+	assert_eq!(run("undefined_var + 5"), None);
+	assert!(
+		evaluate_tokens(&lex("undefined_var + 5"))
+			.unwrap_err()
+			.to_string()
+			.contains("Undefined variable 'undefined_var'")
+	);
+	assert_eq!(run("add_missing(5)"), None);
+	let mut codegen = LLVMCodeGen::new(llvm_context()).unwrap();
+	assert!(
+		codegen
+			.compile_expression(&lex("missing * 2"), &HashMap::new())
+			.is_err()
+	);
+
+	// Defined variables are unaffected
+	run("missing = 2");
+	assert_eq!(run("add_missing(5)"), Some(7.0));
+	assert_eq!(run("missing * 2"), Some(4.0));
+
+	settings().strict_variables = false;
+	settings().undefined_default = 0.0;
+	assert_eq!(run("never_set"), Some(0.0));
+}
+
+#[test]
+fn test_negative_literals_are_single_tokens() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	let is_integer = |token: &Token, expected: i64| matches!(token, Token::Number(lex::LangNumber::Integer(n)) if n.value == expected);
+	let is_operator = |token: &Token, expected: &str| matches!(token, Token::Operator(op) if op.value == expected);
+
+	// A leading minus is part of the literal
+	let tokens = lex("-5 + 3");
+	assert_eq!(tokens.len(), 3);
+	assert!(is_integer(&tokens[0], -5));
+	assert_eq!(run("-5 + 3"), Some(-2.0));
+
+	// Binary subtraction is left alone, with or without spaces
+	for input in ["10 - 5", "10 -5", "10-5"] {
+		let tokens = lex(input);
+		assert_eq!(tokens.len(), 3, "{}", input);
+		assert!(is_operator(&tokens[1], "-"), "{}", input);
+		assert!(is_integer(&tokens[2], 5), "{}", input);
+		assert_eq!(run(input), Some(5.0));
+	}
+	assert!(is_operator(&lex("(1) -5")[3], "-"));
+	assert!(is_operator(&lex("x -5")[1], "-"));
+
+	// After an operator, opening bracket, comma or assignment it is a literal
+	assert!(is_integer(&lex("2 * -3")[2], -3));
+	assert!(is_integer(&lex("2*-3")[2], -3));
+	assert!(is_integer(&lex("f(-1, -2.5)")[2], -1));
+	assert!(matches!(
+		lex("f(-1, -2.5)")[4],
+		Token::Number(lex::LangNumber::RealNumber(ref n)) if n.value == -2.5
+	));
+	assert!(is_integer(&lex("x = -4")[2], -4));
+	assert!(is_integer(&lex("x =-4")[2], -4));
+	assert_eq!(run("2*-3"), Some(-6.0));
+	assert_eq!(run("1 - -1"), Some(2.0));
+	assert_eq!(run("2^-1"), Some(0.5));
+
+	// A following exponent keeps the minus as negation of the power
+	assert!(is_operator(&lex("-2 ^ 2")[0], "-"));
+	assert_eq!(run("-2 ^ 2"), Some(-4.0));
+	assert_eq!(
+		run("-9223372036854775808 + 0"),
+		Some(-9223372036854775808.0)
+	);
+}
+
+#[test]
+fn test_parse_program_returns_syntax_tree() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	let program = "fn square(x) { x * x }\n\
+		cube = (x) => { x * square(x) }\n\
+		y = 3\n\
+		if (y > 2) { square(y) } else { 0 }\n\
+		print(y)";
+	let block = parse::parse_program(program).unwrap();
+	let kinds: Vec<&str> = block
+		.items
+		.iter()
+		.map(|item| match item {
+			parse::LangBlockItem::Line(_) => "line",
+			parse::LangBlockItem::Block(_) => "block",
+			parse::LangBlockItem::Function(_) => "function",
+			parse::LangBlockItem::NamedFunction(_) => "named function",
+			parse::LangBlockItem::FunctionCall(_) => "call",
+			parse::LangBlockItem::List(_) => "list",
+			parse::LangBlockItem::If(_) => "if",
+			parse::LangBlockItem::Match(_) => "match",
+		})
+		.collect();
+	assert_eq!(
+		kinds,
+		["named function", "named function", "line", "if", "call"]
+	);
+	match &block.items[1] {
+		parse::LangBlockItem::NamedFunction(function) => {
+			assert_eq!(function.name, "cube");
+			assert_eq!(function.parameters, ["x"]);
+		}
+		_ => unreachable!(),
+	}
+
+	// Parsing alone defines and evaluates nothing
+	assert!(get_function_param_count("square").is_none());
+	assert_eq!(get_variable("y"), None);
+
+	// Unbalanced braces are parse errors
+	assert_eq!(
+		parse::parse_program("fn f(x) { x").err().unwrap().message,
+		"Syntax error: missing '}'"
+	);
+	assert_eq!(
+		parse::parse_program("1 }").err().unwrap().message,
+		"Syntax error: unmatched '}'"
+	);
+	assert!(parse::parse_program("if (1) { 2 } else 3").is_err());
+	assert_eq!(run("fn f(x) { x"), None);
+}
+
+#[test]
+fn test_parser_recovers_after_malformed_statement() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	// The bad statement is reported and skipped, the good ones around it are kept
+	let (block, diagnostics) =
+		parse::parse_program_with_recovery(lex("x = 1 + 2\nfn f(a: text) { a }\ny = 3 * 4"));
+	assert_eq!(
+		diagnostics,
+		[parse::ParseError {
+			message: "Syntax error: a parameter type must be one of int, real".to_string()
+		}]
+	);
+	let lines: Vec<String> = block
+		.items
+		.iter()
+		.map(|item| match item {
+			parse::LangBlockItem::Line(line) => parse::tokens_to_string(&line.tokens),
+			_ => panic!("expected only lines"),
+		})
+		.collect();
+	assert_eq!(lines, ["x = 1 + 2", "y = 3 * 4"]);
+
+	// A malformed statement's body is skipped whole, even across lines
+	let (block, diagnostics) =
+		parse::parse_program_with_recovery(lex("fn f(a: text) {\n  y = 5\n}\nz = 1"));
+	assert_eq!(diagnostics.len(), 1);
+	match block.items.as_slice() {
+		[parse::LangBlockItem::Line(line)] => {
+			assert_eq!(parse::tokens_to_string(&line.tokens), "z = 1");
+		}
+		_ => panic!("expected only the line after the function"),
+	}
+
+	// So is the rest of the body around a malformed statement inside it
+	let (block, diagnostics) =
+		parse::parse_program_with_recovery(lex("fn f(x) {\n match x { y => { 1 } }\n 2\n}\nz = 1"));
+	assert_eq!(diagnostics.len(), 1);
+	match block.items.as_slice() {
+		[parse::LangBlockItem::Line(line)] => {
+			assert_eq!(parse::tokens_to_string(&line.tokens), "z = 1");
+		}
+		_ => panic!("expected only the line after the function"),
+	}
+
+	// Every malformed statement gets its own diagnostic
+	let (block, diagnostics) = parse::parse_program_with_recovery(lex(
+		"1 }\nfn f(x) when { x }\n2; match 1 { 1 => 2 }; 3",
+	));
+	assert_eq!(diagnostics.len(), 3);
+	assert_eq!(block.items.len(), 3);
+
+	// Well-formed input parses the same as without recovery
+	let program = "fn square(x) { x * x }\nsquare(3)";
+	let (block, diagnostics) = parse::parse_program_with_recovery(lex(program));
+	assert!(diagnostics.is_empty());
+	assert!(block == parse::parse_program(program).unwrap());
+
+	// Nothing runs when any statement is malformed
+	assert_eq!(run("z = 1\nfn f(a: text) { a }"), None);
+	assert_eq!(get_variable("z"), None);
+}
+
+#[test]
+fn test_block_with_definitions_returns_last_expression() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	assert_eq!(run("fn f(x){x}; 2+3"), Some(5.0));
+	assert_eq!(run("g = (x) => { x * 2 }\n1 + g(4)"), Some(9.0));
+	assert_eq!(run("{ fn h(x) { x + 1 }\nh(1) }"), Some(2.0));
+
+	// A definition on its own, or last in the block, still has no value
+	assert_eq!(run("fn f(x){x}"), None);
+	assert_eq!(run("2 + 3; fn f(x){x}"), None);
+	assert!(function_exists("f"));
+	assert!(function_exists("g"));
+}
+
+#[test]
+fn test_function_source_round_trips() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	run("fn half(x) { x / 2.0 }");
+	run("fn classify(x, limit) {\n\
+			y = half(x) - 1.5\n\
+			if (y < -limit) { -1 } else if (y == 0) { 0 } else { max(y, limit) }\n\
+		}");
+
+	assert_eq!(
+		function_source("half").unwrap(),
+		"fn half(x) {\n\tx / 2.0\n}"
+	);
+
+	for name in ["half", "classify"] {
+		let function = function_ast(name).unwrap();
+		let source = function_source(name).unwrap();
+
+		// Parsing the reconstructed source gives back the same definition
+		let block = parse::parse_program(&source).unwrap();
+		assert_eq!(block.items.len(), 1);
+		match &block.items[0] {
+			parse::LangBlockItem::NamedFunction(reparsed) => {
+				assert_eq!(reparsed.name, name);
+				assert!(reparsed.parameters == function.parameters);
+				assert!(reparsed.body == function.body, "{}", source);
+			}
+			_ => panic!("expected a function definition in {}", source),
+		}
+	}
+
+	assert!(function_source("missing").is_none());
+	assert!(function_ast("missing").is_none());
+}
+
+#[test]
+fn test_tuple_assignment_and_call_commas() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	run("fn f(a, b) { a * 10 + b }");
+
+	// Commas inside the call's parentheses belong to the call
+	assert!(split_tuple_assignment(&lex("x = f(1, 2)")).is_none());
+	assert_eq!(run("x = f(1, 2)"), Some(12.0));
+	assert_eq!(get_variable("x"), Some(12.0));
+	assert_eq!(run("x = max(f(1, 2), 3)"), Some(12.0));
+
+	// Commas outside parentheses separate the targets and values
+	let tokens = lex("x, y = 1, 2");
+	let (names, values) = split_tuple_assignment(&tokens).unwrap();
+	assert_eq!(names, ["x", "y"]);
+	assert_eq!(values.len(), 2);
+	run("x, y = 1, 2");
+	assert_eq!(get_variable("x"), Some(1.0));
+	assert_eq!(get_variable("y"), Some(2.0));
+
+	// Both at once
+	run("a, b = f(3, 4), min(5, 6)");
+	assert_eq!(get_variable("a"), Some(34.0));
+	assert_eq!(get_variable("b"), Some(5.0));
+
+	// All values are evaluated before any variable changes
+	run("x, y = y, x");
+	assert_eq!(get_variable("x"), Some(2.0));
+	assert_eq!(get_variable("y"), Some(1.0));
+
+	// Mismatched counts and non-variable targets are errors
+	assert_eq!(run("x, y = 1, 2, 3"), None);
+	assert_eq!(run("x = 1, 2"), None);
+	assert_eq!(run("x, 2 = 1, 2"), None);
+	assert_eq!(get_variable("x"), Some(2.0));
+}
+
+#[test]
+fn test_guarded_function_clauses() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	run("fn abs(x) when x < 0 { -x } fn abs(x) { x }");
+	assert_eq!(run("abs(-3)"), Some(3.0));
+	assert_eq!(run("abs(3)"), Some(3.0));
+	assert_eq!(run("1 + abs(-3) == 4"), Some(1.0));
+
+	// Guards are tried in definition order; the unguarded clause is the fallback
+	run("fn sign(x) { 0 }");
+	run("fn sign(x) when x > 0 { 1 }");
+	run("fn sign(x) when x < 0 { -1 }");
+	assert_eq!(run("sign(5)"), Some(1.0));
+	assert_eq!(run("sign(-5)"), Some(-1.0));
+	assert_eq!(run("sign(0)"), Some(0.0));
+
+	// Redefining a clause with the same guard replaces it
+	run("fn sign(x) when x > 0 { 2 }");
+	assert_eq!(run("sign(5)"), Some(2.0));
+
+	// Defining the same fallback again keeps the guards, but a new one replaces them all
+	run("fn sign(x) { 0 }");
+	assert_eq!(run("sign(-5)"), Some(-1.0));
+	run("fn sign(x) { 7 }");
+	assert_eq!(run("sign(5)"), Some(7.0));
+	assert_eq!(run("sign(-5)"), Some(7.0));
+	assert!(guarded_clauses("sign").is_empty());
+
+	// Without a fallback, a call no guard accepts is an error
+	run("fn positive_root(x) when x >= 0 { sqrt(x) }");
+	assert_eq!(run("positive_root(9)"), Some(3.0));
+	assert_eq!(run("positive_root(-9)"), None);
+
+	// Guards can see every parameter and call other functions
+	run("fn clamp(x, hi) when abs(x) > hi { hi } fn clamp(x, hi) { x }");
+	assert_eq!(run("clamp(-7, 5)"), Some(5.0));
+	assert_eq!(run("clamp(4, 5)"), Some(4.0));
+
+	// The guard survives reconstruction of the source
+	let block = parse::parse_program("fn abs(x) when x < 0 { -x }").unwrap();
+	assert_eq!(
+		parse::block_to_source(&block, 0),
+		"fn abs(x) when x < 0 {\n\t- x\n}\n"
+	);
+	assert!(parse::parse_program("fn f(x) when { x }").is_err());
+}
+
+#[test]
+fn test_strings_in_print_len_and_comparisons() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	// print is run for its output and has no value of its own
+	ERROR_REPORTED.store(false, AtomicOrdering::Relaxed);
+	assert_eq!(run("print(\"done\")"), None);
+	assert!(!ERROR_REPORTED.load(AtomicOrdering::Relaxed));
+	assert_eq!(run("x = print(\"done\")"), None);
+
+	assert_eq!(run("len(\"abc\")"), Some(3.0));
+	assert_eq!(run("len(\"abc\") == 3"), Some(1.0));
+	assert_eq!(run("len(\"\") + 1"), Some(1.0));
+	assert_eq!(run("len(\"ab\") * 2 + 1"), Some(5.0));
+	assert_eq!(run("\"abc\" == \"abc\""), Some(1.0));
+	assert_eq!(run("\"abc\" < \"abd\""), Some(1.0));
+
+	// Strings still can't be used as numbers
+	assert_eq!(run("\"a\" * 2"), None);
+	assert!(evaluate_tokens(&lex("\"a\" * 2")).is_err());
+	assert_eq!(run("len(5)"), None);
+	assert_eq!(run("sqrt(\"a\")"), None);
+}
+
+#[test]
+fn test_stepper_runs_statements_in_order() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	let program = "x = 1\ny = x + 1\nfn twice(a) { a * 2 }\ntwice(y)\ny +\nx + y";
+	let mut stepper = Stepper::new(program).unwrap();
+	assert_eq!(stepper.next_source().unwrap(), "x = 1");
+
+	// Each step only runs its own statement
+	let first = stepper.next().unwrap();
+	assert_eq!(first.result, Some(1.0));
+	assert_eq!(get_variable("x"), Some(1.0));
+	assert_eq!(get_variable("y"), None);
+
+	let rest: Vec<Step> = stepper.collect();
+	let sources: Vec<&str> = rest.iter().map(|step| step.source.as_str()).collect();
+	assert_eq!(
+		sources,
+		[
+			"y = x + 1",
+			"fn twice(a) {\n\ta * 2\n}",
+			"twice(y)",
+			"y +",
+			"x + y"
+		]
+	);
+	let results: Vec<Option<f64>> = rest.iter().map(|step| step.result).collect();
+	assert_eq!(results, [Some(2.0), None, Some(4.0), None, Some(3.0)]);
+
+	// A failing statement is reported and stepping carries on
+	let failed: Vec<bool> = rest.iter().map(|step| step.failed).collect();
+	assert_eq!(failed, [false, false, false, true, false]);
+
+	assert!(Stepper::new("fn broken(x) { x").is_err());
+}
+
+#[test]
+fn test_numeric_derivative() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	run("fn square(x) { x * x }");
+	let slope = run("deriv(square, 3)").unwrap();
+	assert!((slope - 6.0).abs() < 1e-6, "{}", slope);
+
+	// Built-ins and guarded clauses can be differentiated too
+	assert!((run("deriv(sin, 0)").unwrap() - 1.0).abs() < 1e-6);
+	run("fn relu(x) when x < 0 { 0 } fn relu(x) { x }");
+	assert!((run("deriv(relu, 2)").unwrap() - 1.0).abs() < 1e-6);
+	assert!(run("deriv(relu, -2)").unwrap().abs() < 1e-6);
+
+	// Large x keeps a sensible step
+	let slope = run("deriv(square, 1000000)").unwrap();
+	assert!((slope - 2e6).abs() < 1e-3, "{}", slope);
+
+	// The result composes with other expressions
+	assert!((run("1 + deriv(square, 2)").unwrap() - 5.0).abs() < 1e-6);
+
+	// The first argument must name a function
+	assert_eq!(run("deriv(5, 3)"), None);
+	assert_eq!(run("deriv(nope, 3)"), None);
+	assert_eq!(run("deriv(square)"), None);
+}
+
+#[test]
+fn test_numeric_integral() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	run("fn square(x) { x * x }");
+	let area = run("integrate(square, 0, 3)").unwrap();
+	assert!((area - 9.0).abs() < 1e-9, "{}", area);
+
+	// An empty interval has no area; built-ins and expression bounds work too
+	assert_eq!(run("integrate(square, 2, 2)"), Some(0.0));
+	let area = run("integrate(sin, 0, 3.141592653589793)").unwrap();
+	assert!((area - 2.0).abs() < 1e-8, "{}", area);
+	let area = run("integrate(square, 0, 1 + 2)").unwrap();
+	assert!((area - 9.0).abs() < 1e-9, "{}", area);
+
+	// Reversed bounds are rejected
+	assert_eq!(run("integrate(square, 3, 0)"), None);
+	assert_eq!(run("integrate(square, 0)"), None);
+}
+
+#[test]
+fn test_bisection_root_finding() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	run("fn g(x) { x * x - 2 }");
+	let root = run("solve(g, 0, 2)").unwrap();
+	assert!((root - 2f64.sqrt()).abs() < 1e-12, "{}", root);
+
+	// The bounds may come in either order, and an endpoint can be the root
+	let root = run("solve(g, -1, -2)").unwrap();
+	assert!((root + 2f64.sqrt()).abs() < 1e-12, "{}", root);
+	assert_eq!(run("solve(sin, 0, 1)"), Some(0.0));
+
+	// Without a sign change there is no bracketed root
+	assert_eq!(run("solve(g, 2, 3)"), None);
+	assert_eq!(run("solve(g, -1, 1)"), None);
+}
+
+#[test]
+fn test_apply_operator() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	run("fn square(x) { x * x }");
+	run("fn increment(x) { x + 1 }");
+	assert_eq!(run("square @ 3"), Some(9.0));
+	assert_eq!(run("square @ 3 == 9"), Some(1.0));
+
+	// Right-associative: the rightmost application runs first
+	assert_eq!(run("increment @ square @ 2"), Some(5.0));
+	assert_eq!(run("square @ increment @ 2"), Some(9.0));
+
+	// `@` takes a single operand, binding tighter than arithmetic
+	assert_eq!(run("square @ 1 + 2"), Some(3.0));
+	assert_eq!(run("square @ (1 + 2)"), Some(9.0));
+	assert_eq!(run("2 * square @ -3"), Some(18.0));
+	assert_eq!(run("square @ increment(2)"), Some(9.0));
+	assert_eq!(run("sqrt @ 16"), Some(4.0));
+	assert_eq!(run("y = square @ 4"), Some(16.0));
+
+	// Function bodies may apply functions too
+	run("fn twice_squared(x) { 2 * square @ x }");
+	assert_eq!(run("twice_squared(3)"), Some(18.0));
+
+	// The left operand must be a function
+	assert_eq!(run("y @ 3"), None);
+	assert_eq!(run("3 @ 3"), None);
+	assert_eq!(run("square @"), None);
+}
+
+#[test]
+fn test_pipe_operator() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	run("fn square(x) { x * x }");
+	run("fn increment(x) { x + 1 }");
+	assert_eq!(run("3 |> increment |> square"), Some(16.0));
+	assert_eq!(run("3 |> square |> increment"), Some(10.0));
+
+	// The whole expression before the first `|>` is piped in
+	assert_eq!(run("1 + 2 |> square"), Some(9.0));
+	assert_eq!(run("(1 |> increment) * 10"), Some(20.0));
+	assert_eq!(run("x = 4 |> sqrt"), Some(2.0));
+	assert_eq!(run("2 |> square @ 3"), None);
+	assert_eq!(run("square @ 2 |> increment"), Some(5.0));
+
+	run("fn pipeline(x) { x |> increment |> square }");
+	assert_eq!(run("pipeline(1)"), Some(4.0));
+
+	// Each stage must name a function
+	assert_eq!(run("3 |> x"), None);
+	assert_eq!(run("3 |> 4"), None);
+	assert_eq!(run("|> square"), None);
+}
+
+#[test]
+fn test_integer_overflow_modes() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	let value = |input: &str| evaluate_value(&lex(input)).unwrap().unwrap();
+
+	// Lenient by default: an overflowing integer result becomes real
+	assert_eq!(value("5000000000 * 5000000000"), Value::Number(2.5e19));
+	assert_eq!(
+		value("9223372036854775807 + 1"),
+		Value::Number(9223372036854775808.0)
+	);
+	assert_eq!(
+		value("2000000000 * 2000000000"),
+		Value::Integer(4000000000000000000)
+	);
+
+	let _settings = save_settings();
+	settings().overflow_errors = true;
+	assert_eq!(run("5000000000 * 5000000000"), None);
+	assert_eq!(run("9223372036854775807 + 1"), None);
+	assert_eq!(run("-9223372036854775807 - 2"), None);
+	assert_eq!(run("2 ^ 63"), None);
+	assert_eq!(run("-(-9223372036854775807 - 1)"), None);
+	// Results that fit, and real arithmetic, are unaffected
+	assert_eq!(run("1 ^ 5000000000"), Some(1.0));
+	assert_eq!(run("0 ^ 5000000000"), Some(0.0));
+	assert_eq!(run("(-1) ^ 5000000001"), Some(-1.0));
+	assert_eq!(run("-(-9223372036854775807)"), Some(9223372036854775807.0));
+	assert_eq!(
+		value("2000000000 * 2000000000"),
+		Value::Integer(4000000000000000000)
+	);
+	assert_eq!(value("2 ^ -1"), Value::Number(0.5));
+	assert_eq!(value("5000000000.0 * 5000000000"), Value::Number(2.5e19));
+	// Exact mode checks integer operands the same way
+	settings().exact = true;
+	assert_eq!(run("5000000000 * 5000000000"), None);
+}
+
+#[test]
+fn test_string_number_conversions() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	let value = |input: &str| evaluate_value(&lex(input)).unwrap().unwrap();
+
+	assert_eq!(run("to_number(\"2.5\")"), Some(2.5));
+	assert_eq!(value("to_number(\" 42 \")"), Value::Integer(42));
+	assert_eq!(run("to_number(\"-2.5\") * 2"), Some(-5.0));
+	assert_eq!(run("to_number(7)"), Some(7.0));
+	assert_eq!(run("to_number(\"abc\")"), None);
+	assert_eq!(run("to_number(\"\")"), None);
+	assert_eq!(run("to_number(\"1.2.3\")"), None);
+
+	assert_eq!(value("to_string(42)"), Value::String("42".to_string()));
+	assert_eq!(value("to_string(2.5)"), Value::String("2.5".to_string()));
+	assert_eq!(value("to_string(7 / 2)"), Value::String("3.5".to_string()));
+	assert_eq!(
+		value("to_string(0.00000000000000000001)"),
+		Value::String("0.00000000000000000001".to_string())
+	);
+	assert_eq!(
+		value("to_string(1 / 3)"),
+		Value::String("0.3333333333333333".to_string())
+	);
+	assert_eq!(
+		value("to_string(\"a\") + \"b\""),
+		Value::String("ab".to_string())
+	);
+	assert_eq!(run("to_string(42) == \"42\""), Some(1.0));
+	assert_eq!(run("len(to_string(12345))"), Some(5.0));
+
+	// The conversions round-trip
+	assert_eq!(run("to_number(to_string(1.25)) + 1"), Some(2.25));
+	assert_eq!(run("to_number(to_string(1 / 3)) == 1 / 3"), Some(1.0));
+}
+
+#[test]
+fn test_rounding_modes() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	let _settings = save_settings();
+
+	// 2.675 is slightly below 2.675 in binary, so the nearest two-place value is 2.67
+	assert_eq!(format_rounded(2.675, 2, RoundingMode::Nearest), "2.67");
+	assert_eq!(format_rounded(2.675, 2, RoundingMode::Up), "2.68");
+	assert_eq!(format_rounded(2.675, 2, RoundingMode::Down), "2.67");
+	assert_eq!(format_rounded(2.675, 2, RoundingMode::TowardZero), "2.67");
+
+	assert_eq!(format_rounded(-2.675, 2, RoundingMode::Nearest), "-2.67");
+	assert_eq!(format_rounded(-2.675, 2, RoundingMode::Up), "-2.67");
+	assert_eq!(format_rounded(-2.675, 2, RoundingMode::Down), "-2.68");
+	assert_eq!(format_rounded(-2.675, 2, RoundingMode::TowardZero), "-2.67");
+
+	// Values that already fit the precision are never moved
+	for rounding in [
+		RoundingMode::Up,
+		RoundingMode::Down,
+		RoundingMode::TowardZero,
+	] {
+		assert_eq!(format_rounded(2.67, 2, rounding), "2.67");
+		assert_eq!(format_rounded(3.0, 2, rounding), "3");
+	}
+	assert_eq!(format_rounded(1.0 / 3.0, 3, RoundingMode::Up), "0.334");
+	assert_eq!(format_rounded(0.001, 2, RoundingMode::Up), "0.01");
+	assert_eq!(format_rounded(-0.001, 2, RoundingMode::Up), "0");
+
+	// Only display is affected, the stored value keeps full precision
+	settings().precision = 2;
+	settings().rounding = RoundingMode::Up;
+	assert_eq!(format_value(2.675), "2.68");
+	assert_eq!(run("2.675 * 1000"), Some(2675.0));
+}
+
+#[test]
+fn test_list_element_wise_arithmetic() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	let value = |input: &str| evaluate_value(&lex(input)).unwrap().unwrap();
+	let list = |items: &[i64]| Value::List(items.iter().map(|&i| Value::Integer(i)).collect());
+
+	// Lists of the same length pair up their elements
+	assert_eq!(value("[1, 2, 3] + [4, 5, 6]"), list(&[5, 7, 9]));
+	assert_eq!(value("[4, 5, 6] - [1, 1, 1]"), list(&[3, 4, 5]));
+	assert_eq!(value("[1, 2] * [3, 4]"), list(&[3, 8]));
+
+	// A single value is broadcast to every element
+	assert_eq!(value("[1, 2, 3] * 2"), list(&[2, 4, 6]));
+	assert_eq!(value("10 - [1, 2]"), list(&[9, 8]));
+	assert_eq!(value("[1, 2] ^ 2 + 1"), list(&[2, 5]));
+	assert_eq!(value("-[1, -2]"), list(&[-1, 2]));
+	assert_eq!(
+		value("[1, 3] / 2"),
+		Value::List(vec![Value::Number(0.5), Value::Number(1.5)])
+	);
+
+	// Elements are full expressions, and lists can nest or be empty
+	assert_eq!(value("[1 + 1, (2 + 4) / 2, -4]"), list(&[2, 3, -4]));
+	assert_eq!(run("len([max(2, 3), 1])"), Some(2.0));
+	assert_eq!(value("[]"), list(&[]));
+	assert_eq!(
+		value("[[1], [2, 3]] * 2"),
+		Value::List(vec![list(&[2]), list(&[4, 6])])
+	);
+	assert_eq!(run("len([1, 2, 3] + [4, 5, 6])"), Some(3.0));
+
+	// Mismatched lengths and malformed literals are errors
+	assert!(evaluate_value(&lex("[1, 2, 3] + [1, 2]")).is_err());
+	assert_eq!(run("[1, 2] * [1]"), None);
+	assert_eq!(run("len([1, 2)"), None);
+	assert_eq!(run("1 + 2]"), None);
+
+	// clamp01 squeezes a value into the unit interval
+	assert_eq!(run("clamp01(1.5)"), Some(1.0));
+	assert_eq!(run("clamp01(-2)"), Some(0.0));
+	assert_eq!(run("clamp01(0.25)"), Some(0.25));
+}
+
+#[test]
+fn test_list_brackets_and_indexing() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	// A bracketed list on its own is a list literal item, one expression per element
+	let block = parse::parse_program("[1, 2 + 3, [4]]").unwrap();
+	match block.items.as_slice() {
+		[parse::LangBlockItem::List(list)] => {
+			let items: Vec<String> = list
+				.items
+				.iter()
+				.map(|item| parse::tokens_to_string(item))
+				.collect();
+			assert_eq!(items, ["1", "2 + 3", "[ 4 ]"]);
+		}
+		_ => panic!("expected a single list literal"),
+	}
+	assert_eq!(
+		parse::block_to_source(&block, 0).trim_end(),
+		"[1, 2 + 3, [ 4 ]]"
+	);
+	// A list used in an expression stays part of its line
+	for input in ["[1, 2][0]", "[1] + [2]", "xs = [1, 2]"] {
+		let block = parse::parse_program(input).unwrap();
+		assert!(
+			matches!(block.items.as_slice(), [parse::LangBlockItem::Line(_)]),
+			"{}",
+			input
+		);
+	}
+
+	// Unbalanced brackets are rejected by the parser
+	for input in ["a[", "[1, 2", "a]", "f([1, 2)", "(1]"] {
+		assert!(parse::parse_program(input).is_err(), "{}", input);
+	}
+	assert!(parse::parse_program("[[1], [2, (3)]]").is_ok());
+
+	// A bracket after an operand indexes it, counting from zero
+	assert_eq!(run("[10, 20, 30][1]"), Some(20.0));
+	assert_eq!(run("[4, 5, 6][0] + [4, 5, 6][2]"), Some(10.0));
+	assert_eq!(run("[4, 5, 6][1 + 1] * 2"), Some(12.0));
+	assert_eq!(run("-[4, 5, 6][1]"), Some(-5.0));
+	assert_eq!(run("[[1, 2], [3, 4]][1][0]"), Some(3.0));
+	assert_eq!(run("len([[1, 2], [3]][0])"), Some(2.0));
+	assert_eq!(run("[4, 5, 6][3]"), None);
+	assert_eq!(run("[4, 5, 6][-1]"), None);
+	assert_eq!(run("[4, 5, 6][0.5]"), None);
+	assert_eq!(run("x = 2\nx[0]"), None);
+
+	// Variables can hold lists, and index them
+	run("xs = [10, 20, 30]");
+	assert_eq!(run("xs[2] - xs[0]"), Some(20.0));
+	run("i = 1");
+	assert_eq!(run("xs[i]"), Some(20.0));
+	assert_eq!(run("len(xs)"), Some(3.0));
+	assert_eq!(get_variable("xs"), None);
+	// Assigning a number replaces the list, and the other way round
+	run("xs = 5");
+	assert_eq!(run("xs[0]"), None);
+	assert_eq!(get_variable("xs"), Some(5.0));
+	run("xs = [1]");
+	assert_eq!(get_variable("xs"), None);
+	assert_eq!(run("xs[0]"), Some(1.0));
+	assert_eq!(
+		evaluate_value(&lex("[4, 5, 6][3]"))
+			.unwrap_err()
+			.to_string(),
+		"List index 3 is out of range for 3 items"
+	);
+}
+
+#[test]
+fn test_bench_times_expression() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	run("fn square(x) { x * x }");
+	let nanoseconds = run("bench(\"square(2)\", 100)").unwrap();
+	assert!(nanoseconds > 0.0 && nanoseconds < 1e9, "{}", nanoseconds);
+	assert!(run("bench(\"1 + 2\", 1000)").unwrap() > 0.0);
+
+	// The expression really is evaluated each time
+	run("count = 0");
+	run("bench(\"count = count + 1\", 25)");
+	assert_eq!(get_variable("count"), Some(25.0));
+
+	// Errors in the expression and bad counts are reported
+	assert_eq!(run("bench(\"1 +\", 10)"), None);
+	assert_eq!(run("bench(\"1 + 2\", 0)"), None);
+	assert_eq!(run("bench(\"1 + 2\", 2.5)"), None);
+	assert_eq!(run("bench(3, 10)"), None);
+}
+
+#[test]
+fn test_map_filter_reduce() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	let value = |input: &str| evaluate_value(&lex(input)).unwrap().unwrap();
+	let list = |items: &[f64]| Value::List(items.iter().map(|&n| Value::Number(n)).collect());
+
+	run("fn square(x) { x * x }");
+	run("fn add(a, b) { a + b }");
+	run("fn is_even(x) { x % 2 == 0 }");
+
+	assert_eq!(value("map(square, [1, 2, 3])"), list(&[1.0, 4.0, 9.0]));
+	assert_eq!(run("reduce(add, 0, [1, 2, 3, 4])"), Some(10.0));
+	assert_eq!(
+		value("filter(is_even, [1, 2, 3, 4])"),
+		Value::List(vec![Value::Integer(2), Value::Integer(4)])
+	);
+
+	// Built-ins work as the function, empty lists are fine, and calls compose
+	assert_eq!(value("map(sqrt, [4, 9])"), list(&[2.0, 3.0]));
+	assert_eq!(value("map(square, [])"), list(&[]));
+	assert_eq!(run("reduce(add, 5, [])"), Some(5.0));
+	assert_eq!(run("reduce(max, 0, map(square, [3, -5, 2]))"), Some(25.0));
+	assert_eq!(value("map(square, [1, 2]) + [1, 1]"), list(&[2.0, 5.0]));
+	assert_eq!(run("len(filter(is_even, [1, 3, 5]))"), Some(0.0));
+
+	// The function must exist and fit, and the list argument must be a list
+	assert_eq!(run("map(nope, [1])"), None);
+	assert_eq!(run("map(square, 3)"), None);
+	assert_eq!(run("reduce(square, 0, [1, 2])"), None);
+	assert_eq!(run("filter(is_even)"), None);
+
+	// List results are printed at the top level rather than reported as errors
+	for statement in [
+		"map(square, [1, 2])",
+		"filter(is_even, [2])",
+		"[1, 2] + [3, 4]",
+	] {
+		ERROR_REPORTED.store(false, AtomicOrdering::Relaxed);
+		run(statement);
+		assert!(
+			!ERROR_REPORTED.load(AtomicOrdering::Relaxed),
+			"{}",
+			statement
+		);
+	}
+}
+
+#[test]
+fn test_history_is_trimmed_to_limit() {
+	use rustyline::history::History;
+
+	let mut history = rustyline::history::DefaultHistory::with_config(editor_config().unwrap());
+	for i in 1..=HISTORY_LIMIT {
+		history.add(&format!("line {}", i)).unwrap();
+	}
+	// Nothing is dropped while under the limit
+	assert_eq!(history.len(), HISTORY_LIMIT);
+	assert_eq!(history.iter().next().unwrap(), "line 1");
+
+	// The oldest entries go first
+	history.add("one more").unwrap();
+	history.add("and another").unwrap();
+	assert_eq!(history.len(), HISTORY_LIMIT);
+	assert_eq!(history.iter().next().unwrap(), "line 3");
+	assert_eq!(history.iter().last().unwrap(), "and another");
+
+	// The saved file keeps only the newest entries, and so does loading one
+	let path = std::env::temp_dir().join(format!(
+		"test_history_is_trimmed_to_limit_{}.txt",
+		std::process::id()
+	));
+	let mut editor = ReplEditor::with_config(editor_config().unwrap()).unwrap();
+	for i in 1..=HISTORY_LIMIT + 5 {
+		editor.add_history_entry(format!("entry {}", i)).unwrap();
+	}
+	editor.save_history(&path).unwrap();
+	let saved = fs::read_to_string(&path).unwrap();
+	let entries: Vec<&str> = saved.lines().filter(|line| *line != "#V2").collect();
+	assert_eq!(entries.len(), HISTORY_LIMIT);
+	assert_eq!(entries[0], "entry 6");
+	assert_eq!(
+		entries[HISTORY_LIMIT - 1],
+		format!("entry {}", HISTORY_LIMIT + 5)
+	);
+
+	let mut loaded = rustyline::history::DefaultHistory::with_config(editor_config().unwrap());
+	let mut oversized = saved.clone();
+	for i in 1..=3 {
+		oversized.push_str(&format!("extra {}\n", i));
+	}
+	fs::write(&path, oversized).unwrap();
+	loaded.load(&path).unwrap();
+	assert_eq!(loaded.len(), HISTORY_LIMIT);
+	assert_eq!(loaded.iter().next().unwrap(), "entry 9");
+	assert_eq!(loaded.iter().last().unwrap(), "extra 3");
+	fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_word_operators() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	// Each word gives the same result as its symbol
+	assert_eq!(run("5 mod 3"), run("5 % 3"));
+	assert_eq!(run("1 and 0"), run("1 && 0"));
+	assert_eq!(run("2 and 3"), run("2 && 3"));
+	assert_eq!(run("0 or 0"), run("0 || 0"));
+	assert_eq!(run("0 or 4"), run("0 || 4"));
+	assert_eq!(run("3 > 2 and 1 < 2 or 0"), Some(1.0));
+
+	// div rounds down, and not negates truthiness
+	assert_eq!(run("7 div 2"), Some(3.0));
+	assert_eq!(run("-7 div 2"), Some(-4.0));
+	assert_eq!(run("7.5 div 2"), Some(3.0));
+	assert_eq!(run("7 div 0"), None);
+	assert_eq!(run("not 0"), Some(1.0));
+	assert_eq!(run("not 5"), Some(0.0));
+	assert_eq!(run("not 1 == 2"), Some(1.0));
+	assert_eq!(run("1 and not 0"), Some(1.0));
+	assert_eq!(run("not -1"), Some(0.0));
+
+	// Outside operator positions the words are still names
+	assert_eq!(run("mod = 4"), Some(4.0));
+	assert_eq!(run("mod mod 3"), Some(1.0));
+	assert_eq!(run("or = 2"), Some(2.0));
+	assert_eq!(run("or * 3"), Some(6.0));
+
+	// Compiled functions and conditions understand them too
+	run("fn f(a, b) { a div b + not a }");
+	assert_eq!(run("f(7, 2)"), Some(3.0));
+	assert_eq!(run("f(0, 5)"), Some(1.0));
+	assert_eq!(run("if 1 and not 0 { 10 } else { 20 }"), Some(10.0));
+}
+
+#[test]
+fn test_default_assignment() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+	let _settings = save_settings();
+
+	// An undefined variable takes the value
+	assert_eq!(run("x ?= 5"), Some(5.0));
+	assert_eq!(run("x"), Some(5.0));
+
+	// A variable that is already set keeps its value
+	run("x = 3");
+	assert_eq!(run("x ?= 5"), Some(3.0));
+	assert_eq!(run("x == 3"), Some(1.0));
+
+	// Zero counts as unset
+	run("z = 0");
+	assert_eq!(run("z ?= 7"), Some(7.0));
+
+	// The value is only evaluated when it's assigned
+	run("fn touch() { 1 / 0 }");
+	assert_eq!(run("x ?= touch()"), Some(3.0));
+	assert_eq!(run("w ?= touch()"), None);
+
+	// It works in parentheses and in strict mode
+	assert_eq!(run("(y ?= 2) * 10"), Some(20.0));
+	settings().strict_variables = true;
+	assert_eq!(run("s ?= 4"), Some(4.0));
+}
+
+#[test]
+fn test_if_expression_assignment() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	assert_eq!(run("c = 1; y = if (c) { 10 } else { 20 }"), Some(10.0));
+	assert_eq!(run("y == 10"), Some(1.0));
+	assert_eq!(run("c = 0; y = if (c) { 10 } else { 20 }"), Some(20.0));
+	assert_eq!(run("y"), Some(20.0));
+
+	// The branch's last value is assigned, and else-if chains work
+	assert_eq!(
+		run("z = if (c > 0) { 1 } else if (c == 0) {\n  t = 5\n  t * 2\n} else { 3 }"),
+		Some(10.0)
+	);
+	assert_eq!(run("z"), Some(10.0));
+
+	// Functions can use it too, falling back to the interpreter
+	run("fn sign(x) {\n  s = if (x < 0) { -1 } else { 1 }\n  s * 100\n}");
+	assert_eq!(run("sign(-4)"), Some(-100.0));
+	assert_eq!(run("sign(4)"), Some(100.0));
+
+	// The source shows the assignment
+	let block = parse_program("w = if (c) { 1 } else { 2 }").unwrap();
+	assert_eq!(
+		parse::block_to_source(&block, 0),
+		"w = if ( c ) {\n\t1\n} else {\n\t2\n}\n"
+	);
+
+	// Without an else there may be no value to assign
+	assert!(parse_program("y = if (c) { 10 }").is_err());
+}
+
+#[test]
+fn test_match_statement() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	let program = "match x { 1 => { 10 }; 2 => { 20 }; _ => { 99 } }";
+	set_variable("x", 1.0);
+	assert_eq!(run(program), Some(10.0));
+	set_variable("x", 2.0);
+	assert_eq!(run(program), Some(20.0));
+	set_variable("x", 7.0);
+	assert_eq!(run(program), Some(99.0));
+
+	// Arms may be on separate lines, and without a default nothing matches
+	assert_eq!(
+		run("match x - 5 {\n\t1 => { 100 }\n\t2.0 => {\n\t\tt = 4\n\t\tt * 50\n\t}\n}"),
+		Some(200.0)
+	);
+	assert_eq!(run("match x { 1 => { 10 } }"), None);
+
+	// Compiled functions branch on each case
+	run("fn name_of(d) { match d { 0 => { 100 }; -1 => { 200 }; _ => { 300 } } }");
+	assert_eq!(run("name_of(0)"), Some(100.0));
+	assert_eq!(run("name_of(-1)"), Some(200.0));
+	assert_eq!(run("name_of(5)"), Some(300.0));
+
+	// Without a default, a value no arm matches gives nothing, compiled or not
+	run("fn pick(d) { match d { 1 => { 10 } } }");
+	run("fn same(d) { d }");
+	run("fn pick_same(d) { match same(d) { 1 => { 10 } } }");
+	assert_eq!(run("pick(1)"), Some(10.0));
+	assert_eq!(run("pick_same(1)"), Some(10.0));
+	assert_eq!(run("pick(5)"), None);
+	assert_eq!(run("pick_same(5)"), None);
+	let function = FUNCTIONS.lock().unwrap().get("pick").cloned().unwrap();
+	let mut codegen = LLVMCodeGen::new(llvm_context()).unwrap();
+	let error = codegen.compile_function("pick", &function).unwrap_err();
+	assert!(error.to_string().contains("use runtime evaluation"));
+
+	// A variable named match still works
+	assert_eq!(run("match = 3; match * 2"), Some(6.0));
+
+	let block = parse_program("match x { 1 => { 10 }; _ => { 0 } }").unwrap();
+	assert_eq!(
+		parse::block_to_source(&block, 0),
+		"match x {\n\t1 => {\n\t\t10\n\t}\n\t_ => {\n\t\t0\n\t}\n}\n"
+	);
+	assert!(parse_program("match x { y => { 1 } }").is_err());
+	assert!(parse_program("match x { 1 { 1 } }").is_err());
+}
+
+#[test]
+fn test_fast_math_codegen() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+	let _settings = save_settings();
+
+	settings().fast_math = true;
+	run("fn scale(x) { x * 2 + 1 / x - -x }");
+	let function = FUNCTIONS.lock().unwrap().get("scale").cloned().unwrap();
+	let mut codegen = LLVMCodeGen::new(llvm_context()).unwrap();
+	assert!(codegen.fast_math);
+	codegen.compile_function("scale", &function).unwrap();
+	assert!(
+		codegen
+			.module
+			.print_to_string()
+			.to_string()
+			.contains("fmul reassoc")
+	);
+	unsafe {
+		let jit_fn: inkwell::execution_engine::JitFunction<unsafe extern "C" fn(f64) -> f64> =
+			codegen.execution_engine.get_function("scale").unwrap();
+		assert_eq!(jit_fn.call(4.0), 12.25);
+	}
+	assert_eq!(run("scale(2)"), Some(6.5));
+	// Division by zero is still caught
+	assert_eq!(run("fn inverse(x) { 1 / x }; inverse(0)"), None);
+}
+
+#[test]
+fn test_anonymous_function_names_are_deterministic() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	let anonymous_function = |source: &str| match &parse_program(source).unwrap().items[0] {
+		parse::LangBlockItem::Function(function) => function.clone(),
+		_ => panic!("expected an anonymous function"),
+	};
+	let first = anonymous_function("(x, y) => { x * y + 1 }");
+	let second = anonymous_function("(x,y) => {\n\tx*y + 1\n}");
+	let other = anonymous_function("(x, y) => { x * y + 2 }");
+	let name = anonymous_function_name(&first);
+	assert_eq!(name, anonymous_function_name(&second));
+	assert_ne!(name, anonymous_function_name(&other));
+	assert!(name.starts_with("func_2_"));
+
+	// Defining the same function twice stores it once, under that name
+	run("(x, y) => { x * y + 1 }");
+	run("(x, y) => { x * y + 1 }");
+	assert_eq!(function_status().len(), 1);
+	assert_eq!(run(&format!("{}(3, 4)", name)), Some(13.0));
+}
+
+#[test]
+fn test_parameter_type_annotations() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	run("fn scale(n: int, factor: real) { n * factor }");
+	let function = function_ast("scale").unwrap();
+	assert_eq!(function.parameters, ["n", "factor"]);
+	assert_eq!(
+		function.parameter_types,
+		[Some("int".to_string()), Some("real".to_string())]
+	);
+	assert_eq!(
+		function_source("scale").unwrap(),
+		"fn scale(n: int, factor: real) {\n\tn * factor\n}"
+	);
+
+	// Annotations are informational until type checking is turned on
+	assert_eq!(run("scale(2.5, 2)"), Some(5.0));
+	let settings_guard = save_settings();
+	settings().strict_types = true;
+	assert_eq!(run("scale(3, 2)"), Some(6.0));
+	assert_eq!(run("scale(3, 0.5)"), Some(1.5));
+	assert_eq!(run("scale(2.5, 2)"), None);
+	assert_eq!(run("scale(3, 2) + scale(1.5, 1)"), None);
+	// Whole numbers passed through variables and parameters are ints too
+	run("k = 3");
+	assert_eq!(run("scale(k, 2)"), Some(6.0));
+	run("fn g(k) { scale(k, 1) }");
+	assert_eq!(run("g(3)"), Some(3.0));
+	assert_eq!(run("g(3.5)"), None);
+	assert_eq!(run_function("scale", &[3.0, 2.0]).unwrap(), 6.0);
+	drop(settings_guard);
+
+	// Anonymous and assigned functions take annotations too, and unknown types are errors
+	run("half = (x: real) => { x / 2 }");
+	assert_eq!(
+		function_ast("half").unwrap().parameter_types,
+		[Some("real".to_string())]
+	);
+	assert!(parse_program("fn f(x: text) { x }").is_err());
+	assert!(parse_program("fn f(: int) { 1 }").is_err());
+}
+
+#[test]
+fn test_block_as_if_condition() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	run("fn compute() { 3 }");
+	assert_eq!(
+		run("if ({ a = compute(); a }) { 10 } else { 20 }"),
+		Some(10.0)
+	);
+	assert_eq!(
+		run("if ({ a = compute(); a - 3 }) { 10 } else { 20 }"),
+		Some(20.0)
+	);
+	assert_eq!(
+		run("if ({\n\tb = 2\n\tb * b == 4\n}) {\n\t1\n} else {\n\t2\n}"),
+		Some(1.0)
+	);
+
+	// Variables created in the condition stay in its scope
+	assert_eq!(get_variable("a"), None);
+	assert_eq!(get_variable("b"), None);
+	ERROR_REPORTED.store(false, AtomicOrdering::Relaxed);
+	assert_eq!(run("if ({ l = [1, 2]; 1 }) { 2 }"), Some(2.0));
+	assert_eq!(list_variable("l"), None);
+	assert!(!ERROR_REPORTED.load(AtomicOrdering::Relaxed));
+
+	// Assignments to existing variables are kept
+	run("c = 1");
+	assert_eq!(run("if ({ c = 5; c }) { c }"), Some(5.0));
+	assert_eq!(get_variable("c"), Some(5.0));
+
+	// Blocks can be operands anywhere in the condition
+	assert_eq!(run("if (({ 1 }) + ({ 2 })) { 9 }"), Some(9.0));
+	assert_eq!(run("if ({ 1 } - { 1 }) { 9 } else { 8 }"), Some(8.0));
+	assert!(!ERROR_REPORTED.load(AtomicOrdering::Relaxed));
+
+	// Functions fall back to the interpreter for them
+	run("fn positive(x) { if ({ y = x * 2; y > 0 }) { 1 } else { 0 } }");
+	assert_eq!(run("positive(4)"), Some(1.0));
+	assert_eq!(run("positive(-4)"), Some(0.0));
+}
+
+#[test]
+fn test_compiled_infinity_is_not_an_error() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	// Only NaN signals division by zero; infinite results come back as they are
+	run("fn huge(x) { x ^ 1000 }");
+	assert_eq!(run("huge(10)"), Some(f64::INFINITY));
+	assert_eq!(run("-huge(10)"), Some(f64::NEG_INFINITY));
+	assert_eq!(compile_expr_ir("10 ^ 1000").unwrap().0, f64::INFINITY);
+	assert!(jit_result(f64::NEG_INFINITY).is_ok());
+	assert!(jit_result(f64::NAN).is_err());
+
+	// An infinite global flows through compiled code, which still catches division by zero
+	set_variable("limit", f64::INFINITY);
+	run("fn capped(x) { x < limit ? x : limit }");
+	assert_eq!(run("capped(5)"), Some(5.0));
+	run("fn ratio(x) { limit / x }");
+	assert_eq!(run("ratio(2)"), Some(f64::INFINITY));
+	assert_eq!(run("ratio(0)"), None);
+}
+
+#[test]
+fn test_profile_counts_operations() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+	let _settings = save_settings();
+
+	settings().profile = true;
+	assert_eq!(run("2 + 3 * 4"), Some(14.0));
+	assert_eq!(
+		*profile_counts(),
+		ProfileCounts {
+			arithmetic: 2,
+			calls: 0,
+			compilations: 0,
+		}
+	);
+	assert_eq!(
+		format_profile(&profile_counts()),
+		"profile: 2 arithmetic, 0 calls, 0 compilations"
+	);
+
+	// Comparisons aren't arithmetic; a function is compiled when it's defined, not when called
+	assert_eq!(run("-2 < 3 - 6"), Some(0.0));
+	assert_eq!(profile_counts().arithmetic, 1);
+	run("fn twice(x) { x * 2 }");
+	assert!(profile_counts().compilations >= 1);
+	assert_eq!(run("twice(5) + abs(-1)"), Some(11.0));
+	let counts = *profile_counts();
+	assert_eq!((counts.arithmetic, counts.calls), (1, 2));
+	assert_eq!(counts.compilations, 0);
+
+	// Nothing is counted with profiling off
+	settings().profile = false;
+	*profile_counts() = ProfileCounts::ZERO;
+	run("1 + 1");
+	assert_eq!(*profile_counts(), ProfileCounts::ZERO);
+}
+
+#[test]
+fn test_token_limit() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	let settings_guard = save_settings();
+	settings().max_tokens = Some(5);
+	assert_eq!(run("1 + 2 + 3"), Some(6.0));
+	assert_eq!(run("1 + 2 + 3 + 4"), None);
+	// The limit applies to each line on its own
+	assert_eq!(run("x = 1 + 2\nx * 2"), Some(6.0));
+	// Compiling an expression is limited the same way
+	assert!(compile_expr_ir("1 + 2 + 3").is_ok());
+	assert!(compile_expr_ir("1 + 2 + 3 + 4").is_err());
+	let executable_path = std::env::temp_dir().join("test_token_limit");
+	assert!(
+		crate::create_executable_from_expression(
+			"1 + 2 + 3 + 4",
+			executable_path.to_str().unwrap()
+		)
+		.is_err()
+	);
+	drop(settings_guard);
+	assert_eq!(run("1 + 2 + 3 + 4"), Some(10.0));
+
+	assert_eq!(
+		parse::check_token_limit(&lex("1\n2 + 3 + 4"), 3)
+			.unwrap_err()
+			.to_string(),
+		"Line 2 has 5 tokens, more than the limit of 3"
+	);
+	assert!(parse::check_token_limit(&lex("1\n2 + 3"), 3).is_ok());
+}
+
+#[test]
+fn test_repl_command_registry() {
+	let help = help_text();
+	for command in REPL_COMMANDS {
+		assert!(
+			command.usage.starts_with(&format!(":{}", command.name)),
+			"usage of :{} should start with its name",
+			command.name
+		);
+		assert!(!command.description.is_empty());
+		assert!(
+			help.contains(command.usage),
+			":help is missing :{}",
+			command.name
+		);
+		assert!(std::ptr::eq(
+			find_repl_command(command.name).unwrap(),
+			command
+		));
+	}
+	assert_eq!(help.lines().count(), REPL_COMMANDS.len() + 1);
+	assert!(find_repl_command("nonexistent").is_none());
+
+	// Required arguments come from the usage
+	assert!(find_repl_command("precision").unwrap().requires_arguments());
+	assert!(find_repl_command("maxtokens").unwrap().requires_arguments());
+	assert!(!find_repl_command("linker").unwrap().requires_arguments());
+	assert!(!find_repl_command("stats").unwrap().requires_arguments());
+}
+
+#[test]
+fn test_run_command() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	// The function name comes first, then any numbers
+	assert_eq!(
+		parse_run_arguments("hyp 3 -4.5"),
+		Ok(("hyp", vec![3.0, -4.5]))
+	);
+	assert_eq!(parse_run_arguments("  now "), Ok(("now", vec![])));
+	assert!(parse_run_arguments("").is_err());
+	assert_eq!(
+		parse_run_arguments("hyp 3 x"),
+		Err("Invalid argument 'x': all arguments must be numbers".to_string())
+	);
+	assert!(find_repl_command("run").unwrap().requires_arguments());
+
+	// Compiled, interpreted and built-in functions all run
+	run("fn hyp(a, b) { sqrt(a * a + b * b) }");
+	run("fn twice_hyp(a, b) { 2 * hyp(a, b) }");
+	assert_eq!(run_function("hyp", &[3.0, 4.0]).unwrap(), 5.0);
+	assert_eq!(run_function("twice_hyp", &[3.0, 4.0]).unwrap(), 10.0);
+	assert_eq!(run_function("max", &[1.0, 7.0]).unwrap(), 7.0);
+
+	// Wrong argument counts and unknown functions are errors
+	assert!(run_function("hyp", &[3.0]).is_err());
+	assert!(run_function("missing", &[]).is_err());
+}
+
+#[test]
+fn test_call_graph() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	// Dependencies are recorded when a function is defined, even on functions not defined yet
+	run("fn a(x){b(x)}");
+	assert_eq!(
+		FUNCTION_DEPENDENCIES.lock().unwrap().get("a"),
+		Some(&vec!["b".to_string()])
+	);
+	run("fn b(x){x}");
+	let graph = call_graph();
+	assert_eq!(
+		graph,
+		[
+			("a".to_string(), vec!["b".to_string()]),
+			("b".to_string(), vec![])
+		]
+	);
+	assert_eq!(format_call_graph(&graph), "a -> b\nb");
+	assert!(recursion_cycles(&graph).is_empty());
+
+	// Mutual recursion is flagged; a function calling itself is not
+	run("fn is_even(n) { if (n == 0) { 1 } else { is_odd(n - 1) } }");
+	run("fn is_odd(n) { if (n == 0) { 0 } else { is_even(n - 1) } }");
+	run("fn fact(n) { if (n < 2) { 1 } else { n * fact(n - 1) } }");
+	run("fn check(n) when n > 0 { is_even(n) + max(a(n), 1) }");
+	let graph = call_graph();
+	assert_eq!(
+		recursion_cycles(&graph),
+		[vec!["is_even".to_string(), "is_odd".to_string()]]
+	);
+	assert_eq!(
+		format_call_graph(&graph),
+		"a -> b\nb\ncheck -> a, is_even\nfact -> fact\nis_even -> is_odd\n\
+		is_odd -> is_even\nMutual recursion: is_even, is_odd"
+	);
+}
+
+#[test]
+fn test_hoisted_function_definitions() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+	let _settings = save_settings();
+
+	// In order, the call comes before the definition and fails
+	assert_eq!(run("f(2); fn f(x) { x + 1 }"), None);
+	clear_functions();
+
+	settings().hoist_functions = true;
+	assert_eq!(run("f(2); fn f(x) { x + 1 }"), Some(3.0));
+	assert_eq!(
+		run("y = g(3) + h(1)\nfn g(x) { h(x) * 2 }\nfn h(x) { x + 10 }\ny"),
+		Some(37.0)
+	);
+
+	// Definitions keep their order, so a later definition still replaces an earlier one
+	let mut block = parse_program("a(1)\nfn a(x) { 1 }\nb = 2\nfn a(x) { 2 }").unwrap();
+	hoist_function_definitions(&mut block);
+	assert_eq!(
+		parse::block_to_source(&block, 0),
+		"fn a(x) {\n\t1\n}\nfn a(x) {\n\t2\n}\na(1)\nb = 2\n"
+	);
+}
+
+#[test]
+fn test_load_file_summary() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	let path =
+		std::env::temp_dir().join(format!("test_load_file_summary_{}.fc", std::process::id()));
+	fs::write(
+		&path,
+		"(x) => { x }\nfn add(a, b) { a + b }\nfn sub(a, b) { a - b }\n\
+		mul = (a, b) => { a * b }\npi2 = 6.28\ntau = pi2\nadd(1, 2)\n",
+	)
+	.unwrap();
+	let summary = load_file(path.to_str().unwrap()).unwrap();
+	assert_eq!(
+		summary,
+		"loaded 3 functions (add, sub, mul) and 2 variables (pi2, tau)"
+	);
+	assert_eq!(run("mul(tau, 2)"), Some(12.56));
+
+	// Loading again defines nothing new
+	let summary = load_file(path.to_str().unwrap()).unwrap();
+	assert_eq!(summary, "loaded 0 functions and 0 variables");
+
+	fs::write(&path, "tau = 6.283\n").unwrap();
+	let summary = load_file(path.to_str().unwrap()).unwrap();
+	assert_eq!(summary, "loaded 0 functions and 1 variable (tau)");
+
+	// Guarded clauses and list variables count too
+	fs::write(
+		&path,
+		"fn sign(x) when x < 0 { -1 }\nprimes = [2, 3, 5]\nfn sign(x) { 1 }\n",
+	)
+	.unwrap();
+	let summary = load_file(path.to_str().unwrap()).unwrap();
+	assert_eq!(summary, "loaded 1 function (sign) and 1 variable (primes)");
+	fs::write(&path, "fn half(x) when x > 0 { x / 2 }\n").unwrap();
+	let summary = load_file(path.to_str().unwrap()).unwrap();
+	assert_eq!(summary, "loaded 1 function (half) and 0 variables");
+	fs::remove_file(&path).unwrap();
+
+	assert!(load_file("/nonexistent/fcalc_test_load.fc").is_err());
+}
+
+#[test]
+fn test_unknown_symbols_in_function() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+	let _settings = save_settings();
+
+	let unknown = |source: &str| match parse::parse_program(source).unwrap().items.as_slice() {
+		[parse::LangBlockItem::NamedFunction(function)] => unknown_symbols(function),
+		_ => panic!("expected a single function definition"),
+	};
+
+	assert_eq!(unknown("fn f(x){ x + undefinedd }"), ["undefinedd"]);
+	assert_eq!(unknown("fn f(x) { y + x * z + y }"), ["y", "z"]);
+
+	// Parameters, locals, globals, functions, builtins and recursion are all known
+	run("scale = 3");
+	run("fn double(x) { x * 2 }");
+	assert!(unknown("fn f(x) { y = x * scale\ny + double(x) + sqrt(x) }").is_empty());
+	assert!(unknown("fn fact(n) { if (n < 2) { 1 } else { n * fact(n - 1) } }").is_empty());
+	assert!(unknown("fn g(x) when x > 0 { map((v) => { v + x }, [x]) }").is_empty());
+	assert_eq!(unknown("fn g(x) when x > limit { x }"), ["limit"]);
+
+	// Unknown names only warn: the function is still defined and works once they exist
+	settings().check_names = true;
+	run("fn h(x) { x + offset }");
+	assert!(function_exists("h"));
+	run("offset = 10");
+	assert_eq!(run("h(1)"), Some(11.0));
+}
+
+#[test]
+fn test_assignment_echoes() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	clear_variables();
+	clear_functions();
+
+	// Echoing is off by default, so assignments print nothing
+	assert!(!Settings::default().echo_assignments);
+	assert_eq!(run("x = 5"), Some(5.0));
+	assert!(assignment_echoes(&lex("x = 5")).is_empty());
+
+	// With it on, each assigned variable is confirmed with the value it now holds
+	let settings_guard = save_settings();
+	settings().echo_assignments = true;
+	assert_eq!(assignment_echoes(&lex("x = 5")), ["x = 5"]);
+	run("a, b = 1, 2.5");
+	assert_eq!(
+		assignment_echoes(&lex("a, b = 1, 2.5")),
+		["a = 1", "b = 2.5"]
+	);
+	run("p = q = 3");
+	assert_eq!(assignment_echoes(&lex("p = q = 3")), ["p = 3", "q = 3"]);
+	assert!(assignment_echoes(&lex("x + 1")).is_empty());
+
+	// Only printing changes, not the stored value
+	assert_eq!(run("y = 2 * 4"), Some(8.0));
+	assert_eq!(get_variable("y"), Some(8.0));
+	drop(settings_guard);
+}
+
+#[test]
+fn test_precedence_warnings() {
+	let _guard = TEST_MUTEX
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	let _settings = save_settings();
+	clear_variables();
+
+	let warnings = |input: &str| precedence_warnings(&lex(input));
+
+	assert_eq!(
+		warnings("a + b * c"),
+		["'*' is applied before '+'; add parentheses to make the order explicit"]
+	);
+	assert!(warnings("a + (b * c)").is_empty());
+	assert_eq!(warnings("a * b - c").len(), 1);
+	assert_eq!(warnings("-2 ^ 2").len(), 1);
+	assert!(warnings("-(2 ^ 2)").is_empty());
+	assert!(warnings("(-2) ^ 2").is_empty());
+
+	// Operators of one precedence, separate arguments and separate lines are fine
+	assert!(warnings("a + b - c").is_empty());
+	assert!(warnings("f(a + b, c * d)").is_empty());
+	assert!(warnings("x = a + b\ny = c * d").is_empty());
+	assert!(warnings("a + b < c * d").is_empty());
+	assert_eq!(warnings("fn f(x) { x * 2 + 1 }").len(), 1);
+
+	// The warning doesn't change the result
+	settings().precedence_lint = true;
+	assert_eq!(run("2 + 3 * 4"), Some(14.0));
+}
@@ -0,0 +1,323 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A value on the interpreter's evaluation stack
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+	/// Integer, from integer literals and arithmetic on integers
+	Integer(i64),
+	/// Exact fraction in lowest terms with a denominator above 1, from exact mode
+	Rational(i64, i64),
+	/// Real number
+	Number(f64),
+	String(String),
+	/// Ordered collection of values
+	List(Vec<Value>),
+}
+
+impl Value {
+	/// Get the numeric value, or an error if this is not a number
+	pub fn as_number(&self) -> Result<f64, String> {
+		match self {
+			Value::Integer(i) => Ok(*i as f64),
+			Value::Rational(n, d) => Ok(*n as f64 / *d as f64),
+			Value::Number(n) => Ok(*n),
+			Value::String(_) => Err("Strings not supported in arithmetic evaluation".to_string()),
+			Value::List(_) => Err("Lists not supported in arithmetic evaluation".to_string()),
+		}
+	}
+
+	/// Compare two values of the same kind: numbers numerically, strings lexicographically
+	pub fn compare(&self, other: &Value) -> Result<Option<Ordering>, String> {
+		match (self, other) {
+			(Value::Integer(a), Value::Integer(b)) => Ok(Some(a.cmp(b))),
+			(Value::Integer(_) | Value::Rational(..), Value::Integer(_) | Value::Rational(..)) => {
+				// Cross-multiplying by the positive denominators keeps the comparison exact
+				let (an, ad) = self.as_fraction().unwrap();
+				let (bn, bd) = other.as_fraction().unwrap();
+				Ok(Some((an * bd).cmp(&(bn * ad))))
+			}
+			(Value::String(a), Value::String(b)) => Ok(Some(a.cmp(b))),
+			(Value::List(_), _) | (_, Value::List(_)) => Err("Cannot compare lists".to_string()),
+			(Value::String(_), _) | (_, Value::String(_)) => {
+				Err("Cannot compare a string with a number".to_string())
+			}
+			_ => Ok(self.as_number()?.partial_cmp(&other.as_number()?)),
+		}
+	}
+
+	/// Apply `+`, chosen by the kinds of the operands: numbers add, strings concatenate and
+	/// lists add element by element. Mixing strings and numbers is an error.
+	pub fn add(a: &Value, b: &Value) -> Result<Value, String> {
+		if let Some(result) = Value::element_wise("+", a, b, |_, a, b| Value::add(a, b)) {
+			return result;
+		}
+		match (a, b) {
+			(Value::String(x), Value::String(y)) => Ok(Value::String(format!("{}{}", x, y))),
+			(Value::String(_), _) | (_, Value::String(_)) => Err(format!(
+				"Cannot add {} and {}",
+				a.kind_name(),
+				b.kind_name()
+			)),
+			_ => Value::arithmetic("+", a, b),
+		}
+	}
+
+	/// Apply an operator element by element when either operand is a list. Two lists pair up
+	/// their elements and must have the same length; a list and a single value apply the value
+	/// to every element. Returns None if neither operand is a list.
+	fn element_wise(
+		op: &str,
+		a: &Value,
+		b: &Value,
+		apply: fn(&str, &Value, &Value) -> Result<Value, String>,
+	) -> Option<Result<Value, String>> {
+		let items: Result<Vec<Value>, String> = match (a, b) {
+			(Value::List(x), Value::List(y)) if x.len() != y.len() => {
+				return Some(Err(format!(
+					"Cannot apply '{}' to lists of different lengths, {} and {}",
+					op,
+					x.len(),
+					y.len()
+				)));
+			}
+			(Value::List(x), Value::List(y)) => {
+				x.iter().zip(y).map(|(x, y)| apply(op, x, y)).collect()
+			}
+			(Value::List(x), y) => x.iter().map(|x| apply(op, x, y)).collect(),
+			(x, Value::List(y)) => y.iter().map(|y| apply(op, x, y)).collect(),
+			_ => return None,
+		};
+		Some(items.map(Value::List))
+	}
+
+	/// Name of the kind of value, for error messages
+	pub fn kind_name(&self) -> &'static str {
+		match self {
+			Value::Integer(_) | Value::Rational(..) | Value::Number(_) => "a number",
+			Value::String(_) => "a string",
+			Value::List(_) => "a list",
+		}
+	}
+
+	/// Apply an arithmetic operator (`+ - * / % ^ div`). Integers stay integers when the exact
+	/// result is one: `9 / 3` is 3 but `7 / 2` is 3.5. Any real operand, or an integer
+	/// result that overflows, makes the result real. Lists are worked on element by element.
+	pub fn arithmetic(op: &str, a: &Value, b: &Value) -> Result<Value, String> {
+		if let Some(result) = Value::element_wise(op, a, b, Value::arithmetic) {
+			return result;
+		}
+		if let (Value::Integer(x), Value::Integer(y)) = (a, b) {
+			let (x, y) = (*x, *y);
+			if matches!(op, "/" | "%" | "div") && y == 0 {
+				return Err("Division by zero".to_string());
+			}
+			let exact = match op {
+				"+" => x.checked_add(y),
+				"-" => x.checked_sub(y),
+				"*" => x.checked_mul(y),
+				"/" if x.checked_rem(y) == Some(0) => x.checked_div(y),
+				"%" => x.checked_rem(y),
+				// Integer division rounds toward negative infinity, like `floor(x / y)`
+				"div" => x.checked_div(y).map(|quotient| {
+					if x % y != 0 && (x < 0) != (y < 0) {
+						quotient - 1
+					} else {
+						quotient
+					}
+				}),
+				"^" => u32::try_from(y).ok().and_then(|y| x.checked_pow(y)),
+				_ => None,
+			};
+			if let Some(result) = exact {
+				return Ok(Value::Integer(result));
+			}
+		}
+
+		let x = a.as_number()?;
+		let y = b.as_number()?;
+		let result = match op {
+			"+" => x + y,
+			"-" => x - y,
+			"*" => x * y,
+			"/" | "%" | "div" if y == 0.0 => return Err("Division by zero".to_string()),
+			"/" => x / y,
+			"%" => x % y,
+			"div" => (x / y).floor(),
+			"^" => x.powf(y),
+			_ => return Err(format!("Unknown arithmetic operator: {}", op)),
+		};
+		Ok(Value::Number(result))
+	}
+
+	/// Report an integer `+ - * ^` whose result doesn't fit in i64 as an error. Normally such
+	/// results become real; this is for when silently losing precision isn't acceptable.
+	pub fn check_integer_overflow(op: &str, a: &Value, b: &Value) -> Result<(), String> {
+		if let (Value::Integer(x), Value::Integer(y)) = (a, b) {
+			let overflows = match op {
+				"+" => x.checked_add(*y).is_none(),
+				"-" => x.checked_sub(*y).is_none(),
+				"*" => x.checked_mul(*y).is_none(),
+				// Negative exponents give fractions, not large integers, and powers of -1, 0
+				// and 1 stay small however large the exponent is
+				"^" if matches!(x, -1..=1) => false,
+				"^" => {
+					*y >= 0
+						&& u32::try_from(*y)
+							.ok()
+							.and_then(|y| x.checked_pow(y))
+							.is_none()
+				}
+				_ => false,
+			};
+			if overflows {
+				return Err(format!("Integer overflow in {} {} {}", x, op, y));
+			}
+		}
+		Ok(())
+	}
+
+	/// Report negating i64::MIN, the one integer whose negation doesn't fit in i64, as an
+	/// error when overflow is checked
+	pub fn check_negation_overflow(value: &Value) -> Result<(), String> {
+		match value {
+			Value::Integer(x) if x.checked_neg().is_none() => {
+				Err(format!("Integer overflow in -({})", x))
+			}
+			_ => Ok(()),
+		}
+	}
+
+	/// Numerator and denominator of an integer or fraction
+	fn as_fraction(&self) -> Option<(i128, i128)> {
+		match self {
+			Value::Integer(i) => Some((*i as i128, 1)),
+			Value::Rational(n, d) => Some((*n as i128, *d as i128)),
+			_ => None,
+		}
+	}
+
+	/// Build the exact value `numerator / denominator` in lowest terms, or None if it doesn't
+	/// fit in i64 parts
+	fn fraction(numerator: i128, denominator: i128) -> Option<Value> {
+		let divisor = gcd(numerator, denominator);
+		let sign = if denominator < 0 { -1 } else { 1 };
+		let numerator = i64::try_from(sign * numerator / divisor).ok()?;
+		let denominator = i64::try_from(sign * denominator / divisor).ok()?;
+		Some(if denominator == 1 {
+			Value::Integer(numerator)
+		} else {
+			Value::Rational(numerator, denominator)
+		})
+	}
+
+	/// Apply an arithmetic operator in exact mode: integer and fraction operands give an exact
+	/// fraction, so `1 / 3` is 1/3 rather than 0.333... Real operands, fractional exponents
+	/// and results too large for exact parts fall back to `add` and `arithmetic`.
+	pub fn exact_arithmetic(op: &str, a: &Value, b: &Value) -> Result<Value, String> {
+		if let Some(result) = Value::element_wise(op, a, b, Value::exact_arithmetic) {
+			return result;
+		}
+		if let (Some((an, ad)), Some((bn, bd))) = (a.as_fraction(), b.as_fraction()) {
+			if (op == "/" || op == "%") && bn == 0 {
+				return Err("Division by zero".to_string());
+			}
+			let exact = match op {
+				"+" => Value::fraction(an * bd + bn * ad, ad * bd),
+				"-" => Value::fraction(an * bd - bn * ad, ad * bd),
+				"*" => Value::fraction(an * bn, ad * bd),
+				"/" => Value::fraction(an * bd, ad * bn),
+				// Remainder after truncated division, matching `%` on integers and reals
+				"%" => Value::fraction((an * bd) % (bn * ad), ad * bd),
+				"^" if bd == 1 => exact_power(an, ad, bn),
+				_ => None,
+			};
+			if let Some(result) = exact {
+				return Ok(result);
+			}
+		}
+
+		if op == "+" {
+			Value::add(a, b)
+		} else {
+			Value::arithmetic(op, a, b)
+		}
+	}
+
+	/// Pick an item out of a list by its zero-based position
+	pub fn index(&self, index: &Value) -> Result<Value, String> {
+		let Value::List(items) = self else {
+			return Err(format!(
+				"Only lists can be indexed, not {}",
+				self.kind_name()
+			));
+		};
+		let position = index.as_number()?;
+		if position.fract() != 0.0 {
+			return Err(format!("List index {} is not a whole number", position));
+		}
+		match items.get(position as usize) {
+			Some(item) if position >= 0.0 => Ok(item.clone()),
+			_ => Err(format!(
+				"List index {} is out of range for {} items",
+				position,
+				items.len()
+			)),
+		}
+	}
+
+	/// Negate a numeric value, keeping integers as integers where possible. Lists are negated
+	/// element by element.
+	pub fn negate(&self) -> Result<Value, String> {
+		match self {
+			Value::Integer(i) => Ok(i
+				.checked_neg()
+				.map_or(Value::Number(-(*i as f64)), Value::Integer)),
+			Value::Rational(n, d) => Ok(Value::fraction(-(*n as i128), *d as i128)
+				.unwrap_or(Value::Number(-(*n as f64) / *d as f64))),
+			Value::List(items) => Ok(Value::List(
+				items.iter().map(Value::negate).collect::<Result<_, _>>()?,
+			)),
+			_ => Ok(Value::Number(-self.as_number()?)),
+		}
+	}
+}
+
+impl fmt::Display for Value {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Value::Integer(i) => write!(f, "{}", i),
+			Value::Rational(n, d) => write!(f, "{}/{}", n, d),
+			Value::Number(n) => write!(f, "{}", crate::format_value(*n)),
+			Value::String(s) => write!(f, "\"{}\"", s),
+			Value::List(items) => {
+				let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+				write!(f, "[{}]", items.join(", "))
+			}
+		}
+	}
+}
+
+/// Greatest common divisor, always positive for a nonzero denominator
+fn gcd(a: i128, b: i128) -> i128 {
+	let (mut a, mut b) = (a.abs(), b.abs());
+	while b != 0 {
+		(a, b) = (b, a % b);
+	}
+	a.max(1)
+}
+
+/// `(numerator / denominator) ^ exponent` as an exact fraction, or None if it gets too large
+fn exact_power(numerator: i128, denominator: i128, exponent: i128) -> Option<Value> {
+	if numerator == 0 && exponent < 0 {
+		return None;
+	}
+	let power = u32::try_from(exponent.unsigned_abs()).ok()?;
+	let top = numerator.checked_pow(power)?;
+	let bottom = denominator.checked_pow(power)?;
+	if exponent < 0 {
+		Value::fraction(bottom, top)
+	} else {
+		Value::fraction(top, bottom)
+	}
+}
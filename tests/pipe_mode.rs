@@ -0,0 +1,77 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// Helper function to run the binary with the given program piped to stdin
+fn run_piped(program: &str) -> String {
+	run_piped_with_env(program, &[])
+}
+
+// Like run_piped, with extra environment variables set for the binary
+fn run_piped_with_env(program: &str, env: &[(&str, &str)]) -> String {
+	let mut child = Command::new(env!("CARGO_BIN_EXE_fcalc"))
+		.envs(env.iter().copied())
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.spawn()
+		.expect("Failed to start fcalc");
+
+	child
+		.stdin
+		.take()
+		.unwrap()
+		.write_all(program.as_bytes())
+		.expect("Failed to write program to stdin");
+
+	let output = child.wait_with_output().expect("Failed to wait for fcalc");
+	assert!(output.status.success());
+	String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+#[test]
+fn test_pipe_mode_evaluates_program() {
+	let stdout = run_piped("fn square(x) { x * x }\nsquare(5)\nx = 3\nx + 4\n");
+	assert_eq!(stdout, "25\n7\n");
+}
+
+#[test]
+fn test_pipe_mode_skips_repl_banner() {
+	let stdout = run_piped("2 + 3\n");
+	assert!(!stdout.contains("Fast Calculator"));
+	assert_eq!(stdout.trim(), "5");
+}
+
+#[test]
+fn test_pipe_mode_prints_strings() {
+	let stdout = run_piped("print(\"done\")\nprint(len(\"abc\") * 2)\n");
+	assert_eq!(stdout, "done\n6\n");
+}
+
+#[test]
+fn test_pipe_mode_recursive_fibonacci() {
+	let program = "fn fib(n) {\n\tif (n < 2) {\n\t\tn\n\t} else {\n\t\tfib(n-1) + fib(n-2)\n\t}\n}\n\
+		fib(10)\nfib(10) == 55\nfib(0)\nfib(1)\n";
+	let stdout = run_piped(program);
+	assert_eq!(stdout, "55\n1\n0\n1\n");
+}
+
+#[test]
+fn test_pipe_mode_prints_lists_and_strings() {
+	let program = "[1, 2, 3] + [4, 5, 6]\nfn square(x) { x * x }\nfn is_odd(x) { x % 2 }\n\
+		fn add(a, b) { a + b }\nmap(square, [1, 2, 3])\nfilter(is_odd, [1, 2, 3])\n\
+		reduce(add, 0, map(square, [1, 2, 3]))\nto_string(42)\nto_string(2.5) + \"!\"\n";
+	let stdout = run_piped(program);
+	assert_eq!(
+		stdout,
+		"[5, 7, 9]\n[1, 4, 9]\n[1, 3]\n14\n\"42\"\n\"2.5!\"\n"
+	);
+}
+
+#[test]
+fn test_pipe_mode_echoes_chained_assignments() {
+	let program = "a = b = 3\nc = 4\na + b\n";
+	let stdout = run_piped_with_env(program, &[("FCALC_ECHO", "on")]);
+	assert_eq!(stdout, "a = 3\nb = 3\nc = 4\n6\n");
+
+	// Without echo, assignments print nothing
+	assert_eq!(run_piped(program), "6\n");
+}